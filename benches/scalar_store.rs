@@ -0,0 +1,84 @@
+use blstrs::Scalar as Fr;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use lurk::{
+    field::LurkField,
+    scalar_store::ScalarStore,
+    store::{Ptr, Store},
+};
+
+fn long_list<F: LurkField>(store: &mut Store<F>, len: u64) -> Ptr<F> {
+    let elts: Vec<_> = (0..len).map(|i| store.num(i)).collect();
+    let list = store.list(&elts);
+    store.hydrate_scalar_cache();
+    list
+}
+
+/// A balanced binary tree of conses with `len` distinct leaves, whose frontiers grow with depth, unlike those of a list.
+fn wide_tree<F: LurkField>(store: &mut Store<F>, len: u64) -> Ptr<F> {
+    let mut level: Vec<_> = (0..len).map(|i| store.num(i)).collect();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [car, cdr] => store.cons(*car, *cdr),
+                [leaf] => *leaf,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    store.hydrate_scalar_cache();
+    level[0]
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    // Hydrating the list hashes it recursively on rayon's threads, which needs more than their default stack.
+    rayon::ThreadPoolBuilder::new()
+        .stack_size(256 << 20)
+        .build_global()
+        .unwrap();
+
+    // `Store` hashes each cons of a list by walking the rest of it, so hydrating and finalizing a list takes time
+    // quadratic in its length, which keeps the list shorter than the tree.
+    let list_len = 10_000;
+    let tree_len = 100_000;
+    let mut store = Store::<Fr>::default();
+    let list = long_list(&mut store, list_len);
+    let tree = wide_tree(&mut store, tree_len);
+
+    let mut group = c.benchmark_group("scalar_store_finalize");
+    group.sample_size(10);
+
+    for (name, len, expr) in [("list", list_len, list), ("tree", tree_len, tree)] {
+        group.bench_with_input(
+            BenchmarkId::new(format!("serial_{}", name), len),
+            &expr,
+            |b, expr| {
+                b.iter(|| {
+                    let mut scalar_store = ScalarStore::default();
+                    scalar_store.add_ptr(&store, expr);
+                    scalar_store.finalize(&store);
+                    black_box(scalar_store)
+                })
+            },
+        );
+
+        #[cfg(feature = "parallel")]
+        group.bench_with_input(
+            BenchmarkId::new(format!("parallel_{}", name), len),
+            &expr,
+            |b, expr| {
+                b.iter(|| {
+                    let mut scalar_store = ScalarStore::default();
+                    scalar_store.add_ptr(&store, expr);
+                    scalar_store.finalize_parallel(&store);
+                    black_box(scalar_store)
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
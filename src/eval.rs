@@ -1575,8 +1575,18 @@ where
 
     // Wraps frames in Result type in order to fail gracefully
     pub fn get_frames(&mut self) -> Result<Vec<Frame<IO<F>, Witness<F>>>, LurkError> {
-        let frame = FrameIt::new(self.initial(), self.store)?;
-        let result_frame = ResultFrame(Ok(frame)).into_iter().take(self.limit);
+        let initial = self.initial();
+        Self::get_frames_from(initial, self.store, self.limit)
+    }
+
+    /// Like `get_frames`, but starting from `initial`, which need not be the start of an evaluation.
+    pub fn get_frames_from(
+        initial: IO<F>,
+        store: &mut Store<F>,
+        limit: usize,
+    ) -> Result<Vec<Frame<IO<F>, Witness<F>>>, LurkError> {
+        let frame = FrameIt::new(initial, store)?;
+        let result_frame = ResultFrame(Ok(frame)).into_iter().take(limit);
         let ret: Result<Vec<_>, _> = result_frame.collect();
         ret
     }
@@ -1745,7 +1755,7 @@ mod test {
         expected_iterations: usize,
     ) {
         let limit = 100000;
-        let env = empty_sym_env(&s);
+        let env = empty_sym_env(s);
         let (
             IO {
                 expr: new_expr,
@@ -1758,8 +1768,8 @@ mod test {
 
         if let Some(expected_result) = expected_result {
             dbg!(
-                &expected_result.fmt_to_string(&s),
-                &new_expr.fmt_to_string(&s),
+                &expected_result.fmt_to_string(s),
+                &new_expr.fmt_to_string(s),
             );
             assert!(s.ptr_eq(&expected_result, &new_expr).unwrap());
         }
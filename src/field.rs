@@ -1,5 +1,5 @@
+use cid::Cid;
 use ff::{PrimeField, PrimeFieldBits};
-use libipld::cid::Cid;
 use serde::{Deserialize, Serialize};
 use std::hash::Hash;
 
@@ -167,6 +167,43 @@ impl LurkField for pasta_curves::Fp {
     }
 }
 
+/// The fixed-width big-endian bytes of the integer which `f` represents, computed from its bits so that they do not
+/// depend on the byte order of `F::Repr`. Comparing these bytes compares field elements as integers, identically for
+/// every field and platform.
+pub fn canonical_bytes<F: LurkField>(f: &F) -> Vec<u8> {
+    let num_bits = F::NUM_BITS as usize;
+    let mut bytes = vec![0u8; (num_bits + 7) / 8];
+    let len = bytes.len();
+    for (i, bit) in f.to_le_bits().iter().take(num_bits).enumerate() {
+        if *bit {
+            bytes[len - 1 - i / 8] |= 1 << (i % 8);
+        }
+    }
+    bytes
+}
+
+/// Render `f` as the fixed-width hex of its `to_repr` bytes.
+pub(crate) fn f_to_hex<F: LurkField>(f: &F) -> String {
+    f.to_repr()
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Parse the output of `f_to_hex`.
+pub(crate) fn f_from_hex<F: LurkField>(s: &str) -> Option<F> {
+    let repr_len = F::zero().to_repr().as_ref().len();
+    if s.len() != 2 * repr_len || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let bytes = (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    F::from_bytes(&bytes)
+}
+
 // For working around the orphan trait impl rule
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct FWrap<F: LurkField>(pub F);
@@ -249,6 +286,29 @@ mod test {
         let f2 = <Fr as LurkField>::from_multihash(hash);
         Some(f1.0) == f2
     }
+    #[test]
+    fn test_canonical_bytes() {
+        let mut expected = vec![0u8; 32];
+        expected[30] = 1;
+        expected[31] = 2;
+        assert_eq!(expected, canonical_bytes(&Fr::from(0x0102)));
+        assert_eq!(expected, canonical_bytes(&pasta_curves::Fp::from(0x0102)));
+        assert_eq!(vec![0u8; 32], canonical_bytes(&Fr::from(0)));
+    }
+
+    #[quickcheck]
+    fn test_canonical_bytes_order(x: u64, y: u64) -> bool {
+        canonical_bytes(&Fr::from(x)).cmp(&canonical_bytes(&Fr::from(y))) == x.cmp(&y)
+    }
+
+    #[quickcheck]
+    fn test_canonical_bytes_repr(f: FWrap<Fr>) -> bool {
+        // The repr of `Fr` is little-endian, so reversing it gives the same bytes.
+        let mut repr = f.0.to_repr().as_ref().to_vec();
+        repr.reverse();
+        repr == canonical_bytes(&f.0)
+    }
+
     #[quickcheck]
     fn test_cid_consistency(args: (Tag, FWrap<Fr>)) -> bool {
         let (tag1, dig1) = args;
@@ -285,6 +285,7 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "ipld")]
     #[quickcheck]
     fn prop_num_ipld(x: Num<Fr>) -> bool {
         if let Ok(ipld) = libipld::serde::to_ipld(x) {
@@ -341,6 +341,7 @@ mod tests {
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn outer_prove_aux0(
         s: &mut Store<Fr>,
         expr: Ptr<Fr>,
@@ -359,14 +360,14 @@ mod tests {
 
         let pvk = groth16::prepare_verifying_key(&groth_params.vk);
 
-        let e = empty_sym_env(&s);
+        let e = empty_sym_env(s);
 
         if check_constraint_systems {
             let padding_predicate = |count| groth_prover.needs_frame_padding(count);
             let frames = Evaluator::generate_frames(expr, e, s, limit, padding_predicate).unwrap();
             s.hydrate_scalar_cache();
 
-            let multi_frames = MultiFrame::from_frames(DEFAULT_CHUNK_FRAME_COUNT, &frames, &s);
+            let multi_frames = MultiFrame::from_frames(DEFAULT_CHUNK_FRAME_COUNT, &frames, s);
 
             let cs = groth_prover.outer_synthesize(&multi_frames).unwrap();
 
@@ -397,7 +398,7 @@ mod tests {
                         groth_params,
                         &INNER_PRODUCT_SRS,
                         expr,
-                        empty_sym_env(&s),
+                        empty_sym_env(s),
                         s,
                         limit,
                         rng,
@@ -415,8 +416,8 @@ mod tests {
                     &srs_vk,
                     &pvk,
                     rng,
-                    &public_inputs.to_inputs(&s),
-                    &public_outputs.to_inputs(&s),
+                    &public_inputs.to_inputs(s),
+                    &public_outputs.to_inputs(s),
                     &proof.proof,
                     TRANSCRIPT_INCLUDE,
                     AggregateVersion::V2,
@@ -318,6 +318,7 @@ mod tests {
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn nova_test_full_aux(
         s: &mut Store<Fr>,
         expr: &str,
@@ -332,14 +333,14 @@ mod tests {
         let limit = 100000;
         let expr = s.read(expr).unwrap();
 
-        let e = empty_sym_env(&s);
+        let e = empty_sym_env(s);
 
         let nova_prover = NovaProver::<Fr>::new(chunk_frame_count);
 
         if check_nova {
             let pp = public_params(chunk_frame_count);
             let (proof, z0, zi, num_steps) = nova_prover
-                .evaluate_and_prove(&pp, expr, empty_sym_env(&s), s, limit)
+                .evaluate_and_prove(&pp, expr, empty_sym_env(s), s, limit)
                 .unwrap();
 
             let res = proof.verify(&pp, num_steps, z0.clone(), &zi);
@@ -358,7 +359,7 @@ mod tests {
             .get_evaluation_frames(expr, e, s, limit)
             .unwrap();
 
-        let multiframes = MultiFrame::from_frames(nova_prover.chunk_frame_count(), &frames, &s);
+        let multiframes = MultiFrame::from_frames(nova_prover.chunk_frame_count(), &frames, s);
 
         let len = multiframes.len();
 
@@ -377,7 +378,7 @@ mod tests {
             multiframe.clone().synthesize(&mut cs).unwrap();
 
             if let Some(prev) = previous_frame {
-                assert!(prev.precedes(&multiframe));
+                assert!(prev.precedes(multiframe));
             }
             assert!(cs.is_satisfied());
             assert!(cs.verify(&multiframe.public_inputs()));
@@ -393,7 +394,7 @@ mod tests {
         if let Some(expected_emitted) = expected_emitted {
             let emitted_vec: Vec<_> = frames
                 .iter()
-                .flat_map(|frame| frame.output.maybe_emitted_expression(&s))
+                .flat_map(|frame| frame.output.maybe_emitted_expression(s))
                 .collect();
             assert_eq!(expected_emitted, emitted_vec);
         }
@@ -1,39 +1,317 @@
-use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
-use crate::field::LurkField;
+use crate::error::{LurkError, ParserError};
+use crate::eval::{Evaluator, IO};
+#[cfg(feature = "canonical")]
+use crate::field::canonical_bytes;
+use crate::field::{f_from_hex, f_to_hex, LurkField};
 
-use crate::store::{Op1, Op2, Pointer, Ptr, ScalarContPtr, ScalarPtr, Store, Tag};
+use crate::store::{
+    ptr_from_hex, ptr_to_hex, ContPtr, ContTag, Continuation, HashConstants, Op1, Op2, Pointer,
+    Ptr, ScalarContPtr, ScalarPointer, ScalarPtr, Store, Tag,
+};
 use crate::{Num, UInt};
+use itertools::Itertools;
+use neptune::Poseidon;
 use serde::Deserialize;
 use serde::Serialize;
+use subtle::ConstantTimeEq;
+use thiserror::Error;
 
 /// `ScalarStore` allows realization of a graph of `ScalarPtr`s suitable for serialization to IPLD. `ScalarExpression`s
 /// are composed only of `ScalarPtr`s, so `scalar_map` suffices to allow traverseing an arbitrary DAG.
+/// `ScalarStore` is serialized as a `VersionedScalarStore`, so pending pointers are not serialized.
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "VersionedScalarStore<F>", try_from = "VersionedScalarStore<F>")]
 pub struct ScalarStore<F: LurkField> {
     scalar_map: BTreeMap<ScalarPtr<F>, Option<ScalarExpression<F>>>,
     scalar_cont_map: BTreeMap<ScalarContPtr<F>, Option<ScalarContinuation<F>>>,
-    #[serde(skip)]
     pending_scalar_ptrs: Vec<ScalarPtr<F>>,
+    pending_scalar_cont_ptrs: Vec<ScalarContPtr<F>>,
+}
+
+/// The version of the serialized schema of `ScalarStore`, to be bumped whenever `ScalarExpression` or
+/// `ScalarContinuation` change in a way which affects their serialization.
+pub const SCALAR_STORE_VERSION: u16 = 1;
+
+/// The serialized representation of a `ScalarStore`, tagged with the version of its schema so that data written by
+/// an incompatible version is rejected rather than misread.
+#[derive(Serialize, Deserialize)]
+struct VersionedScalarStore<F: LurkField> {
+    version: u16,
+    scalar_map: BTreeMap<ScalarPtr<F>, Option<ScalarExpression<F>>>,
+    scalar_cont_map: BTreeMap<ScalarContPtr<F>, Option<ScalarContinuation<F>>>,
+}
+
+/// Returned when deserializing a `ScalarStore` whose schema version is not `SCALAR_STORE_VERSION`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("VersionMismatch: expected ScalarStore version {expected}, found {found}")]
+pub struct VersionMismatch {
+    pub expected: u16,
+    pub found: u16,
+}
+
+impl<F: LurkField> From<ScalarStore<F>> for VersionedScalarStore<F> {
+    fn from(store: ScalarStore<F>) -> Self {
+        Self {
+            version: SCALAR_STORE_VERSION,
+            scalar_map: store.scalar_map,
+            scalar_cont_map: store.scalar_cont_map,
+        }
+    }
+}
+
+impl<F: LurkField> TryFrom<VersionedScalarStore<F>> for ScalarStore<F> {
+    type Error = VersionMismatch;
+
+    fn try_from(versioned: VersionedScalarStore<F>) -> Result<Self, Self::Error> {
+        if versioned.version != SCALAR_STORE_VERSION {
+            return Err(VersionMismatch {
+                expected: SCALAR_STORE_VERSION,
+                found: versioned.version,
+            });
+        }
+        Ok(Self {
+            scalar_map: versioned.scalar_map,
+            scalar_cont_map: versioned.scalar_cont_map,
+            ..Default::default()
+        })
+    }
 }
 
 impl<'a, F: LurkField> ScalarStore<F> {
     /// Create a new `ScalarStore` and add all `ScalarPtr`s reachable in the scalar representation of `expr`.
+    /// Panics if `store.scalar_cache` has not been hydrated; see `try_new_with_expr`.
     pub fn new_with_expr(store: &Store<F>, expr: &Ptr<F>) -> (Self, Option<ScalarPtr<F>>) {
+        Self::try_new_with_expr(store, expr).expect("failed to create ScalarStore")
+    }
+
+    /// Like `new_with_expr`, but return `ScalarStoreError::NotHydrated` if `store.scalar_cache` has not been hydrated.
+    pub fn try_new_with_expr(
+        store: &Store<F>,
+        expr: &Ptr<F>,
+    ) -> Result<(Self, Option<ScalarPtr<F>>), ScalarStoreError<F>> {
         let mut new = Self::default();
-        let scalar_ptr = new.add_one_ptr(store, expr);
-        if let Some(scalar_ptr) = scalar_ptr {
-            (new, Some(scalar_ptr))
-        } else {
-            (new, None)
+        let scalar_ptr = new.try_add_one_ptr(store, expr)?;
+        Ok((new, scalar_ptr))
+    }
+
+    /// Read `src` into a fresh `Store`, hydrate its scalar cache, and create a new `ScalarStore` with all `ScalarPtr`s
+    /// reachable from the expression read, returning it along with the expression's `ScalarPtr`.
+    pub fn from_source(src: &str) -> Result<(Self, ScalarPtr<F>), ParserError> {
+        let mut store = Store::<F>::default();
+        let expr = store.read(src)?;
+        store.hydrate_scalar_cache();
+        let (new, scalar_ptr) = Self::new_with_expr(&store, &expr);
+        Ok((
+            new,
+            scalar_ptr.expect("hydrated expression has a ScalarPtr"),
+        ))
+    }
+
+    /// Create a new `ScalarStore` and add all `ScalarPtr`s reachable from any of `exprs`, sharing common
+    /// subexpressions. Return it along with the `ScalarPtr` of each of `exprs`, in order. Panics if `store.scalar_cache`
+    /// has not been hydrated; see `try_new_with_exprs`.
+    pub fn new_with_exprs(store: &Store<F>, exprs: &[Ptr<F>]) -> (Self, Vec<Option<ScalarPtr<F>>>) {
+        Self::try_new_with_exprs(store, exprs).expect("failed to create ScalarStore")
+    }
+
+    /// Like `new_with_exprs`, but return `ScalarStoreError::NotHydrated` if `store.scalar_cache` has not been hydrated.
+    pub fn try_new_with_exprs(
+        store: &Store<F>,
+        exprs: &[Ptr<F>],
+    ) -> Result<(Self, Vec<Option<ScalarPtr<F>>>), ScalarStoreError<F>> {
+        let mut new = Self::default();
+        let scalar_ptrs = exprs
+            .iter()
+            .map(|expr| hashed(store, new.add_ptr(store, expr)))
+            .collect::<Result<_, _>>()?;
+        new.try_finalize(store)?;
+        Ok((new, scalar_ptrs))
+    }
+
+    /// Create a new `ScalarStore` and add all `ScalarPtr`s and `ScalarContPtr`s reachable from the expression,
+    /// environment and continuation of `io`, returning it along with their scalar pointers. This captures a paused
+    /// evaluation, not just an expression.
+    /// Panics if `store.scalar_cache` has not been hydrated, or if any part of `io` has no scalar hash in it; see
+    /// `try_new_with_io`.
+    pub fn new_with_io(
+        store: &Store<F>,
+        io: &IO<F>,
+    ) -> (Self, ScalarPtr<F>, ScalarPtr<F>, ScalarContPtr<F>) {
+        Self::try_new_with_io(store, io).expect("failed to create ScalarStore")
+    }
+
+    /// Like `new_with_io`, but return `ScalarStoreError::NotHydrated` if `store.scalar_cache` has not been hydrated,
+    /// and `ScalarStoreError::Unhashed` if any part of `io` has no scalar hash in it.
+    #[allow(clippy::type_complexity)]
+    pub fn try_new_with_io(
+        store: &Store<F>,
+        io: &IO<F>,
+    ) -> Result<(Self, ScalarPtr<F>, ScalarPtr<F>, ScalarContPtr<F>), ScalarStoreError<F>> {
+        let mut new = Self::default();
+        let expr = hashed(store, new.add_ptr(store, &io.expr))?
+            .ok_or(ScalarStoreError::Unhashed("expression"))?;
+        let env = hashed(store, new.add_ptr(store, &io.env))?
+            .ok_or(ScalarStoreError::Unhashed("environment"))?;
+        let cont = hashed(store, new.add_cont_ptr(store, &io.cont))?
+            .ok_or(ScalarStoreError::Unhashed("continuation"))?;
+        new.try_finalize(store)?;
+        Ok((new, expr, env, cont))
+    }
+
+    /// Evaluate from `initial` for at most `limit` steps, and snapshot the expression and continuation of each state
+    /// reached, each in its own `ScalarStore` in which common subexpressions are shared. The first snapshot is
+    /// `initial`, and each following one is the state after one more step, so `n` steps yield `n + 1` snapshots. This
+    /// interns into `store` and hydrates its scalar cache.
+    pub fn trace(
+        store: &mut Store<F>,
+        initial: IO<F>,
+        limit: usize,
+    ) -> Result<Vec<(Self, ScalarPtr<F>, ScalarContPtr<F>)>, LurkError> {
+        let frames = Evaluator::get_frames_from(initial, store, limit)?;
+        store.hydrate_scalar_cache();
+
+        let snapshot = |io: &IO<F>| {
+            let mut new = Self::default();
+            let expr = new.add_ptr(store, &io.expr).expect("failed to add expr");
+            let cont = new
+                .add_cont_ptr(store, &io.cont)
+                .expect("failed to add cont");
+            new.finalize(store);
+            (new, expr, cont)
+        };
+        let mut ios: Vec<IO<F>> = frames.iter().map(|frame| frame.input).collect();
+        // Unless evaluation completed, the state after the last step is not the input of any frame.
+        if let Some(last) = frames.last().filter(|frame| !frame.is_complete()) {
+            ios.push(last.output);
+        }
+        Ok(ios.iter().map(snapshot).collect())
+    }
+
+    /// Merge the snapshots of a `trace` into a single `ScalarStore`, so that the nodes they share are stored once, and
+    /// return it with the roots of each step, in order. If any entry conflicts, return a `MergeConflict` naming it.
+    pub fn compress_trace(
+        trace: &[(Self, ScalarPtr<F>, ScalarContPtr<F>)],
+    ) -> Result<(Self, Vec<RootRefs<F>>), MergeConflict<F>> {
+        let mut merged = Self::default();
+        let mut roots = Vec::with_capacity(trace.len());
+        for (snapshot, expr, cont) in trace {
+            merged.merge(snapshot)?;
+            roots.push(RootRefs {
+                expr: *expr,
+                cont: *cont,
+            });
         }
+        Ok((merged, roots))
+    }
+
+    /// Intern the proper list of `elements` in `store`, hydrate its scalar cache, and add the list and everything
+    /// reachable from it, returning the `ScalarPtr` of its head. An empty list is `nil`.
+    pub fn intern_list(&mut self, store: &mut Store<F>, elements: &[Ptr<F>]) -> ScalarPtr<F> {
+        let list = store.list(elements);
+        store.hydrate_scalar_cache();
+        self.add_one_ptr(store, &list)
+            .expect("hydrated list has a ScalarPtr")
     }
 
-    /// Add all ScalarPtrs representing and reachable from expr.
+    /// Add all ScalarPtrs representing and reachable from expr. Panics if `store.scalar_cache` has not been hydrated;
+    /// see `try_add_one_ptr`.
     pub fn add_one_ptr(&mut self, store: &Store<F>, expr: &Ptr<F>) -> Option<ScalarPtr<F>> {
-        let scalar_ptr = self.add_ptr(store, expr);
-        self.finalize(store);
-        scalar_ptr
+        self.try_add_one_ptr(store, expr)
+            .expect("failed to add ScalarPtr")
+    }
+
+    /// Like `add_one_ptr`, but return `ScalarStoreError::NotHydrated` if `store.scalar_cache` has not been hydrated.
+    pub fn try_add_one_ptr(
+        &mut self,
+        store: &Store<F>,
+        expr: &Ptr<F>,
+    ) -> Result<Option<ScalarPtr<F>>, ScalarStoreError<F>> {
+        let scalar_ptr = hashed(store, self.add_ptr(store, expr))?;
+        self.try_finalize(store)?;
+        Ok(scalar_ptr)
+    }
+
+    /// Like `add_one_ptr`, but also return every `ScalarPtr` inserted into the store by this call, in the order they
+    /// were inserted, so that a caller can track what changed. Pointers which were already present are not reported.
+    pub fn add_one_ptr_reporting(
+        &mut self,
+        store: &Store<F>,
+        expr: &Ptr<F>,
+    ) -> (Option<ScalarPtr<F>>, Vec<ScalarPtr<F>>) {
+        let mut inserted = Vec::new();
+        let scalar_ptr = store.get_expr_hash(expr);
+        if let Some(scalar_ptr) = scalar_ptr {
+            if self.add(store, expr, scalar_ptr) {
+                inserted.push(scalar_ptr);
+            }
+        }
+        self.finalize_aux(store, |scalar_ptr, new| {
+            if new {
+                inserted.push(scalar_ptr)
+            }
+        })
+        .expect("failed to finalize ScalarStore");
+        (scalar_ptr, inserted)
+    }
+
+    /// Count how much sharing adding `root` to this store would find, without modifying it. Every `ScalarPtr` reached
+    /// while traversing from `root` is a visit, and a visit to a pointer which was already present, whether in the
+    /// store or earlier in the traversal, is a dedup hit. For an empty store, this measures the sharing within `root`.
+    pub fn dedup_stats(&self, store: &Store<F>, root: &Ptr<F>) -> DedupStats {
+        // Traverse as `finalize` would, recording the pointers it would insert in local sets instead of the store.
+        let mut seen = BTreeSet::new();
+        let mut seen_conts = BTreeSet::new();
+        let mut pending = Vec::new();
+        let mut pending_conts = Vec::new();
+        let mut stats = DedupStats::default();
+        if let Some(scalar_ptr) = store.get_expr_hash(root) {
+            pending.push(scalar_ptr);
+        }
+        loop {
+            if let Some(scalar_ptr) = pending.pop() {
+                stats.visits += 1;
+                if self.scalar_map.contains_key(&scalar_ptr) || !seen.insert(scalar_ptr) {
+                    continue;
+                }
+                stats.unique += 1;
+                let expr = store
+                    .scalar_ptr_map
+                    .get(&scalar_ptr)
+                    .and_then(|ptr| ScalarExpression::from_ptr(store, &*ptr));
+                if let Some(expr) = expr {
+                    if let Some(children) = Self::child_scalar_ptrs(&expr) {
+                        pending.extend(children);
+                    }
+                    if let ScalarExpression::Thunk(thunk) = expr {
+                        pending_conts.push(thunk.continuation);
+                    }
+                }
+            } else if let Some(scalar_cont_ptr) = pending_conts.pop() {
+                if self.scalar_cont_map.contains_key(&scalar_cont_ptr)
+                    || !seen_conts.insert(scalar_cont_ptr)
+                {
+                    continue;
+                }
+                let cont = store
+                    .fetch_scalar_cont(&scalar_cont_ptr)
+                    .and_then(|cont_ptr| ScalarContinuation::from_ptr(store, &cont_ptr));
+                if let Some(cont) = cont {
+                    let (scalar_ptrs, scalar_cont_ptrs) = Self::child_scalar_cont_ptrs(&cont);
+                    pending.extend(scalar_ptrs);
+                    pending_conts.extend(scalar_cont_ptrs);
+                }
+            } else {
+                break;
+            }
+        }
+        stats
     }
 
     /// Add the `ScalarPtr` representing `expr`, and queue it for proceessing.
@@ -47,31 +325,180 @@ impl<'a, F: LurkField> ScalarStore<F> {
         }
     }
 
-    /// Add a single `ScalarPtr` and queue it for processing.
-    /// NOTE: This requires that `store.scalar_cache` has been hydrated.
-    fn add_scalar_ptr(&mut self, store: &Store<F>, scalar_ptr: ScalarPtr<F>) {
+    /// Add a single `ScalarPtr` and queue it for processing. If `store` does not know the expression `scalar_ptr`
+    /// represents, as when it was reached through an opaque pointer, it is added as opaque. Return whether it was newly
+    /// inserted.
+    /// NOTE: This requires that `store.scalar_cache` has been hydrated, which `try_finalize` checks.
+    fn add_scalar_ptr(&mut self, store: &Store<F>, scalar_ptr: ScalarPtr<F>) -> bool {
         // Find the ptr corresponding to scalar_ptr.
         if let Some(ptr) = store.scalar_ptr_map.get(&scalar_ptr) {
-            self.add(store, &*ptr, scalar_ptr);
+            self.add(store, &*ptr, scalar_ptr)
+        } else if let Entry::Vacant(entry) = self.scalar_map.entry(scalar_ptr) {
+            entry.insert(None);
+            true
+        } else {
+            false
         }
     }
 
     /// Add the `ScalarPtr` and `ScalarExpression` associated with `ptr`. The relationship between `ptr` and
     /// `scalar_ptr` is not checked here, so `add` should only be called by `add_ptr` and `add_scalar_ptr`, which
-    /// enforce this relationship.
-    fn add(&mut self, store: &Store<F>, ptr: &Ptr<F>, scalar_ptr: ScalarPtr<F>) {
+    /// enforce this relationship. Return whether `scalar_ptr` was newly inserted.
+    fn add(&mut self, store: &Store<F>, ptr: &Ptr<F>, scalar_ptr: ScalarPtr<F>) -> bool {
         let mut new_pending_scalar_ptrs: Vec<ScalarPtr<F>> = Default::default();
+        let mut new_pending_scalar_cont_ptrs: Vec<ScalarContPtr<F>> = Default::default();
+        let mut inserted = false;
 
         // If `scalar_ptr` is not already in the map, queue its children for processing.
         self.scalar_map.entry(scalar_ptr).or_insert_with(|| {
+            inserted = true;
             let scalar_expression = ScalarExpression::from_ptr(store, ptr)?;
             if let Some(more_scalar_ptrs) = Self::child_scalar_ptrs(&scalar_expression) {
                 new_pending_scalar_ptrs.extend(more_scalar_ptrs);
             }
+            if let ScalarExpression::Thunk(thunk) = &scalar_expression {
+                new_pending_scalar_cont_ptrs.push(thunk.continuation);
+            }
             Some(scalar_expression)
         });
 
         self.pending_scalar_ptrs.extend(new_pending_scalar_ptrs);
+        self.pending_scalar_cont_ptrs
+            .extend(new_pending_scalar_cont_ptrs);
+        inserted
+    }
+
+    /// Add all `ScalarPtr`s and `ScalarContPtr`s representing and reachable from `cont`. This is the continuation
+    /// counterpart of `add_one_ptr`. Panics if `store.scalar_cache` has not been hydrated; see `try_add_one_cont_ptr`.
+    pub fn add_one_cont_ptr(
+        &mut self,
+        store: &Store<F>,
+        cont: &ContPtr<F>,
+    ) -> Option<ScalarContPtr<F>> {
+        self.try_add_one_cont_ptr(store, cont)
+            .expect("failed to add ScalarContPtr")
+    }
+
+    /// Like `add_one_cont_ptr`, but return `ScalarStoreError::NotHydrated` if `store.scalar_cache` has not been
+    /// hydrated.
+    pub fn try_add_one_cont_ptr(
+        &mut self,
+        store: &Store<F>,
+        cont: &ContPtr<F>,
+    ) -> Result<Option<ScalarContPtr<F>>, ScalarStoreError<F>> {
+        let scalar_cont_ptr = hashed(store, self.add_cont_ptr(store, cont))?;
+        self.try_finalize(store)?;
+        Ok(scalar_cont_ptr)
+    }
+
+    /// Add the `ScalarContPtr` representing `cont`, and queue it for processing.
+    pub fn add_cont_ptr(
+        &mut self,
+        store: &Store<F>,
+        cont: &ContPtr<F>,
+    ) -> Option<ScalarContPtr<F>> {
+        // Find the scalar_cont_ptr representing cont.
+        if let Some(scalar_cont_ptr) = store.get_cont_hash(cont) {
+            self.add_cont(store, cont, scalar_cont_ptr);
+            Some(scalar_cont_ptr)
+        } else {
+            None
+        }
+    }
+
+    /// Add a single `ScalarContPtr` and queue it for processing.
+    /// NOTE: This requires that `store.scalar_cache` has been hydrated.
+    fn add_scalar_cont_ptr(&mut self, store: &Store<F>, scalar_cont_ptr: ScalarContPtr<F>) {
+        // Find the cont_ptr corresponding to scalar_cont_ptr.
+        if let Some(cont_ptr) = store.fetch_scalar_cont(&scalar_cont_ptr) {
+            self.add_cont(store, &cont_ptr, scalar_cont_ptr);
+        }
+    }
+
+    /// Add the `ScalarContPtr` and `ScalarContinuation` associated with `ptr`. As with `add`, the relationship between
+    /// `ptr` and `scalar_cont_ptr` is not checked here.
+    fn add_cont(&mut self, store: &Store<F>, ptr: &ContPtr<F>, scalar_cont_ptr: ScalarContPtr<F>) {
+        let mut new_pending_scalar_ptrs: Vec<ScalarPtr<F>> = Default::default();
+        let mut new_pending_scalar_cont_ptrs: Vec<ScalarContPtr<F>> = Default::default();
+
+        // If `scalar_cont_ptr` is not already in the map, queue its children for processing.
+        self.scalar_cont_map
+            .entry(scalar_cont_ptr)
+            .or_insert_with(|| {
+                let scalar_continuation = ScalarContinuation::from_ptr(store, ptr)?;
+                let (more_scalar_ptrs, more_scalar_cont_ptrs) =
+                    Self::child_scalar_cont_ptrs(&scalar_continuation);
+                new_pending_scalar_ptrs.extend(more_scalar_ptrs);
+                new_pending_scalar_cont_ptrs.extend(more_scalar_cont_ptrs);
+                Some(scalar_continuation)
+            });
+
+        self.pending_scalar_ptrs.extend(new_pending_scalar_ptrs);
+        self.pending_scalar_cont_ptrs
+            .extend(new_pending_scalar_cont_ptrs);
+    }
+
+    /// All the `ScalarPtr`s and `ScalarContPtr`s directly reachable from `scalar_continuation`.
+    fn child_scalar_cont_ptrs(
+        scalar_continuation: &ScalarContinuation<F>,
+    ) -> (Vec<ScalarPtr<F>>, Vec<ScalarContPtr<F>>) {
+        match scalar_continuation {
+            ScalarContinuation::Outermost
+            | ScalarContinuation::Error
+            | ScalarContinuation::Dummy
+            | ScalarContinuation::Terminal => (vec![], vec![]),
+            ScalarContinuation::Call0 {
+                saved_env,
+                continuation,
+            } => (vec![*saved_env], vec![*continuation]),
+            ScalarContinuation::Call {
+                unevaled_arg,
+                saved_env,
+                continuation,
+            } => (vec![*unevaled_arg, *saved_env], vec![*continuation]),
+            ScalarContinuation::Call2 {
+                function,
+                saved_env,
+                continuation,
+            } => (vec![*function, *saved_env], vec![*continuation]),
+            ScalarContinuation::Tail {
+                saved_env,
+                continuation,
+            } => (vec![*saved_env], vec![*continuation]),
+            ScalarContinuation::Lookup {
+                saved_env,
+                continuation,
+            } => (vec![*saved_env], vec![*continuation]),
+            ScalarContinuation::Unop { continuation, .. } => (vec![], vec![*continuation]),
+            ScalarContinuation::Binop {
+                saved_env,
+                unevaled_args,
+                continuation,
+                ..
+            } => (vec![*saved_env, *unevaled_args], vec![*continuation]),
+            ScalarContinuation::Binop2 {
+                evaled_arg,
+                continuation,
+                ..
+            } => (vec![*evaled_arg], vec![*continuation]),
+            ScalarContinuation::If {
+                unevaled_args,
+                continuation,
+            } => (vec![*unevaled_args], vec![*continuation]),
+            ScalarContinuation::Let {
+                var,
+                body,
+                saved_env,
+                continuation,
+            }
+            | ScalarContinuation::LetRec {
+                var,
+                body,
+                saved_env,
+                continuation,
+            } => (vec![*var, *body, *saved_env], vec![*continuation]),
+            ScalarContinuation::Emit { continuation } => (vec![], vec![*continuation]),
+        }
     }
 
     /// All the `ScalarPtr`s directly reachable from `scalar_expression`, if any.
@@ -88,25 +515,151 @@ impl<'a, F: LurkField> ScalarStore<F> {
             } => Some([*arg, *body, *closed_env].into()),
             ScalarExpression::Num(_) => None,
             ScalarExpression::Str(_) => None,
-            ScalarExpression::Thunk(_) => None,
+            ScalarExpression::Thunk(thunk) => Some([thunk.value].into()),
             ScalarExpression::Char(_) => None,
             ScalarExpression::UInt(_) => None,
         }
     }
 
-    /// Unqueue all the pending `ScalarPtr`s and add them, queueing all of their children, then repeat until the queue
-    /// is pending queue is empty.
-    fn add_pending_scalar_ptrs(&mut self, store: &Store<F>) {
-        while let Some(scalar_ptr) = self.pending_scalar_ptrs.pop() {
-            self.add_scalar_ptr(store, scalar_ptr);
+    /// Unqueue at most `budget` pending `ScalarPtr`s and `ScalarContPtr`s and add them, queueing all of their
+    /// children, and return how many were unqueued. Fewer than `budget` are unqueued only if both pending queues become
+    /// empty, so traversal of a large graph can be paused and resumed by calling `step` until `is_finalized`.
+    pub fn step(&mut self, store: &Store<F>, budget: usize) -> usize {
+        self.step_aux(store, budget, |_, _| ())
+    }
+
+    /// Like `step`, but call `visit` with each `ScalarPtr` unqueued and whether it was newly inserted.
+    fn step_aux(
+        &mut self,
+        store: &Store<F>,
+        budget: usize,
+        mut visit: impl FnMut(ScalarPtr<F>, bool),
+    ) -> usize {
+        let mut consumed = 0;
+        while consumed < budget {
+            if let Some(scalar_ptr) = self.pending_scalar_ptrs.pop() {
+                let new = self.add_scalar_ptr(store, scalar_ptr);
+                visit(scalar_ptr, new);
+            } else if let Some(scalar_cont_ptr) = self.pending_scalar_cont_ptrs.pop() {
+                self.add_scalar_cont_ptr(store, scalar_cont_ptr);
+            } else {
+                break;
+            }
+            consumed += 1;
         }
-        assert!(self.pending_scalar_ptrs.is_empty());
+        consumed
+    }
+
+    /// True if no pointers are pending, so that all reachable `ScalarPtr`s and `ScalarContPtr`s have been added.
+    pub fn is_finalized(&self) -> bool {
+        self.pending_scalar_ptrs.is_empty() && self.pending_scalar_cont_ptrs.is_empty()
     }
 
     /// Method which finalizes the `ScalarStore`, ensuring that all reachable `ScalarPtr`s have been added.
+    /// Panics if `store.scalar_cache` has not been hydrated; see `try_finalize`.
     pub fn finalize(&mut self, store: &Store<F>) {
-        self.add_pending_scalar_ptrs(store);
+        self.try_finalize(store)
+            .expect("failed to finalize ScalarStore");
+    }
+
+    /// Like `finalize`, but return `ScalarStoreError::NotHydrated` instead of adding anything if `store` has
+    /// un-hydrated pointers. Children are found through `store.scalar_ptr_map`, so finalizing against such a store
+    /// would silently record reachable expressions as opaque.
+    pub fn try_finalize(&mut self, store: &Store<F>) -> Result<(), ScalarStoreError<F>> {
+        self.finalize_aux(store, |_, _| ())
+    }
+
+    /// Like `try_finalize`, but call `visit` as `step_aux` does.
+    fn finalize_aux(
+        &mut self,
+        store: &Store<F>,
+        visit: impl FnMut(ScalarPtr<F>, bool),
+    ) -> Result<(), ScalarStoreError<F>> {
+        if !self.is_finalized() && !store.is_hydrated() {
+            return Err(ScalarStoreError::NotHydrated);
+        }
+        self.step_aux(store, usize::MAX, visit);
+        assert!(self.is_finalized());
+        Ok(())
+    }
+
+    /// Like `finalize`, but processes each frontier of pending pointers in parallel. Looking up the expressions and
+    /// continuations pending pointers represent only reads `store`, so this is done with rayon, and the results are
+    /// then added serially, queueing their children as the next frontier. Only pointers in the same frontier are looked
+    /// up together, so this pays off on wide graphs such as balanced trees. On a long list, each frontier holds only a
+    /// car and a cdr, and this does the work of `finalize` plus the overhead of rayon.
+    /// Panics if `store.scalar_cache` has not been hydrated; see `try_finalize_parallel`.
+    #[cfg(feature = "parallel")]
+    pub fn finalize_parallel(&mut self, store: &Store<F>) {
+        self.try_finalize_parallel(store)
+            .expect("failed to finalize ScalarStore");
+    }
+
+    /// Like `finalize_parallel`, but return `ScalarStoreError::NotHydrated` instead of adding anything if `store` has
+    /// un-hydrated pointers, as `try_finalize` does.
+    #[cfg(feature = "parallel")]
+    pub fn try_finalize_parallel(&mut self, store: &Store<F>) -> Result<(), ScalarStoreError<F>> {
+        use rayon::prelude::*;
+        if !self.is_finalized() && !store.is_hydrated() {
+            return Err(ScalarStoreError::NotHydrated);
+        }
+
+        while !self.is_finalized() {
+            let pending: BTreeSet<ScalarPtr<F>> = std::mem::take(&mut self.pending_scalar_ptrs)
+                .into_iter()
+                .filter(|scalar_ptr| !self.scalar_map.contains_key(scalar_ptr))
+                .collect();
+            let pending_conts: BTreeSet<ScalarContPtr<F>> =
+                std::mem::take(&mut self.pending_scalar_cont_ptrs)
+                    .into_iter()
+                    .filter(|scalar_cont_ptr| !self.scalar_cont_map.contains_key(scalar_cont_ptr))
+                    .collect();
+
+            let exprs: Vec<(ScalarPtr<F>, Option<ScalarExpression<F>>)> = pending
+                .into_par_iter()
+                .map(|scalar_ptr| {
+                    let expr = store
+                        .scalar_ptr_map
+                        .get(&scalar_ptr)
+                        .and_then(|ptr| ScalarExpression::from_ptr(store, &*ptr));
+                    (scalar_ptr, expr)
+                })
+                .collect();
+            let conts: Vec<(ScalarContPtr<F>, Option<ScalarContinuation<F>>)> = pending_conts
+                .into_par_iter()
+                .filter_map(|scalar_cont_ptr| {
+                    // As in `add_scalar_cont_ptr`, a continuation unknown to `store` is not added.
+                    let cont_ptr = store.fetch_scalar_cont(&scalar_cont_ptr)?;
+                    Some((
+                        scalar_cont_ptr,
+                        ScalarContinuation::from_ptr(store, &cont_ptr),
+                    ))
+                })
+                .collect();
+
+            for (scalar_ptr, expr) in exprs {
+                if let Some(expr) = &expr {
+                    if let Some(children) = Self::child_scalar_ptrs(expr) {
+                        self.pending_scalar_ptrs.extend(children);
+                    }
+                    if let ScalarExpression::Thunk(thunk) = expr {
+                        self.pending_scalar_cont_ptrs.push(thunk.continuation);
+                    }
+                }
+                self.scalar_map.insert(scalar_ptr, expr);
+            }
+            for (scalar_cont_ptr, cont) in conts {
+                if let Some(cont) = &cont {
+                    let (scalar_ptrs, scalar_cont_ptrs) = Self::child_scalar_cont_ptrs(cont);
+                    self.pending_scalar_ptrs.extend(scalar_ptrs);
+                    self.pending_scalar_cont_ptrs.extend(scalar_cont_ptrs);
+                }
+                self.scalar_cont_map.insert(scalar_cont_ptr, cont);
+            }
+        }
+        Ok(())
     }
+
     pub fn get_expr(&self, ptr: &ScalarPtr<F>) -> Option<&ScalarExpression<F>> {
         let x = self.scalar_map.get(ptr)?;
         (*x).as_ref()
@@ -117,569 +670,8488 @@ impl<'a, F: LurkField> ScalarStore<F> {
         (*x).as_ref()
     }
 
-    pub fn to_store_with_expr(&mut self, ptr: &ScalarPtr<F>) -> Option<(Store<F>, Ptr<F>)> {
-        if self.pending_scalar_ptrs.is_empty() {
-            let mut store = Store::new();
+    /// Like `get_expr`, but return an owned copy, which does not borrow the store.
+    pub fn get_expr_owned(&self, ptr: &ScalarPtr<F>) -> Option<ScalarExpression<F>> {
+        self.get_expr(ptr).cloned()
+    }
+
+    /// Like `get_cont`, but return an owned copy, which does not borrow the store.
+    pub fn get_cont_owned(&self, ptr: &ScalarContPtr<F>) -> Option<ScalarContinuation<F>> {
+        self.get_cont(ptr).copied()
+    }
+
+    /// True if `ptr` is in the store, whether its expression is known or opaque.
+    pub fn contains_expr(&self, ptr: &ScalarPtr<F>) -> bool {
+        self.scalar_map.contains_key(ptr)
+    }
+
+    /// True if `ptr` is in the store, whether its continuation is known or opaque.
+    pub fn contains_cont(&self, ptr: &ScalarContPtr<F>) -> bool {
+        self.scalar_cont_map.contains_key(ptr)
+    }
+
+    /// Whether `ptr` is absent from the store, present but opaque, or present with a known expression. Unlike
+    /// `get_expr`, this distinguishes opaque pointers from dangling ones.
+    pub fn status(&self, ptr: &ScalarPtr<F>) -> PointerStatus<'_, F> {
+        match self.scalar_map.get(ptr) {
+            None => PointerStatus::Unknown,
+            Some(None) => PointerStatus::Opaque,
+            Some(Some(expr)) => PointerStatus::Known(expr),
+        }
+    }
+
+    /// Iterate over every `ScalarPtr` in the store, in order, along with its expression, which is `None` if opaque.
+    pub fn iter_exprs(
+        &self,
+    ) -> impl Iterator<Item = (&ScalarPtr<F>, Option<&ScalarExpression<F>>)> {
+        self.scalar_map
+            .iter()
+            .map(|(ptr, expr)| (ptr, expr.as_ref()))
+    }
+
+    /// Iterate over every `ScalarContPtr` in the store, in order, along with its continuation, which is `None` if
+    /// opaque.
+    pub fn iter_conts(
+        &self,
+    ) -> impl Iterator<Item = (&ScalarContPtr<F>, Option<&ScalarContinuation<F>>)> {
+        self.scalar_cont_map
+            .iter()
+            .map(|(ptr, cont)| (ptr, cont.as_ref()))
+    }
 
-            let ptr = store.intern_scalar_ptr(*ptr, self)?;
+    /// Insert all of `other`'s entries into `self`. Since pointers are content-addressed, an entry present in both
+    /// stores must have the same value in each, unless one of them is opaque, in which case the known value is kept.
+    /// If any entry conflicts, return a `MergeConflict` naming it and leave `self` unchanged.
+    pub fn merge(&mut self, other: &ScalarStore<F>) -> Result<(), MergeConflict<F>> {
+        self.check_conflicts(other)?;
 
-            for scalar_ptr in self.scalar_map.keys() {
-                store.intern_scalar_ptr(*scalar_ptr, self);
+        for (ptr, expr) in &other.scalar_map {
+            let entry = self.scalar_map.entry(*ptr).or_insert(None);
+            if entry.is_none() {
+                *entry = expr.clone();
             }
-            for ptr in self.scalar_cont_map.keys() {
-                store.intern_scalar_cont_ptr(*ptr, self);
+        }
+        for (ptr, cont) in &other.scalar_cont_map {
+            let entry = self.scalar_cont_map.entry(*ptr).or_insert(None);
+            if entry.is_none() {
+                *entry = *cont;
             }
-            Some((store, ptr))
-        } else {
-            None
         }
+        self.pending_scalar_ptrs.extend(&other.pending_scalar_ptrs);
+        self.pending_scalar_cont_ptrs
+            .extend(&other.pending_scalar_cont_ptrs);
+
+        Ok(())
+    }
+
+    /// Partition the entries of `self` into `n` shards, placing each in the shard given by the low bits of its
+    /// pointer's value, so that the same entry always lands in the same shard. Shards are generally incomplete, since
+    /// an entry's children may land elsewhere. Pending pointers all go to the first shard, so that `merge_all`
+    /// reproduces `self` exactly. Panics if `n` is zero.
+    pub fn shard(&self, n: usize) -> Vec<ScalarStore<F>> {
+        assert!(n > 0, "cannot shard into zero shards");
+        let index = |value: &F| (value.to_u64_unchecked() % n as u64) as usize;
+        let mut shards = vec![ScalarStore::default(); n];
+
+        for (ptr, expr) in &self.scalar_map {
+            shards[index(ptr.value())]
+                .scalar_map
+                .insert(*ptr, expr.clone());
+        }
+        for (ptr, cont) in &self.scalar_cont_map {
+            shards[index(ptr.value())]
+                .scalar_cont_map
+                .insert(*ptr, *cont);
+        }
+        shards[0].pending_scalar_ptrs = self.pending_scalar_ptrs.clone();
+        shards[0].pending_scalar_cont_ptrs = self.pending_scalar_cont_ptrs.clone();
+        shards
+    }
+
+    /// Merge `shards`, such as those produced by `shard`, into a single `ScalarStore`, in order. If any entry
+    /// conflicts, return a `MergeConflict` naming it.
+    pub fn merge_all(
+        shards: impl IntoIterator<Item = ScalarStore<F>>,
+    ) -> Result<ScalarStore<F>, MergeConflict<F>> {
+        let mut res = ScalarStore::default();
+        for shard in shards {
+            res.merge(&shard)?;
+        }
+        Ok(res)
     }
-    pub fn to_store(&mut self) -> Option<Store<F>> {
-        if self.pending_scalar_ptrs.is_empty() {
-            let mut store = Store::new();
 
-            for ptr in self.scalar_map.keys() {
-                store.intern_scalar_ptr(*ptr, self);
+    /// Expand the opaque entries of `self` which are known in `other`, adding everything reachable from them in `other`
+    /// which `self` lacks or holds only as opaque. Since pointers are content-addressed, an entry present in both
+    /// stores must have the same value in each, unless one of them is opaque. If any entry conflicts, return a
+    /// `MergeConflict` naming it and leave `self` unchanged.
+    pub fn hydrate_from(&mut self, other: &ScalarStore<F>) -> Result<(), MergeConflict<F>> {
+        self.check_conflicts(other)?;
+
+        let opaque_scalar_ptrs = self
+            .scalar_map
+            .iter()
+            .filter(|(ptr, expr)| {
+                expr.is_none() && matches!(other.scalar_map.get(ptr), Some(Some(_)))
+            })
+            .map(|(ptr, _)| *ptr)
+            .collect();
+        let opaque_scalar_cont_ptrs = self
+            .scalar_cont_map
+            .iter()
+            .filter(|(ptr, cont)| {
+                cont.is_none() && matches!(other.scalar_cont_map.get(ptr), Some(Some(_)))
+            })
+            .map(|(ptr, _)| *ptr)
+            .collect();
+        let (scalar_ptrs, scalar_cont_ptrs) =
+            other.reachable(opaque_scalar_ptrs, opaque_scalar_cont_ptrs);
+
+        for ptr in scalar_ptrs {
+            let entry = self.scalar_map.entry(ptr).or_insert(None);
+            if entry.is_none() {
+                *entry = other.scalar_map[&ptr].clone();
             }
-            for ptr in self.scalar_cont_map.keys() {
-                store.intern_scalar_cont_ptr(*ptr, self);
+        }
+        for ptr in scalar_cont_ptrs {
+            let entry = self.scalar_cont_map.entry(ptr).or_insert(None);
+            if entry.is_none() {
+                *entry = other.scalar_cont_map[&ptr];
             }
-            Some(store)
-        } else {
-            None
         }
+
+        Ok(())
     }
-}
 
-impl<'a, F: LurkField> ScalarExpression<F> {
-    fn from_ptr(store: &Store<F>, ptr: &Ptr<F>) -> Option<Self> {
-        match ptr.tag() {
-            Tag::Nil => Some(ScalarExpression::Nil),
-            Tag::Cons => store.fetch_cons(ptr).and_then(|(car, cdr)| {
-                store.get_expr_hash(car).and_then(|car| {
-                    store
-                        .get_expr_hash(cdr)
-                        .map(|cdr| ScalarExpression::Cons(car, cdr))
-                })
-            }),
-            Tag::Comm => store.fetch_comm(ptr).and_then(|(secret, payload)| {
-                store
-                    .get_expr_hash(payload)
-                    .map(|payload| ScalarExpression::Comm(secret.0, payload))
-            }),
-            Tag::Sym => store
-                .fetch_sym(ptr)
-                .map(|str| ScalarExpression::Sym(str.into())),
-            Tag::Fun => store.fetch_fun(ptr).and_then(|(arg, body, closed_env)| {
-                store.get_expr_hash(arg).and_then(|arg| {
-                    store.get_expr_hash(body).and_then(|body| {
-                        store
-                            .get_expr_hash(closed_env)
-                            .map(|closed_env| ScalarExpression::Fun {
-                                arg,
-                                body,
-                                closed_env,
-                            })
-                    })
-                })
-            }),
-            Tag::Num => store.fetch_num(ptr).map(|num| match num {
-                Num::U64(x) => ScalarExpression::Num((*x).into()),
-                Num::Scalar(x) => ScalarExpression::Num(*x),
-            }),
+    /// Return a `MergeConflict` for the first entry which is known in both `self` and `other` with different values.
+    fn check_conflicts(&self, other: &ScalarStore<F>) -> Result<(), MergeConflict<F>> {
+        for (ptr, expr) in &other.scalar_map {
+            if let Some(Some(existing)) = self.scalar_map.get(ptr) {
+                if matches!(expr, Some(expr) if expr != existing) {
+                    return Err(MergeConflict(uptr(ptr)));
+                }
+            }
+        }
+        for (ptr, cont) in &other.scalar_cont_map {
+            if let Some(Some(existing)) = self.scalar_cont_map.get(ptr) {
+                if matches!(cont, Some(cont) if cont != existing) {
+                    return Err(MergeConflict(uptr(ptr)));
+                }
+            }
+        }
+        Ok(())
+    }
 
-            Tag::Str => store
-                .fetch_str(ptr)
-                .map(|str| ScalarExpression::Str(str.to_string())),
-            Tag::Char => store.fetch_char(ptr).map(ScalarExpression::Char),
-            Tag::U64 => store.fetch_uint(ptr).map(ScalarExpression::UInt),
-            Tag::Thunk => unimplemented!(),
+    /// Compare `self` with `other`, reporting the pointers of each map which are present only in one store, or in both
+    /// but with different values.
+    pub fn diff(&self, other: &Self) -> ScalarStoreDiff<F> {
+        let mut diff = ScalarStoreDiff::default();
+
+        diff_maps(&self.scalar_map, &other.scalar_map, &mut diff);
+        diff_maps(&self.scalar_cont_map, &other.scalar_cont_map, &mut diff);
+
+        diff.only_in_self.sort();
+        diff.only_in_other.sort();
+        diff.differing.sort();
+        diff
+    }
+
+    /// Remove every expression and continuation not reachable from `roots`. Roots which are not in the store are
+    /// ignored, and each entry is visited at most once, so cycles are harmless.
+    pub fn gc(&mut self, roots: &[ScalarPtr<F>]) {
+        let (live_scalar_ptrs, live_scalar_cont_ptrs) = self.reachable(roots.to_vec(), Vec::new());
+
+        self.scalar_map
+            .retain(|scalar_ptr, _| live_scalar_ptrs.contains(scalar_ptr));
+        self.scalar_cont_map
+            .retain(|scalar_cont_ptr, _| live_scalar_cont_ptrs.contains(scalar_cont_ptr));
+    }
+
+    /// A new store containing only the expressions and continuations reachable from `root`, leaving `self`
+    /// unchanged, together with `root` itself. Returns `None` if `root` is not in the store.
+    pub fn subgraph(&self, root: &ScalarPtr<F>) -> Option<(Self, ScalarPtr<F>)> {
+        if !self.scalar_map.contains_key(root) {
+            return None;
         }
+        let (live_scalar_ptrs, live_scalar_cont_ptrs) = self.reachable(vec![*root], Vec::new());
+
+        let scalar_map = live_scalar_ptrs
+            .into_iter()
+            .map(|scalar_ptr| (scalar_ptr, self.scalar_map[&scalar_ptr].clone()))
+            .collect();
+        let scalar_cont_map = live_scalar_cont_ptrs
+            .into_iter()
+            .map(|scalar_cont_ptr| (scalar_cont_ptr, self.scalar_cont_map[&scalar_cont_ptr]))
+            .collect();
+
+        Some((
+            Self {
+                scalar_map,
+                scalar_cont_map,
+                ..Default::default()
+            },
+            *root,
+        ))
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub enum ScalarExpression<F: LurkField> {
-    Nil,
-    Cons(ScalarPtr<F>, ScalarPtr<F>),
-    Comm(F, ScalarPtr<F>),
-    Sym(String),
-    Fun {
-        arg: ScalarPtr<F>,
-        body: ScalarPtr<F>,
-        closed_env: ScalarPtr<F>,
-    },
-    Num(F),
-    Str(String),
-    Thunk(ScalarThunk<F>),
-    Char(char),
-    UInt(UInt),
-}
+    /// A preview of the expression `root` represents, expanded only down to `max_depth`, leaving `self` unchanged.
+    /// Depth is that of the source tree: `root` has depth 0, the elements of a list, like the other children of an
+    /// expression, are one deeper than the list, and a list's `cdr`s are as deep as the list. Expressions shallower
+    /// than `max_depth` are expanded as in `self`, and those at `max_depth` are included as opaque, as are the
+    /// continuations of expanded thunks, so the preview is a complete `ScalarStore` from which deeper subtrees can be
+    /// loaded on demand. A shared expression takes its smallest depth. If `root` is not in the store, the preview is
+    /// empty.
+    pub fn to_shallow(&self, root: &ScalarPtr<F>, max_depth: usize) -> ScalarStore<F> {
+        let mut preview = Self::default();
+        if !self.scalar_map.contains_key(root) {
+            return preview;
+        }
 
-impl<'a, F: LurkField> Default for ScalarExpression<F> {
-    fn default() -> Self {
-        Self::Nil
+        // Find the depth of each expression with a breadth-first search in which following a `cdr` is free.
+        let mut depths: BTreeMap<ScalarPtr<F>, usize> = BTreeMap::new();
+        let mut pending = std::collections::VecDeque::from([(*root, 0)]);
+        while let Some((ptr, depth)) = pending.pop_front() {
+            if depths.get(&ptr).map_or(false, |d| *d <= depth) {
+                continue;
+            }
+            depths.insert(ptr, depth);
+            if depth >= max_depth {
+                continue;
+            }
+            match self.get_expr(&ptr) {
+                Some(ScalarExpression::Cons(car, cdr)) => {
+                    pending.push_front((*cdr, depth));
+                    pending.push_back((*car, depth + 1));
+                }
+                Some(expr) => pending.extend(
+                    Self::child_scalar_ptrs(expr)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|child| (child, depth + 1)),
+                ),
+                None => (),
+            }
+        }
+
+        for (ptr, depth) in depths {
+            let expr = match self.scalar_map.get(&ptr) {
+                Some(expr) if depth < max_depth => expr.clone(),
+                Some(_) => None,
+                None => continue,
+            };
+            if let Some(ScalarExpression::Thunk(thunk)) = &expr {
+                if self.scalar_cont_map.contains_key(&thunk.continuation) {
+                    preview.scalar_cont_map.insert(thunk.continuation, None);
+                }
+            }
+            preview.scalar_map.insert(ptr, expr);
+        }
+        preview
     }
-}
 
-// Unused for now, but will be needed when we serialize Thunks to IPLD.
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-pub struct ScalarThunk<F: LurkField> {
-    pub(crate) value: ScalarPtr<F>,
-    pub(crate) continuation: ScalarContPtr<F>,
-}
+    /// Keep only the expressions for which `f` returns true, like `BTreeMap::retain`. Unlike `gc`, this does not
+    /// consider reachability, so the result may be incomplete; use `check_complete` to find the pointers left dangling.
+    pub fn retain(&mut self, f: impl Fn(&ScalarPtr<F>, &Option<ScalarExpression<F>>) -> bool) {
+        self.scalar_map.retain(|ptr, expr| f(ptr, expr));
+    }
 
-impl<F: LurkField> Copy for ScalarThunk<F> {}
+    /// Like `retain`, but for continuations.
+    pub fn retain_conts(
+        &mut self,
+        f: impl Fn(&ScalarContPtr<F>, &Option<ScalarContinuation<F>>) -> bool,
+    ) {
+        self.scalar_cont_map.retain(|ptr, cont| f(ptr, cont));
+    }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub enum ScalarContinuation<F: LurkField> {
-    Outermost,
-    Call {
-        unevaled_arg: ScalarPtr<F>,
-        saved_env: ScalarPtr<F>,
-        continuation: ScalarContPtr<F>,
-    },
-    Call2 {
-        function: ScalarPtr<F>,
-        saved_env: ScalarPtr<F>,
-        continuation: ScalarContPtr<F>,
-    },
-    Tail {
-        saved_env: ScalarPtr<F>,
-        continuation: ScalarContPtr<F>,
-    },
-    Error,
-    Lookup {
-        saved_env: ScalarPtr<F>,
-        continuation: ScalarContPtr<F>,
-    },
-    Unop {
-        operator: Op1,
-        continuation: ScalarContPtr<F>,
-    },
-    Binop {
-        operator: Op2,
-        saved_env: ScalarPtr<F>,
-        unevaled_args: ScalarPtr<F>,
-        continuation: ScalarContPtr<F>,
-    },
-    Binop2 {
-        operator: Op2,
-        evaled_arg: ScalarPtr<F>,
-        continuation: ScalarContPtr<F>,
-    },
-    If {
-        unevaled_args: ScalarPtr<F>,
-        continuation: ScalarContPtr<F>,
-    },
-    Let {
-        var: ScalarPtr<F>,
-        body: ScalarPtr<F>,
-        saved_env: ScalarPtr<F>,
-        continuation: ScalarContPtr<F>,
-    },
-    LetRec {
-        var: ScalarPtr<F>,
-        body: ScalarPtr<F>,
-        saved_env: ScalarPtr<F>,
-        continuation: ScalarContPtr<F>,
-    },
-    Emit {
-        continuation: ScalarContPtr<F>,
-    },
-    Dummy,
-    Terminal,
-}
+    /// Rebuild the `ScalarStore` over another field, converting every field element, including the tags and values of
+    /// pointers, with `f`. Nothing is rehashed, so the result is only meaningful if `f` preserves the meaning of each
+    /// element: the fields must be compatible, in that every element which occurs in the store is represented in `G`
+    /// and `f` is injective on them. Otherwise, distinct pointers may collide, and tags may no longer be recognized.
+    pub fn map_field<G: LurkField>(&self, f: impl Fn(&F) -> G) -> ScalarStore<G> {
+        let parts = |tag: &F, value: &F| (f(tag), f(value));
+        ScalarStore {
+            scalar_map: self
+                .scalar_map
+                .iter()
+                .map(|(ptr, expr)| {
+                    (
+                        map_ptr(ptr, &parts),
+                        expr.as_ref().map(|expr| expr.map_field(&f)),
+                    )
+                })
+                .collect(),
+            scalar_cont_map: self
+                .scalar_cont_map
+                .iter()
+                .map(|(ptr, cont)| {
+                    (
+                        map_ptr(ptr, &parts),
+                        cont.as_ref().map(|cont| cont.map_field(&f)),
+                    )
+                })
+                .collect(),
+            pending_scalar_ptrs: self
+                .pending_scalar_ptrs
+                .iter()
+                .map(|ptr| map_ptr(ptr, &parts))
+                .collect(),
+            pending_scalar_cont_ptrs: self
+                .pending_scalar_cont_ptrs
+                .iter()
+                .map(|ptr| map_ptr(ptr, &parts))
+                .collect(),
+        }
+    }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::eval::empty_sym_env;
-    use crate::field::FWrap;
-    use crate::store::ScalarPointer;
-    use blstrs::Scalar as Fr;
+    /// True if `self` and `other` hold the same expressions, ignoring their continuations and pending pointers.
+    pub fn expr_eq(&self, other: &Self) -> bool {
+        self.scalar_map == other.scalar_map
+    }
 
-    use quickcheck::{Arbitrary, Gen};
+    /// True if `self` and `other` represent the same content-addressed graph, up to opaqueness: an opaque entry is
+    /// compatible with any expansion of the same pointer, and entries present in only one store are allowed if they
+    /// are reachable in that store from an entry which is opaque in the other.
+    pub fn equiv(&self, other: &Self) -> bool {
+        self.equiv_aux(other) && other.equiv_aux(self)
+    }
 
-    use crate::test::frequency;
+    /// Check the half of `equiv` concerning the entries of `self`.
+    fn equiv_aux(&self, other: &Self) -> bool {
+        let mut hidden_scalar_ptrs = Vec::new();
+        for (ptr, expr) in &self.scalar_map {
+            match (expr, other.scalar_map.get(ptr)) {
+                (Some(expr), Some(Some(other_expr))) if expr != other_expr => return false,
+                (Some(_), Some(None)) => hidden_scalar_ptrs.push(*ptr),
+                _ => (),
+            }
+        }
+        let mut hidden_scalar_cont_ptrs = Vec::new();
+        for (ptr, cont) in &self.scalar_cont_map {
+            match (cont, other.scalar_cont_map.get(ptr)) {
+                (Some(cont), Some(Some(other_cont))) if cont != other_cont => return false,
+                (Some(_), Some(None)) => hidden_scalar_cont_ptrs.push(*ptr),
+                _ => (),
+            }
+        }
 
-    use libipld::serde::from_ipld;
-    use libipld::serde::to_ipld;
+        let (hidden_scalar_ptrs, hidden_scalar_cont_ptrs) =
+            self.reachable(hidden_scalar_ptrs, hidden_scalar_cont_ptrs);
+        self.scalar_map
+            .keys()
+            .all(|ptr| other.scalar_map.contains_key(ptr) || hidden_scalar_ptrs.contains(ptr))
+            && self.scalar_cont_map.keys().all(|ptr| {
+                other.scalar_cont_map.contains_key(ptr) || hidden_scalar_cont_ptrs.contains(ptr)
+            })
+    }
 
-    impl Arbitrary for ScalarThunk<Fr> {
-        fn arbitrary(g: &mut Gen) -> Self {
-            ScalarThunk {
-                value: Arbitrary::arbitrary(g),
-                continuation: Arbitrary::arbitrary(g),
+    /// The expressions and continuations present in the store and reachable from `scalar_ptrs` or `scalar_cont_ptrs`.
+    /// Each entry is visited at most once, so cycles are harmless.
+    fn reachable(
+        &self,
+        scalar_ptrs: Vec<ScalarPtr<F>>,
+        scalar_cont_ptrs: Vec<ScalarContPtr<F>>,
+    ) -> (BTreeSet<ScalarPtr<F>>, BTreeSet<ScalarContPtr<F>>) {
+        let mut live_scalar_ptrs: BTreeSet<ScalarPtr<F>> = Default::default();
+        let mut live_scalar_cont_ptrs: BTreeSet<ScalarContPtr<F>> = Default::default();
+        let mut pending_scalar_ptrs = scalar_ptrs;
+        let mut pending_scalar_cont_ptrs = scalar_cont_ptrs;
+
+        loop {
+            if let Some(scalar_ptr) = pending_scalar_ptrs.pop() {
+                if let Some(expr) = self.scalar_map.get(&scalar_ptr) {
+                    if !live_scalar_ptrs.insert(scalar_ptr) {
+                        continue;
+                    }
+                    if let Some(expr) = expr {
+                        pending_scalar_ptrs
+                            .extend(Self::child_scalar_ptrs(expr).unwrap_or_default());
+                        if let ScalarExpression::Thunk(thunk) = expr {
+                            pending_scalar_cont_ptrs.push(thunk.continuation);
+                        }
+                    }
+                }
+            } else if let Some(scalar_cont_ptr) = pending_scalar_cont_ptrs.pop() {
+                if let Some(cont) = self.scalar_cont_map.get(&scalar_cont_ptr) {
+                    if !live_scalar_cont_ptrs.insert(scalar_cont_ptr) {
+                        continue;
+                    }
+                    if let Some(cont) = cont {
+                        let (scalar_ptrs, scalar_cont_ptrs) = Self::child_scalar_cont_ptrs(cont);
+                        pending_scalar_ptrs.extend(scalar_ptrs);
+                        pending_scalar_cont_ptrs.extend(scalar_cont_ptrs);
+                    }
+                }
+            } else {
+                break;
             }
         }
+
+        (live_scalar_ptrs, live_scalar_cont_ptrs)
     }
 
-    #[quickcheck]
-    fn prop_scalar_thunk_ipld(x: ScalarThunk<Fr>) -> bool {
-        if let Ok(ipld) = to_ipld(x) {
-            if let Ok(y) = from_ipld(ipld) {
-                x == y
+    /// The child `ScalarPtr`s of every expression in the store, in order, with no children for opaque expressions.
+    /// Computing this once lets successive graph queries, such as `fold_with_adjacency`, `depth_with_adjacency`,
+    /// `size_with_adjacency` and `find_cycles_with_adjacency`, share it rather than each recomputing children.
+    pub fn adjacency(&self) -> BTreeMap<ScalarPtr<F>, Vec<ScalarPtr<F>>> {
+        self.scalar_map
+            .iter()
+            .map(|(scalar_ptr, expr)| {
+                let children = expr
+                    .as_ref()
+                    .and_then(Self::child_scalar_ptrs)
+                    .unwrap_or_default();
+                (*scalar_ptr, children)
+            })
+            .collect()
+    }
+
+    /// Every `ScalarPtr` referenced as a child more than once, by expressions or continuations, counting each reference.
+    /// These are the points at which the graph is a DAG rather than a tree.
+    pub fn shared_pointers(&self) -> BTreeSet<ScalarPtr<F>> {
+        let mut in_degrees: BTreeMap<ScalarPtr<F>, usize> = BTreeMap::new();
+        let expr_children = self
+            .scalar_map
+            .values()
+            .flatten()
+            .flat_map(|expr| Self::child_scalar_ptrs(expr).unwrap_or_default());
+        let cont_children = self
+            .scalar_cont_map
+            .values()
+            .flatten()
+            .flat_map(|cont| Self::child_scalar_cont_ptrs(cont).0);
+        for child in expr_children.chain(cont_children) {
+            *in_degrees.entry(child).or_insert(0) += 1;
+        }
+        in_degrees
+            .into_iter()
+            .filter(|(_, in_degree)| *in_degree > 1)
+            .map(|(scalar_ptr, _)| scalar_ptr)
+            .collect()
+    }
+
+    /// Find reference cycles in the expression graph, using a depth-first search over `child_scalar_ptrs`. Each cycle
+    /// is reported once, as the path of pointers from the first one reached by the search back around to itself.
+    pub fn find_cycles(&self) -> Vec<Vec<ScalarPtr<F>>> {
+        self.find_cycles_aux(None)
+    }
+
+    /// Like `find_cycles`, but using a precomputed `adjacency`.
+    pub fn find_cycles_with_adjacency(
+        &self,
+        adjacency: &BTreeMap<ScalarPtr<F>, Vec<ScalarPtr<F>>>,
+    ) -> Vec<Vec<ScalarPtr<F>>> {
+        self.find_cycles_aux(Some(adjacency))
+    }
+
+    fn find_cycles_aux(
+        &self,
+        adjacency: Option<&BTreeMap<ScalarPtr<F>, Vec<ScalarPtr<F>>>>,
+    ) -> Vec<Vec<ScalarPtr<F>>> {
+        let children = |scalar_ptr: &ScalarPtr<F>| self.dfs_children(adjacency, scalar_ptr);
+
+        let mut cycles = Vec::new();
+        let mut visited: BTreeSet<ScalarPtr<F>> = Default::default();
+
+        for root in self.scalar_map.keys() {
+            if visited.contains(root) {
+                continue;
+            }
+            // The current DFS path, with the children of each pointer which remain to be visited.
+            let mut path = vec![(*root, children(root))];
+            let mut on_path: BTreeSet<ScalarPtr<F>> = Default::default();
+            on_path.insert(*root);
+
+            while let Some((_, remaining)) = path.last_mut() {
+                if let Some(child) = remaining.pop() {
+                    if on_path.contains(&child) {
+                        let start = path.iter().position(|(p, _)| *p == child).unwrap();
+                        let cycle: Vec<_> = path[start..].iter().map(|(p, _)| *p).collect();
+                        // A pointer may reach the same cycle through more than one of its children.
+                        if !cycles.contains(&cycle) {
+                            cycles.push(cycle);
+                        }
+                    } else if !visited.contains(&child) && self.scalar_map.contains_key(&child) {
+                        on_path.insert(child);
+                        path.push((child, children(&child)));
+                    }
+                } else {
+                    let (scalar_ptr, _) = path.pop().unwrap();
+                    on_path.remove(&scalar_ptr);
+                    visited.insert(scalar_ptr);
+                }
+            }
+        }
+        cycles
+    }
+
+    /// Fold `f` over every expression reachable from `root`, in post-order, so each expression is visited after all
+    /// of its children. Shared subexpressions are visited once, and opaque ones not at all. A pointer already being
+    /// visited is skipped, so this terminates even if the graph has cycles.
+    pub fn fold<A>(
+        &self,
+        root: &ScalarPtr<F>,
+        init: A,
+        f: impl FnMut(A, &ScalarPtr<F>, &ScalarExpression<F>) -> A,
+    ) -> A {
+        self.fold_aux(None, root, init, f)
+    }
+
+    /// Like `fold`, but using a precomputed `adjacency`.
+    pub fn fold_with_adjacency<A>(
+        &self,
+        adjacency: &BTreeMap<ScalarPtr<F>, Vec<ScalarPtr<F>>>,
+        root: &ScalarPtr<F>,
+        init: A,
+        f: impl FnMut(A, &ScalarPtr<F>, &ScalarExpression<F>) -> A,
+    ) -> A {
+        self.fold_aux(Some(adjacency), root, init, f)
+    }
+
+    fn fold_aux<A>(
+        &self,
+        adjacency: Option<&BTreeMap<ScalarPtr<F>, Vec<ScalarPtr<F>>>>,
+        root: &ScalarPtr<F>,
+        init: A,
+        mut f: impl FnMut(A, &ScalarPtr<F>, &ScalarExpression<F>) -> A,
+    ) -> A {
+        let mut acc = init;
+        if !self.scalar_map.contains_key(root) {
+            return acc;
+        }
+        let mut visited: BTreeSet<ScalarPtr<F>> = Default::default();
+        visited.insert(*root);
+        let mut path = vec![(*root, self.dfs_children(adjacency, root))];
+
+        while let Some((_, remaining)) = path.last_mut() {
+            if let Some(child) = remaining.pop() {
+                if self.scalar_map.contains_key(&child) && visited.insert(child) {
+                    path.push((child, self.dfs_children(adjacency, &child)));
+                }
             } else {
-                false
+                let (scalar_ptr, _) = path.pop().unwrap();
+                if let Some(expr) = self.get_expr(&scalar_ptr) {
+                    acc = f(acc, &scalar_ptr, expr);
+                }
             }
-        } else {
-            false
         }
+        acc
     }
 
-    impl Arbitrary for ScalarExpression<Fr> {
-        fn arbitrary(g: &mut Gen) -> Self {
-            let input: Vec<(i64, Box<dyn Fn(&mut Gen) -> ScalarExpression<Fr>>)> = vec![
-                (100, Box::new(|_| Self::Nil)),
-                (
-                    100,
-                    Box::new(|g| Self::Cons(ScalarPtr::arbitrary(g), ScalarPtr::arbitrary(g))),
-                ),
-                (100, Box::new(|g| Self::Sym(String::arbitrary(g)))),
-                (100, Box::new(|g| Self::Str(String::arbitrary(g)))),
-                (
-                    100,
-                    Box::new(|g| {
-                        let f = FWrap::arbitrary(g);
-                        Self::Num(f.0)
-                    }),
-                ),
-                (
-                    100,
-                    Box::new(|g| Self::Fun {
-                        arg: ScalarPtr::arbitrary(g),
-                        body: ScalarPtr::arbitrary(g),
-                        closed_env: ScalarPtr::arbitrary(g),
-                    }),
-                ),
-                (100, Box::new(|g| Self::Thunk(ScalarThunk::arbitrary(g)))),
-            ];
-            frequency(g, input)
+    /// Every pointer in the store reachable from `root`, ordered so that each one precedes all of its parents and `root`
+    /// comes last. Opaque pointers are included, as leaves. If a cycle is reachable, it is returned instead, as the
+    /// path of pointers from the first one reached back around to itself.
+    pub fn topo_order(&self, root: &ScalarPtr<F>) -> Result<Vec<ScalarPtr<F>>, Cycle<F>> {
+        let mut order = Vec::new();
+        if !self.scalar_map.contains_key(root) {
+            return Ok(order);
+        }
+        let mut visited: BTreeSet<ScalarPtr<F>> = Default::default();
+        let mut on_path: BTreeSet<ScalarPtr<F>> = Default::default();
+        on_path.insert(*root);
+        let mut path = vec![(*root, self.dfs_children(None, root))];
+
+        while let Some((_, remaining)) = path.last_mut() {
+            if let Some(child) = remaining.pop() {
+                if on_path.contains(&child) {
+                    let start = path.iter().position(|(p, _)| *p == child).unwrap();
+                    return Err(Cycle(path[start..].iter().map(|(p, _)| *p).collect()));
+                } else if !visited.contains(&child) && self.scalar_map.contains_key(&child) {
+                    on_path.insert(child);
+                    path.push((child, self.dfs_children(None, &child)));
+                }
+            } else {
+                let (scalar_ptr, _) = path.pop().unwrap();
+                on_path.remove(&scalar_ptr);
+                visited.insert(scalar_ptr);
+                order.push(scalar_ptr);
+            }
         }
+        Ok(order)
     }
 
-    impl Arbitrary for ScalarContinuation<Fr> {
-        fn arbitrary(g: &mut Gen) -> Self {
-            let input: Vec<(i64, Box<dyn Fn(&mut Gen) -> ScalarContinuation<Fr>>)> = vec![
-                (100, Box::new(|_| Self::Outermost)),
-                (
-                    100,
-                    Box::new(|g| Self::Call {
-                        unevaled_arg: ScalarPtr::arbitrary(g),
-                        saved_env: ScalarPtr::arbitrary(g),
-                        continuation: ScalarContPtr::arbitrary(g),
-                    }),
-                ),
-                (
-                    100,
-                    Box::new(|g| Self::Call2 {
-                        function: ScalarPtr::arbitrary(g),
-                        saved_env: ScalarPtr::arbitrary(g),
-                        continuation: ScalarContPtr::arbitrary(g),
-                    }),
-                ),
-                (
-                    100,
-                    Box::new(|g| Self::Tail {
-                        saved_env: ScalarPtr::arbitrary(g),
-                        continuation: ScalarContPtr::arbitrary(g),
-                    }),
-                ),
-                (100, Box::new(|_| Self::Error)),
-                (
-                    100,
-                    Box::new(|g| Self::Lookup {
-                        saved_env: ScalarPtr::arbitrary(g),
-                        continuation: ScalarContPtr::arbitrary(g),
-                    }),
-                ),
-                (
-                    100,
-                    Box::new(|g| Self::Unop {
-                        operator: Op1::arbitrary(g),
-                        continuation: ScalarContPtr::arbitrary(g),
-                    }),
-                ),
-                (
-                    100,
-                    Box::new(|g| Self::Binop {
-                        operator: Op2::arbitrary(g),
-                        saved_env: ScalarPtr::arbitrary(g),
-                        unevaled_args: ScalarPtr::arbitrary(g),
-                        continuation: ScalarContPtr::arbitrary(g),
-                    }),
-                ),
-                (
-                    100,
-                    Box::new(|g| Self::Binop2 {
-                        operator: Op2::arbitrary(g),
-                        evaled_arg: ScalarPtr::arbitrary(g),
-                        continuation: ScalarContPtr::arbitrary(g),
-                    }),
-                ),
-                (
-                    100,
-                    Box::new(|g| Self::If {
-                        unevaled_args: ScalarPtr::arbitrary(g),
-                        continuation: ScalarContPtr::arbitrary(g),
-                    }),
-                ),
-                (
-                    100,
-                    Box::new(|g| Self::Let {
-                        var: ScalarPtr::arbitrary(g),
-                        body: ScalarPtr::arbitrary(g),
-                        saved_env: ScalarPtr::arbitrary(g),
-                        continuation: ScalarContPtr::arbitrary(g),
-                    }),
-                ),
-                (
-                    100,
-                    Box::new(|g| Self::LetRec {
-                        var: ScalarPtr::arbitrary(g),
-                        saved_env: ScalarPtr::arbitrary(g),
-                        body: ScalarPtr::arbitrary(g),
-                        continuation: ScalarContPtr::arbitrary(g),
-                    }),
-                ),
-                (100, Box::new(|_| Self::Dummy)),
-                (100, Box::new(|_| Self::Terminal)),
-            ];
-            frequency(g, input)
+    /// Every symbol name reachable from `root`, including those in the bodies and closed environments of functions.
+    pub fn collect_symbols(&self, root: &ScalarPtr<F>) -> BTreeSet<String> {
+        self.fold(root, BTreeSet::new(), |mut symbols, _, expr| {
+            if let ScalarExpression::Sym(s) = expr {
+                symbols.insert(s.clone());
+            }
+            symbols
+        })
+    }
+
+    /// The elements of the proper list `head`, as the `car` of each `Cons` along its chain of `cdr`s up to `nil`. If
+    /// the chain ends in anything else, including an opaque or missing expression, or loops back on itself, return an
+    /// `ImproperList` naming the offending tail.
+    pub fn list_elements(&self, head: &ScalarPtr<F>) -> Result<Vec<ScalarPtr<F>>, ImproperList<F>> {
+        let mut elements = Vec::new();
+        let mut seen = BTreeSet::new();
+        let mut tail = head;
+        loop {
+            match self.get_expr(tail) {
+                Some(ScalarExpression::Nil) => return Ok(elements),
+                Some(ScalarExpression::Cons(car, cdr)) if seen.insert(*tail) => {
+                    elements.push(*car);
+                    tail = cdr;
+                }
+                _ => return Err(ImproperList(*tail)),
+            }
+        }
+    }
+
+    /// The bindings of the environment `env`, such as the `closed_env` of a `Fun`, innermost first, as the name of each
+    /// variable and the pointer to its value. An environment is a list of bindings `(var . val)`, in which a `letrec`
+    /// contributes a list of such bindings instead of a single one, and the empty environment is `nil`. The walk stops
+    /// at the first part of `env` which is not of this form, or is opaque or missing, returning the bindings before it.
+    pub fn env_bindings(&self, env: &ScalarPtr<F>) -> Vec<(String, ScalarPtr<F>)> {
+        let mut bindings = Vec::new();
+        let mut env = env;
+        while let Some(ScalarExpression::Cons(binding, rest)) = self.get_expr(env) {
+            let complete = match self.get_expr(binding) {
+                Some(ScalarExpression::Cons(var_or_binding, val_or_more_bindings)) => {
+                    match self.get_expr(var_or_binding) {
+                        Some(ScalarExpression::Sym(var)) => {
+                            bindings.push((var.clone(), *val_or_more_bindings));
+                            true
+                        }
+                        Some(ScalarExpression::Cons(..)) => {
+                            self.push_bindings(binding, &mut bindings)
+                        }
+                        _ => false,
+                    }
+                }
+                _ => false,
+            };
+            if !complete {
+                break;
+            }
+            env = rest;
         }
+        bindings
+    }
+
+    /// Push the bindings of the `letrec` frame `frame`, a list of bindings `(var . val)`, onto `bindings`, and return
+    /// whether the whole frame was well-formed.
+    fn push_bindings(
+        &self,
+        frame: &ScalarPtr<F>,
+        bindings: &mut Vec<(String, ScalarPtr<F>)>,
+    ) -> bool {
+        let mut frame = frame;
+        loop {
+            match self.get_expr(frame) {
+                Some(ScalarExpression::Nil) => return true,
+                Some(ScalarExpression::Cons(binding, rest)) => match self.get_expr(binding) {
+                    Some(ScalarExpression::Cons(var, val)) => match self.get_expr(var) {
+                        Some(ScalarExpression::Sym(var)) => {
+                            bindings.push((var.clone(), *val));
+                            frame = rest;
+                        }
+                        _ => return false,
+                    },
+                    _ => return false,
+                },
+                _ => return false,
+            }
+        }
+    }
+
+    /// The number of expressions in the store with each `Tag`, counting opaque expressions by the tag of their pointer.
+    /// Pointers whose tag is not a valid `Tag` are not counted.
+    pub fn tag_histogram(&self) -> BTreeMap<Tag, usize> {
+        let mut histogram = BTreeMap::new();
+        for tag in self
+            .scalar_map
+            .keys()
+            .filter_map(|ptr| Tag::from_field(*ptr.tag()))
+        {
+            *histogram.entry(tag).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Like `tag_histogram`, but for continuations.
+    pub fn cont_tag_histogram(&self) -> BTreeMap<ContTag, usize> {
+        let mut histogram = BTreeMap::new();
+        for tag in self
+            .scalar_cont_map
+            .keys()
+            .filter_map(|ptr| ContTag::from_field(*ptr.tag()))
+        {
+            *histogram.entry(tag).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Every `Sym` and `Str` entry in the store, reachable or not, keyed by pointer. The tag of each pointer tells
+    /// which of the two it is.
+    pub fn string_table(&self) -> BTreeMap<ScalarPtr<F>, String> {
+        self.scalar_map
+            .iter()
+            .filter_map(|(ptr, expr)| match expr {
+                Some(ScalarExpression::Sym(s)) | Some(ScalarExpression::Str(s)) => {
+                    Some((*ptr, s.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The pointer of the `Sym` named `name`, by a linear scan of the store. The name must match exactly, so a symbol
+    /// read from source must be given in upper case, as the reader interns it.
+    pub fn find_sym(&self, name: &str) -> Option<ScalarPtr<F>> {
+        self.scalar_map.iter().find_map(|(ptr, expr)| match expr {
+            Some(ScalarExpression::Sym(sym)) if sym == name => Some(*ptr),
+            _ => None,
+        })
+    }
+
+    /// The pointer of the `Str` with contents `s`, by a linear scan of the store.
+    pub fn find_str(&self, s: &str) -> Option<ScalarPtr<F>> {
+        self.scalar_map.iter().find_map(|(ptr, expr)| match expr {
+            Some(ScalarExpression::Str(str)) if str == s => Some(*ptr),
+            _ => None,
+        })
+    }
+
+    /// The length of the longest path of expressions from `root` to a leaf, counting both ends, so an atom has depth 1
+    /// and `(1)`, a `Cons` of `1` and `nil`, has depth 2. Opaque expressions are not counted, and an edge which closes a
+    /// cycle is ignored.
+    pub fn depth(&self, root: &ScalarPtr<F>) -> usize {
+        self.depth_aux(None, root)
+    }
+
+    /// Like `depth`, but using a precomputed `adjacency`.
+    pub fn depth_with_adjacency(
+        &self,
+        adjacency: &BTreeMap<ScalarPtr<F>, Vec<ScalarPtr<F>>>,
+        root: &ScalarPtr<F>,
+    ) -> usize {
+        self.depth_aux(Some(adjacency), root)
+    }
+
+    fn depth_aux(
+        &self,
+        adjacency: Option<&BTreeMap<ScalarPtr<F>, Vec<ScalarPtr<F>>>>,
+        root: &ScalarPtr<F>,
+    ) -> usize {
+        let depths = self.fold_aux(adjacency, root, BTreeMap::new(), |mut depths, ptr, _| {
+            let max_child_depth = self
+                .dfs_children(adjacency, ptr)
+                .iter()
+                .filter_map(|child| depths.get(child))
+                .max()
+                .copied()
+                .unwrap_or(0);
+            depths.insert(*ptr, max_child_depth + 1);
+            depths
+        });
+        depths.get(root).copied().unwrap_or(0)
+    }
+
+    /// The number of distinct expressions reachable from `root`, including `root`. Shared subexpressions are counted
+    /// once, and opaque expressions are not counted.
+    pub fn size(&self, root: &ScalarPtr<F>) -> usize {
+        self.fold(root, 0, |size, _, _| size + 1)
+    }
+
+    /// Like `size`, but using a precomputed `adjacency`.
+    pub fn size_with_adjacency(
+        &self,
+        adjacency: &BTreeMap<ScalarPtr<F>, Vec<ScalarPtr<F>>>,
+        root: &ScalarPtr<F>,
+    ) -> usize {
+        self.fold_with_adjacency(adjacency, root, 0, |size, _, _| size + 1)
+    }
+
+    /// A rough estimate of the constraints needed to prove evaluation over `root`: the sum of the
+    /// `DEFAULT_CONSTRAINT_WEIGHTS` of every distinct expression reachable from it. Opaque expressions are not counted.
+    pub fn estimate_constraints(&self, root: &ScalarPtr<F>) -> usize {
+        self.estimate_constraints_with(root, &DEFAULT_CONSTRAINT_WEIGHTS)
+    }
+
+    /// Like `estimate_constraints`, but using `weights`.
+    pub fn estimate_constraints_with(
+        &self,
+        root: &ScalarPtr<F>,
+        weights: &ConstraintWeights,
+    ) -> usize {
+        self.fold(root, 0, |total, _, expr| total + weights.weight(expr))
+    }
+
+    /// Render the expression `root` represents as Lurk source, as the `Write` impl for `Expression` does for a `Store`.
+    /// Opaque expressions render as `<opaque:hash>`. Return `None` if any reachable pointer is absent, or if the
+    /// graph has a cycle.
+    pub fn to_source(&self, root: &ScalarPtr<F>) -> Option<String> {
+        self.to_source_aux(root, false)
+    }
+
+    /// Like `to_source`, but render each `(quote x)` form as the reader shorthand `'x`, for human inspection. Only the
+    /// rendering differs: the graph is unchanged, and reading the output produces the same expression.
+    pub fn to_source_with_quotes(&self, root: &ScalarPtr<F>) -> Option<String> {
+        self.to_source_aux(root, true)
+    }
+
+    fn to_source_aux(&self, root: &ScalarPtr<F>, quotes: bool) -> Option<String> {
+        self.topo_order(root).ok()?;
+        let mut out = Vec::new();
+        self.write_source(root, quotes, &mut out).ok()??;
+        String::from_utf8(out).ok()
+    }
+
+    /// The quoted expression, if `ptr` is a `(quote x)` form.
+    fn quoted(&self, ptr: &ScalarPtr<F>) -> Option<&ScalarPtr<F>> {
+        match self.get_expr(ptr)? {
+            ScalarExpression::Cons(car, cdr) => {
+                if self.get_expr(car)? != &ScalarExpression::Sym("QUOTE".into()) {
+                    return None;
+                }
+                match self.get_expr(cdr)? {
+                    ScalarExpression::Cons(quoted, nil)
+                        if self.get_expr(nil) == Some(&ScalarExpression::Nil) =>
+                    {
+                        Some(quoted)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn write_source<W: Write>(
+        &self,
+        ptr: &ScalarPtr<F>,
+        quotes: bool,
+        w: &mut W,
+    ) -> io::Result<Option<()>> {
+        let expr = match self.scalar_map.get(ptr) {
+            None => return Ok(None),
+            Some(None) => {
+                write!(w, "<opaque:{}>", crate::Num::Scalar(*ptr.value()))?;
+                return Ok(Some(()));
+            }
+            Some(Some(expr)) => expr,
+        };
+        match expr {
+            ScalarExpression::Nil => write!(w, "NIL")?,
+            ScalarExpression::Sym(s) => crate::writer::write_symbol::<F, _>(w, s)?,
+            ScalarExpression::Str(s) => write!(w, "\"{}\"", s)?,
+            ScalarExpression::Num(x) => match x.to_u64() {
+                Some(n) => write!(w, "{}", n)?,
+                None => write!(w, "{}", crate::Num::Scalar(*x))?,
+            },
+            ScalarExpression::Char(c) => write!(w, "#\\{}", c)?,
+            ScalarExpression::UInt(n) => write!(w, "{}u64", n)?,
+            ScalarExpression::Comm(..) => write!(w, "(comm {})", crate::Num::Scalar(*ptr.value()))?,
+            ScalarExpression::Fun { arg, body, .. } => {
+                write!(w, "<FUNCTION (")?;
+                // A zero-argument function has the dummy argument `_`, which is not printed.
+                if self.get_expr(arg) != Some(&ScalarExpression::Sym("_".into()))
+                    && self.write_source(arg, quotes, w)?.is_none()
+                {
+                    return Ok(None);
+                }
+                write!(w, ") ")?;
+                // The body is a list, so print its elements without the opening parenthesis.
+                if self.write_tail(body, quotes, w)?.is_none() {
+                    return Ok(None);
+                }
+                write!(w, ">")?;
+            }
+            ScalarExpression::Thunk(thunk) => {
+                write!(w, "Thunk{{ value: ")?;
+                if self.write_source(&thunk.value, quotes, w)?.is_none() {
+                    return Ok(None);
+                }
+                write!(
+                    w,
+                    " => cont: {} }}",
+                    crate::Num::Scalar(*thunk.continuation.value())
+                )?;
+            }
+            ScalarExpression::Cons(..) => match self.quoted(ptr).filter(|_| quotes) {
+                Some(quoted) => {
+                    write!(w, "'")?;
+                    if self.write_source(quoted, quotes, w)?.is_none() {
+                        return Ok(None);
+                    }
+                }
+                None => {
+                    write!(w, "(")?;
+                    if self.write_tail(ptr, quotes, w)?.is_none() {
+                        return Ok(None);
+                    }
+                }
+            },
+        }
+        Ok(Some(()))
+    }
+
+    /// Write the elements of the list `ptr` and the closing parenthesis, iterating along the `cdr`s.
+    fn write_tail<W: Write>(
+        &self,
+        ptr: &ScalarPtr<F>,
+        quotes: bool,
+        w: &mut W,
+    ) -> io::Result<Option<()>> {
+        let mut ptr = *ptr;
+        loop {
+            match self.scalar_map.get(&ptr) {
+                None => return Ok(None),
+                Some(Some(ScalarExpression::Nil)) => break,
+                Some(Some(ScalarExpression::Cons(car, cdr))) => {
+                    if self.write_source(car, quotes, w)?.is_none() {
+                        return Ok(None);
+                    }
+                    match self.scalar_map.get(cdr) {
+                        Some(Some(ScalarExpression::Nil)) => (),
+                        Some(Some(ScalarExpression::Cons(..))) => write!(w, " ")?,
+                        _ => {
+                            write!(w, " . ")?;
+                            if self.write_source(cdr, quotes, w)?.is_none() {
+                                return Ok(None);
+                            }
+                            break;
+                        }
+                    }
+                    ptr = *cdr;
+                }
+                Some(_) => {
+                    write!(w, ". ")?;
+                    if self.write_source(&ptr, quotes, w)?.is_none() {
+                        return Ok(None);
+                    }
+                    break;
+                }
+            }
+        }
+        write!(w, ")")?;
+        Ok(Some(()))
+    }
+
+    /// Render the expressions and continuations reachable from `root` as a Graphviz DOT graph, with one node per
+    /// pointer, labeled by its tag and the low hex digits of its value, and an edge to each child. Shared children
+    /// have a single node, so sharing is visible. Continuations are drawn as dashed boxes, and opaque expressions as
+    /// dotted ellipses.
+    pub fn to_dot(&self, root: &ScalarPtr<F>) -> String {
+        let mut nodes: BTreeMap<UPtr<F>, Vec<UPtr<F>>> = Default::default();
+        let mut pending: Vec<UPtr<F>> = vec![uptr(root)];
+
+        while let Some(node) = pending.pop() {
+            if nodes.contains_key(&node) {
+                continue;
+            }
+            let children: Vec<UPtr<F>> = if let Some(tag) = ContTag::from_field(*node.tag()) {
+                let scalar_cont_ptr = ScalarContPtr::from_parts(tag.as_field(), *node.value());
+                match self.scalar_cont_map.get(&scalar_cont_ptr) {
+                    Some(Some(cont)) => {
+                        let (scalar_ptrs, scalar_cont_ptrs) = Self::child_scalar_cont_ptrs(cont);
+                        let scalar_ptrs = scalar_ptrs.iter().map(uptr);
+                        scalar_ptrs
+                            .chain(scalar_cont_ptrs.iter().map(uptr))
+                            .collect()
+                    }
+                    Some(None) => vec![],
+                    None => continue,
+                }
+            } else {
+                let scalar_ptr = ScalarPtr::from_parts(*node.tag(), *node.value());
+                match self.scalar_map.get(&scalar_ptr) {
+                    Some(Some(expr)) => {
+                        let mut children: Vec<UPtr<F>> = Self::child_scalar_ptrs(expr)
+                            .unwrap_or_default()
+                            .iter()
+                            .map(uptr)
+                            .collect();
+                        if let ScalarExpression::Thunk(thunk) = expr {
+                            children.push(uptr(&thunk.continuation));
+                        }
+                        children
+                    }
+                    Some(None) => vec![],
+                    None => continue,
+                }
+            };
+            pending.extend(children.iter().rev());
+            nodes.insert(node, children);
+        }
+
+        let ids: BTreeMap<UPtr<F>, usize> =
+            nodes.keys().enumerate().map(|(i, n)| (*n, i)).collect();
+        let mut dot = String::from("digraph {\n");
+        for (node, id) in &ids {
+            let hash = crate::Num::Scalar(*node.value()).to_string();
+            let short_hash = &hash[hash.len() - 8..];
+            let (label, style) = if let Some(tag) = ContTag::from_field(*node.tag()) {
+                (format!("{:?}", tag), "shape=box, style=dashed")
+            } else {
+                let tag = Tag::from_field(*node.tag());
+                let label = tag.map_or_else(|| "Unknown".into(), |tag| format!("{:?}", tag));
+                let scalar_ptr = ScalarPtr::from_parts(*node.tag(), *node.value());
+                if let Some(None) = self.scalar_map.get(&scalar_ptr) {
+                    (label, "shape=ellipse, style=dotted")
+                } else {
+                    (label, "shape=ellipse")
+                }
+            };
+            dot.push_str(&format!(
+                "  n{} [label=\"{} {}\", {}];\n",
+                id, label, short_hash, style
+            ));
+        }
+        for (node, children) in &nodes {
+            for child in children {
+                if let Some(child_id) = ids.get(child) {
+                    dot.push_str(&format!("  n{} -> n{};\n", ids[node], child_id));
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The children of `scalar_ptr`, from `adjacency` if given, reversed so that popping them visits them in order.
+    fn dfs_children(
+        &self,
+        adjacency: Option<&BTreeMap<ScalarPtr<F>, Vec<ScalarPtr<F>>>>,
+        scalar_ptr: &ScalarPtr<F>,
+    ) -> Vec<ScalarPtr<F>> {
+        let mut children = match adjacency {
+            Some(adjacency) => adjacency.get(scalar_ptr).cloned().unwrap_or_default(),
+            None => self
+                .get_expr(scalar_ptr)
+                .and_then(Self::child_scalar_ptrs)
+                .unwrap_or_default(),
+        };
+        children.reverse();
+        children
+    }
+
+    /// A content-addressed digest of the whole store: its `ser_f` encoding, prefixed by its length, folded into a single
+    /// field element with Poseidon. Since `ser_f` orders entries by pointer, the digest does not depend on the order in
+    /// which entries were inserted.
+    pub fn digest(&self) -> Result<F, ScalarStoreError<F>> {
+        let constants = HashConstants::<F>::default();
+        let absorb = |acc: F, chunk: &[F]| {
+            let mut preimage = [acc, F::zero(), F::zero(), F::zero()];
+            preimage[1..=chunk.len()].copy_from_slice(chunk);
+            Poseidon::new_with_preimage(&preimage, constants.c4()).hash()
+        };
+
+        // Hash the entries as `ser_entries` yields them rather than building the whole `ser_f` vector, carrying any
+        // elements which do not fill a chunk over to the next entry.
+        let mut acc = F::from(self.serialized_len_fr() as u64);
+        let mut chunk = Vec::with_capacity(3);
+        for (uptr, body) in self.ser_entries() {
+            for f in [uptr.0, uptr.1].iter().chain(body?.iter()) {
+                chunk.push(*f);
+                if chunk.len() == 3 {
+                    acc = absorb(acc, &chunk);
+                    chunk.clear();
+                }
+            }
+        }
+        if !chunk.is_empty() {
+            acc = absorb(acc, &chunk);
+        }
+        Ok(acc)
+    }
+
+    /// Recompute the commitment hash of each `Comm` from its secret and payload, as `Store` does, and check that it
+    /// is the value of the `Comm`'s `ScalarPtr`. On failure, return the `Comm`s which do not match, in order. Opaque
+    /// commitments have no secret to check, so they are not reported.
+    pub fn verify_commitments(&self) -> Result<(), Vec<ScalarPtr<F>>> {
+        let constants = HashConstants::<F>::default();
+        let mismatches: Vec<ScalarPtr<F>> = self
+            .scalar_map
+            .iter()
+            .filter_map(|(scalar_ptr, expr)| match expr {
+                Some(ScalarExpression::Comm(secret, payload)) => {
+                    let preimage = [*secret, *payload.tag(), *payload.value()];
+                    let hash = Poseidon::new_with_preimage(&preimage, constants.c3()).hash();
+                    let expected = ScalarPtr::from_parts(Tag::Comm.as_field(), hash);
+                    (!bool::from(scalar_ptr.ct_eq(&expected))).then(|| *scalar_ptr)
+                }
+                _ => None,
+            })
+            .collect();
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(mismatches)
+        }
+    }
+
+    /// Check that `store` reproduces every expression in the `ScalarStore`: that it has a `Ptr` for each `ScalarPtr`,
+    /// which hashes back to that `ScalarPtr` and, unless the entry is opaque, represents the same expression. On
+    /// failure, return the `ScalarPtr`s which are not reproduced, in order. This is a cross-check of `to_store`.
+    /// NOTE: This requires that `store.scalar_cache` has been hydrated.
+    pub fn validate_against_store(&self, store: &Store<F>) -> Result<(), Vec<ScalarPtr<F>>> {
+        let mismatches: Vec<ScalarPtr<F>> = self
+            .scalar_map
+            .iter()
+            .filter(|(scalar_ptr, expr)| {
+                let reproduced = store.fetch_scalar(scalar_ptr).map_or(false, |ptr| {
+                    store.get_expr_hash(&ptr).as_ref() == Some(*scalar_ptr)
+                        && expr.as_ref().map_or(true, |expr| {
+                            ScalarExpression::from_ptr(store, &ptr).as_ref() == Some(expr)
+                        })
+                });
+                !reproduced
+            })
+            .map(|(scalar_ptr, _)| *scalar_ptr)
+            .collect();
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(mismatches)
+        }
+    }
+
+    /// Recompute the pointer of every expression and continuation from its contents, as `Store` hashes them, and re-key
+    /// the maps accordingly, so that the store is consistent again after its contents have been edited. Children are
+    /// rehashed before their parents, so references to them are updated too. Opaque entries have no contents to hash,
+    /// so they keep their pointers. The store must be finalized and complete, and its expressions must have no cycles.
+    pub fn rehash(&mut self) -> Result<(), ScalarStoreError<F>> {
+        self.rehash_aux().map(|_| ())
+    }
+
+    /// Like `rehash`, but return the new pointer of each expression, keyed by its old one.
+    fn rehash_aux(&mut self) -> Result<BTreeMap<ScalarPtr<F>, ScalarPtr<F>>, ScalarStoreError<F>> {
+        if let Some(cycle) = self.find_cycles().first() {
+            return Err(ScalarStoreError::Cycle(uptr(&cycle[0])));
+        }
+        // Interning rebuilds each expression from its children's contents, so `store` hashes it afresh.
+        let mut store = self.to_store()?;
+        let ptrs = self
+            .scalar_map
+            .keys()
+            .map(|ptr| {
+                store
+                    .intern_scalar_ptr(*ptr, self)
+                    .ok_or_else(|| ScalarStoreError::InternFailed(uptr(ptr)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let cont_ptrs = self
+            .scalar_cont_map
+            .keys()
+            .map(|ptr| {
+                store
+                    .intern_scalar_cont_ptr(*ptr, self)
+                    .ok_or_else(|| ScalarStoreError::InternFailed(uptr(ptr)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        store.hydrate_scalar_cache();
+
+        let mut rehashed = Self::default();
+        for ptr in ptrs.iter() {
+            rehashed.add_ptr(&store, ptr);
+        }
+        for cont_ptr in cont_ptrs.iter() {
+            rehashed.add_cont_ptr(&store, cont_ptr);
+        }
+        rehashed.try_finalize(&store)?;
+        let rekeyed = self
+            .scalar_map
+            .keys()
+            .zip(ptrs.iter())
+            .filter_map(|(old, ptr)| Some((*old, store.get_expr_hash(ptr)?)))
+            .collect();
+        *self = rehashed;
+        Ok(rekeyed)
+    }
+
+    /// Rewrite every reference to the expression `old`, in any expression or continuation, to refer to `new` instead,
+    /// then `rehash`, so that the ancestors of each rewritten reference are re-keyed by their new contents, up to the
+    /// roots. Return the new pointer of each expression whose pointer changed, keyed by its old one, so that callers can
+    /// find their roots again. `old` itself is kept. `new` must be present, and the store must satisfy the requirements
+    /// of `rehash`; in particular, `new` must not contain `old`, or the rewrite would create a cycle. On error, `self` is
+    /// left unchanged.
+    pub fn replace(
+        &mut self,
+        old: ScalarPtr<F>,
+        new: ScalarPtr<F>,
+    ) -> Result<BTreeMap<ScalarPtr<F>, ScalarPtr<F>>, ScalarStoreError<F>> {
+        if !self.scalar_map.contains_key(&new) {
+            return Err(ScalarStoreError::MissingPtr(uptr(&new)));
+        }
+        let swap = |tag: &F, value: &F| {
+            if (tag, value) == (old.tag(), old.value()) {
+                (*new.tag(), *new.value())
+            } else {
+                (*tag, *value)
+            }
+        };
+
+        let mut rewritten = self.clone();
+        for expr in rewritten.scalar_map.values_mut().flatten() {
+            *expr = expr.map_parts(&|f| *f, &swap);
+        }
+        for cont in rewritten.scalar_cont_map.values_mut().flatten() {
+            *cont = cont.map_ptrs(&swap);
+        }
+        let rekeyed = rewritten.rehash_aux()?;
+        *self = rewritten;
+        Ok(rekeyed
+            .into_iter()
+            .filter(|(old, new)| old != new)
+            .collect())
+    }
+
+    /// Make every `Comm` opaque, so that its secret is not serialized. Its `ScalarPtr`, which is the commitment, and its
+    /// payload are kept.
+    pub fn redact_secrets(&mut self) {
+        for expr in self.scalar_map.values_mut() {
+            if let Some(ScalarExpression::Comm(..)) = expr {
+                *expr = None;
+            }
+        }
+    }
+
+    /// Bring the store into a canonical form, so that stores which represent the same content have the same `ser_f`
+    /// encoding and `digest`. The normalization rules are:
+    /// - An opaque entry whose expression is determined by its `ScalarPtr` alone is expanded. These are the immediate
+    ///   values: `Num`, `Char` and `U64`, whose value is the pointer's value, and the empty `Str` and `Sym`, whose
+    ///   hash is zero. Other opaque entries are kept, since their expressions cannot be recovered from a hash.
+    /// - A `Num` holds a single field element, so the `U64` and `Scalar` representations which `Store` distinguishes
+    ///   are already identified when entries are added, and need no further normalization.
+    /// - Entries are ordered by pointer, which the maps and `ser_f` already guarantee.
+    pub fn canonicalize(&mut self) {
+        for (ptr, expr) in self.scalar_map.iter_mut() {
+            if expr.is_none() {
+                *expr = ScalarExpression::from_immediate(ptr);
+            }
+        }
+    }
+
+    /// The total number of expression and continuation entries in the store.
+    pub fn len(&self) -> usize {
+        self.scalar_map.len() + self.scalar_cont_map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scalar_map.is_empty() && self.scalar_cont_map.is_empty()
+    }
+
+    pub fn to_store_with_expr(
+        &mut self,
+        ptr: &ScalarPtr<F>,
+    ) -> Result<(Store<F>, Ptr<F>), ScalarStoreError<F>> {
+        if !self.scalar_map.contains_key(ptr) {
+            return Err(ScalarStoreError::MissingPtr(uptr(ptr)));
+        }
+        let mut store = self.to_store()?;
+        let ptr = store
+            .intern_scalar_ptr(*ptr, self)
+            .ok_or_else(|| ScalarStoreError::InternFailed(uptr(ptr)))?;
+
+        Ok((store, ptr))
+    }
+
+    pub fn to_store(&mut self) -> Result<Store<F>, ScalarStoreError<F>> {
+        self.check_internable()?;
+
+        let mut store = Store::new();
+
+        for ptr in self.scalar_map.keys() {
+            store
+                .intern_scalar_ptr(*ptr, self)
+                .ok_or_else(|| ScalarStoreError::InternFailed(uptr(ptr)))?;
+        }
+        for ptr in self.scalar_cont_map.keys() {
+            store
+                .intern_scalar_cont_ptr(*ptr, self)
+                .ok_or_else(|| ScalarStoreError::InternFailed(uptr(ptr)))?;
+        }
+        Ok(store)
+    }
+
+    /// Like `to_store`, but check that each expression and continuation hashes, once interned, to the pointer it is
+    /// stored under, so that an untrusted `ScalarStore` cannot claim contents for a pointer which does not commit to
+    /// them. Opaque entries have no contents, so there is nothing to check.
+    pub fn to_store_verified(&mut self) -> Result<Store<F>, VerifyError<F>> {
+        self.check_internable()?;
+
+        let mut store = Store::new();
+
+        for (scalar_ptr, expr) in self.scalar_map.iter() {
+            let ptr = store
+                .intern_scalar_ptr(*scalar_ptr, self)
+                .ok_or_else(|| ScalarStoreError::InternFailed(uptr(scalar_ptr)))?;
+            if expr.is_some() {
+                let actual = store
+                    .hash_expr(&ptr)
+                    .ok_or_else(|| ScalarStoreError::InternFailed(uptr(scalar_ptr)))?;
+                if actual != *scalar_ptr {
+                    return Err(VerifyError::HashMismatch {
+                        claimed: uptr(scalar_ptr),
+                        actual: uptr(&actual),
+                    });
+                }
+            }
+        }
+        for (scalar_cont_ptr, cont) in self.scalar_cont_map.iter() {
+            let ptr = store
+                .intern_scalar_cont_ptr(*scalar_cont_ptr, self)
+                .ok_or_else(|| ScalarStoreError::InternFailed(uptr(scalar_cont_ptr)))?;
+            if cont.is_some() {
+                let actual = store
+                    .hash_cont(&ptr)
+                    .ok_or_else(|| ScalarStoreError::InternFailed(uptr(scalar_cont_ptr)))?;
+                if actual != *scalar_cont_ptr {
+                    return Err(VerifyError::HashMismatch {
+                        claimed: uptr(scalar_cont_ptr),
+                        actual: uptr(&actual),
+                    });
+                }
+            }
+        }
+        Ok(store)
+    }
+
+    /// Check what `to_store` requires before interning: that the store is finalized, that every child is present, and
+    /// that every tag is known.
+    fn check_internable(&self) -> Result<(), ScalarStoreError<F>> {
+        if !self.is_finalized() {
+            return Err(ScalarStoreError::Pending);
+        }
+        self.check_children()?;
+        if let Some(ptr) = self
+            .scalar_map
+            .keys()
+            .find(|ptr| Tag::from_field(*ptr.tag()).is_none())
+        {
+            return Err(ScalarStoreError::UnknownTag(uptr(ptr)));
+        }
+        if let Some(ptr) = self
+            .scalar_cont_map
+            .keys()
+            .find(|ptr| ContTag::from_field(*ptr.tag()).is_none())
+        {
+            return Err(ScalarStoreError::UnknownTag(uptr(ptr)));
+        }
+        Ok(())
+    }
+
+    /// Check that every field element of every entry, in its pointer or its payload, is canonical: that
+    /// `F::from_repr(f.to_repr())` round-trips. On failure, return the first offending entry in `UPtr` order. An entry
+    /// which cannot be encoded at all also fails.
+    pub fn validate(&self) -> Result<(), ValidationError<F>> {
+        for (uptr, body) in self.ser_entries() {
+            let body = body.map_err(|_| ValidationError(uptr))?;
+            let canonical = |f: &F| Option::<F>::from(F::from_repr(f.to_repr())) == Some(*f);
+            if !(canonical(&uptr.0) && canonical(&uptr.1) && body.iter().all(canonical)) {
+                return Err(ValidationError(uptr));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every `ScalarPtr` and `ScalarContPtr` referenced by a stored expression or continuation, including
+    /// the continuation of a `Thunk`, is present in the store. On failure, return the referenced pointers which are
+    /// absent, as `UPtr`s, in order and without duplicates.
+    pub fn check_complete(&self) -> Result<(), Vec<UPtr<F>>> {
+        let missing = self.missing_children();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+
+    /// Check that every child of every expression and continuation is itself present, so that no pointer is left
+    /// dangling when interning into a `Store`. This is `check_complete`, reporting the first missing child.
+    fn check_children(&self) -> Result<(), ScalarStoreError<F>> {
+        self.check_complete()
+            .map_err(|missing| ScalarStoreError::MissingChild(missing[0]))
+    }
+
+    /// True if the store has entries and all of them are opaque, which usually means that redaction went too far or
+    /// that the `Store` it was built from had not been hydrated.
+    pub fn is_fully_opaque(&self) -> bool {
+        !self.is_empty()
+            && self.scalar_map.values().all(Option::is_none)
+            && self.scalar_cont_map.values().all(Option::is_none)
+    }
+
+    /// Diagnose conditions which are legal but usually indicate a bug in whatever produced the store: that it is
+    /// empty, that it is fully opaque, or that children are dangling. Return a warning for each which holds.
+    pub fn health_check(&self) -> Vec<HealthWarning<F>> {
+        let mut warnings = Vec::new();
+        if self.is_empty() {
+            warnings.push(HealthWarning::Empty);
+        }
+        if self.is_fully_opaque() {
+            warnings.push(HealthWarning::FullyOpaque);
+        }
+        let missing = self.missing_children();
+        if !missing.is_empty() {
+            warnings.push(HealthWarning::DanglingChildren(missing));
+        }
+        warnings
+    }
+
+    /// Every child of every expression and continuation which is not itself present, in order and without duplicates.
+    fn missing_children(&self) -> Vec<UPtr<F>> {
+        let mut missing = BTreeSet::new();
+        for expr in self.scalar_map.values().flatten() {
+            for child in Self::child_scalar_ptrs(expr).unwrap_or_default() {
+                if !self.scalar_map.contains_key(&child) {
+                    missing.insert(uptr(&child));
+                }
+            }
+            if let ScalarExpression::Thunk(thunk) = expr {
+                if !self.scalar_cont_map.contains_key(&thunk.continuation) {
+                    missing.insert(uptr(&thunk.continuation));
+                }
+            }
+        }
+        for cont in self.scalar_cont_map.values().flatten() {
+            let (scalar_ptrs, scalar_cont_ptrs) = Self::child_scalar_cont_ptrs(cont);
+            for child in scalar_ptrs {
+                if !self.scalar_map.contains_key(&child) {
+                    missing.insert(uptr(&child));
+                }
+            }
+            for child in scalar_cont_ptrs {
+                if !self.scalar_cont_map.contains_key(&child) {
+                    missing.insert(uptr(&child));
+                }
+            }
+        }
+        missing.into_iter().collect()
+    }
+
+    /// Serialize the `ScalarStore` as a flat vector of field elements. Expressions and continuations are merged into a
+    /// single map ordered by `UPtr`, and each entry is written as `[tag, value, len, known, ...payload]`, where `known`
+    /// is zero for an opaque entry (which has no payload) and one otherwise, and `len` is the number of elements which
+    /// follow it in the entry. This makes entries self-delimiting, so that `de_f` can skip entries with unknown tags.
+    pub fn ser_f(self) -> Result<Vec<F>, ScalarStoreError<F>> {
+        let mut res = Vec::new();
+        for (uptr, body) in self.ser_entries() {
+            res.push(uptr.0);
+            res.push(uptr.1);
+            res.extend(body?);
+        }
+        Ok(res)
+    }
+
+    /// The number of field elements which `ser_f` would produce, computed from the arity of each entry without encoding
+    /// it. This does not check that the store can be encoded: `ser_f` may still fail on a `Char`.
+    pub fn serialized_len_fr(&self) -> usize {
+        let entry_len = |payload_len: Option<usize>| 4 + payload_len.unwrap_or(0);
+        let exprs = self
+            .scalar_map
+            .values()
+            .map(|expr| entry_len(expr.as_ref().map(ScalarExpression::ser_len)));
+        let conts = self
+            .scalar_cont_map
+            .values()
+            .map(|cont| entry_len(cont.as_ref().map(ScalarContinuation::ser_len)));
+        exprs.chain(conts).sum()
+    }
+
+    /// Serialize the `ScalarStore` as `ser_f` does, then compress the result with `rle_encode`, which shrinks runs of
+    /// equal field elements, such as the zero chunks of a long string of NULs, to two elements each.
+    pub fn ser_f_compressed(self) -> Result<Vec<F>, ScalarStoreError<F>> {
+        Ok(rle_encode(&self.ser_f()?))
+    }
+
+    /// Deserialize a `ScalarStore` from the output of `ser_f_compressed`, which must decompress to at most `max_len`
+    /// field elements. Since a single run can expand to any length, `max_len` is what bounds the memory used on
+    /// untrusted input. Positions in errors from `de_f` refer to the decompressed input.
+    pub fn de_f_compressed(input: &[F], max_len: usize) -> Result<Self, DeserializeError> {
+        Self::de_f(&rle_decode(input, max_len)?)
+    }
+
+    /// Write the `ser_f` encoding of the `ScalarStore` to `w`, as the `to_repr` bytes of each field element, one entry
+    /// at a time rather than first building the whole vector. An entry which cannot be encoded is reported as an error
+    /// of kind `InvalidData`.
+    pub fn serialize_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for (uptr, body) in self.ser_entries() {
+            let body = body.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            for f in [uptr.0, uptr.1].iter().chain(body.iter()) {
+                w.write_all(f.to_repr().as_ref())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a `ScalarStore` written by `serialize_to` from `r`, until the end of input. Malformed input is reported as
+    /// an error of kind `InvalidData`.
+    pub fn deserialize_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut fs = Vec::new();
+        while let Some(f) = read_field(r)? {
+            fs.push(f);
+        }
+        Self::de_f(&fs).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Lazily read the expressions of a `ScalarStore` written by `serialize_to` from `r`, yielding one entry at a time in
+    /// serialization order without building the whole store. Continuations and entries with unknown tags are skipped.
+    /// Malformed input is reported as an error of kind `InvalidData`, after which the iterator ends.
+    pub fn deserialize_lazy<R: Read>(
+        mut r: R,
+    ) -> impl Iterator<Item = io::Result<(ScalarPtr<F>, Option<ScalarExpression<F>>)>> {
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            while !failed {
+                match Self::read_entry(&mut r) {
+                    Ok(Some(entry)) => {
+                        if let Some(expr) = entry.scalar_map.into_iter().next() {
+                            return Some(Ok(expr));
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        failed = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    /// Read a single `ser_f` entry from `r` and decode it as a `ScalarStore` holding at most that entry, or `None` if
+    /// `r` is already at the end of input.
+    fn read_entry<R: Read>(r: &mut R) -> io::Result<Option<Self>> {
+        let tag = match read_field(r)? {
+            Some(tag) => tag,
+            None => return Ok(None),
+        };
+        let mut next =
+            || read_field::<F, R>(r)?.ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof));
+        let mut fs = vec![tag, next()?, next()?];
+        let len = fs[2]
+            .to_u64()
+            .and_then(|len| usize::try_from(len).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid entry length"))?;
+        for _ in 0..len {
+            fs.push(next()?);
+        }
+        Self::de_f(&fs)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Append the entries of the `ScalarStore` which are not already in the file at `path` to it, in the format of
+    /// `serialize_to`, creating the file if needed. The file can be read back with `deserialize_from` or
+    /// `read_fr_file`, and holds each pointer at most once however often it is appended to.
+    ///
+    /// The pointers already in the file are recorded in an index alongside it, at `append_index_path(path)`: the
+    /// length in bytes of the file it describes as a little-endian `u64`, followed by the `to_repr` bytes of the tag
+    /// and value of each entry's pointer. This makes membership checks cheap, since only the index has to be read
+    /// rather than the whole file. The index is only a cache: if it is missing, malformed or records a length other
+    /// than the file's, it is rebuilt by scanning the entry headers of the file.
+    pub fn append_to_file(&self, path: &Path) -> io::Result<()> {
+        let index_path = Self::append_index_path(path);
+        let data_len = match std::fs::metadata(path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        let mut on_disk = match Self::read_append_index(&index_path, data_len)? {
+            Some(on_disk) => on_disk,
+            None if data_len == 0 => BTreeSet::new(),
+            None => Self::scan_ptrs(&mut io::BufReader::new(std::fs::File::open(path)?))?,
+        };
+
+        // Encode every new entry before writing anything, so that an entry which cannot be encoded leaves the file
+        // unchanged.
+        let mut entries = Vec::new();
+        for (uptr, body) in self.ser_entries() {
+            if on_disk.contains(&uptr) {
+                continue;
+            }
+            let body = body.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            for f in [uptr.0, uptr.1].iter().chain(body.iter()) {
+                entries.extend_from_slice(f.to_repr().as_ref());
+            }
+            on_disk.insert(uptr);
+        }
+
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&entries)?;
+
+        // The index is replaced as a whole, so that an interrupted write leaves either the old index, whose length
+        // then no longer matches the file, or the new one.
+        let mut index = (data_len + entries.len() as u64).to_le_bytes().to_vec();
+        for uptr in on_disk.iter() {
+            index.extend_from_slice(uptr.0.to_repr().as_ref());
+            index.extend_from_slice(uptr.1.to_repr().as_ref());
+        }
+        let mut tmp_path = index_path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        std::fs::write(&tmp_path, index)?;
+        std::fs::rename(&tmp_path, &index_path)
+    }
+
+    /// The path of the index which `append_to_file` keeps for the file at `path`: `path` with `.idx` appended.
+    pub fn append_index_path(path: &Path) -> PathBuf {
+        let mut index_path = path.as_os_str().to_owned();
+        index_path.push(".idx");
+        PathBuf::from(index_path)
+    }
+
+    /// Read the pointers recorded in the index at `index_path`, or `None` if it is missing, malformed or describes a
+    /// file whose length is not `data_len`.
+    fn read_append_index(
+        index_path: &Path,
+        data_len: u64,
+    ) -> io::Result<Option<BTreeSet<UPtr<F>>>> {
+        let bytes = match std::fs::read(index_path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if bytes.len() < 8 || bytes[..8] != data_len.to_le_bytes() {
+            return Ok(None);
+        }
+        let mut index = &bytes[8..];
+        let mut on_disk = BTreeSet::new();
+        loop {
+            let tag = match read_field(&mut index) {
+                Ok(Some(tag)) => tag,
+                Ok(None) => return Ok(Some(on_disk)),
+                Err(_) => return Ok(None),
+            };
+            match read_field(&mut index) {
+                Ok(Some(value)) => on_disk.insert(UPtr::new(tag, value)),
+                _ => return Ok(None),
+            };
+        }
+    }
+
+    /// Read the pointers of the entries written by `serialize_to` to `r`, skipping their payloads.
+    fn scan_ptrs<R: Read>(r: &mut R) -> io::Result<BTreeSet<UPtr<F>>> {
+        let mut ptrs = BTreeSet::new();
+        while let Some(tag) = read_field(r)? {
+            let mut next = || {
+                read_field::<F, R>(r)?.ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+            };
+            let value = next()?;
+            let len = next()?.to_u64().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "invalid entry length")
+            })?;
+            for _ in 0..len {
+                next()?;
+            }
+            ptrs.insert(UPtr::new(tag, value));
+        }
+        Ok(ptrs)
+    }
+
+    /// Serialize the `ScalarStore` to a self-describing binary format: a 4-byte magic, a version byte and the
+    /// little-endian `FIELD_CODEC` of `F`, followed by the `serialize_to` encoding of the store.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ScalarStoreError<F>> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&BYTES_MAGIC);
+        bytes.push(BYTES_VERSION);
+        bytes.extend_from_slice(&F::FIELD_CODEC.to_le_bytes());
+        for (uptr, body) in self.ser_entries() {
+            for f in [uptr.0, uptr.1].iter().chain(body?.iter()) {
+                bytes.extend_from_slice(f.to_repr().as_ref());
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Deserialize a `ScalarStore` from the output of `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DeserializeError> {
+        let header_len = BYTES_MAGIC.len() + 1 + 8;
+        if bytes.len() < header_len || bytes[..BYTES_MAGIC.len()] != BYTES_MAGIC {
+            return Err(DeserializeError::InvalidMagic);
+        }
+        let version = bytes[BYTES_MAGIC.len()];
+        if version != BYTES_VERSION {
+            return Err(DeserializeError::UnsupportedVersion(version));
+        }
+        let mut codec = [0u8; 8];
+        codec.copy_from_slice(&bytes[BYTES_MAGIC.len() + 1..header_len]);
+        let codec = u64::from_le_bytes(codec);
+        if codec != F::FIELD_CODEC {
+            return Err(DeserializeError::FieldMismatch(codec));
+        }
+
+        Self::from_repr_bytes(&bytes[header_len..])
+    }
+
+    /// Deserialize a `ScalarStore` from concatenated `to_repr` bytes of the field elements of its `ser_f` encoding, as
+    /// written by `serialize_to`.
+    fn from_repr_bytes(body: &[u8]) -> Result<Self, DeserializeError> {
+        let repr_len = F::Repr::default().as_ref().len();
+        if body.len() % repr_len != 0 {
+            return Err(DeserializeError::UnexpectedEnd(body.len() / repr_len));
+        }
+        let fs = body
+            .chunks(repr_len)
+            .enumerate()
+            .map(|(i, chunk)| F::from_bytes(chunk).ok_or(DeserializeError::InvalidFieldElement(i)))
+            .collect::<Result<Vec<F>, _>>()?;
+
+        Self::de_f(&fs)
+    }
+
+    /// Read a file of concatenated field element reprs, as written by `serialize_to`, and decode it with `de_f`. A
+    /// decoding failure is reported with the byte offset in the file of the field element at which it occurred.
+    pub fn read_fr_file(path: &Path) -> Result<Self, FrFileError> {
+        let bytes = std::fs::read(path)?;
+        let repr_len = F::Repr::default().as_ref().len();
+        Self::from_repr_bytes(&bytes).map_err(|error| {
+            let offset = match error {
+                DeserializeError::UnexpectedEnd(i)
+                | DeserializeError::InvalidPayload(i)
+                | DeserializeError::InvalidFieldElement(i) => i * repr_len,
+                _ => 0,
+            };
+            FrFileError::Decode { offset, error }
+        })
+    }
+
+    /// Serialize the `ScalarStore` with bincode, a compact format which, unlike IPLD, is not self-describing. Like
+    /// every serde encoding, this is of the `VersionedScalarStore`, so pending pointers are not serialized.
+    pub fn to_bincode(&self) -> bincode::Result<Vec<u8>>
+    where
+        F: Serialize,
+    {
+        bincode::serialize(self)
+    }
+
+    /// Deserialize a `ScalarStore` from the output of `to_bincode`.
+    pub fn from_bincode(bytes: &[u8]) -> bincode::Result<Self>
+    where
+        F: for<'de> Deserialize<'de>,
+    {
+        bincode::deserialize(bytes)
+    }
+
+    /// Serialize the `ScalarStore` to IPLD, encode it as DAG-CBOR, and return the CID of the resulting block, hashed
+    /// with the multihash `code`, or with `DEFAULT_CID_CODE` if none is given.
+    #[cfg(feature = "ipld")]
+    pub fn to_cid(&self, code: Option<u64>) -> Result<cid::Cid, CidError>
+    where
+        F: Serialize,
+    {
+        use libipld::cbor::DagCborCodec;
+        use multihash::MultihashDigest;
+
+        let code = code.unwrap_or(DEFAULT_CID_CODE);
+        let hasher =
+            multihash::Code::try_from(code).map_err(|_| CidError::UnsupportedCode(code))?;
+        let bytes = self.to_ipld_bytes()?;
+        Ok(cid::Cid::new_v1(DagCborCodec.into(), hasher.digest(&bytes)))
+    }
+
+    /// Serialize the `ScalarStore` to IPLD and encode it as DAG-CBOR.
+    #[cfg(feature = "ipld")]
+    pub fn to_ipld_bytes(&self) -> Result<Vec<u8>, IpldError>
+    where
+        F: Serialize,
+    {
+        use libipld::{cbor::DagCborCodec, codec::Codec, serde::to_ipld};
+
+        let ipld = to_ipld(self.clone()).map_err(|e| IpldError::Encode(e.to_string()))?;
+        DagCborCodec
+            .encode(&ipld)
+            .map_err(|e| IpldError::Encode(e.to_string()))
+    }
+
+    /// Deserialize a `ScalarStore` from the output of `to_ipld_bytes`.
+    #[cfg(feature = "ipld")]
+    pub fn from_ipld_bytes(bytes: &[u8]) -> Result<Self, IpldError>
+    where
+        F: for<'de> Deserialize<'de>,
+    {
+        use libipld::{cbor::DagCborCodec, codec::Codec, serde::from_ipld, Ipld};
+
+        let ipld: Ipld = DagCborCodec
+            .decode(bytes)
+            .map_err(|e| IpldError::Decode(e.to_string()))?;
+        from_ipld(ipld).map_err(|e| IpldError::Decode(e.to_string()))
+    }
+
+    /// Encode the store as one DAG-CBOR block per expression and continuation, each paired with its CID, so that the
+    /// graph can be navigated and deduplicated by IPLD tooling. A block is a map holding the pointer under `ptr`, its
+    /// entry under `expr` or `cont` (null if opaque), and CID links to the blocks of its children in the store under
+    /// `children`. Children are encoded before their parents, so blocks are returned in topological order.
+    #[cfg(feature = "ipld")]
+    pub fn to_dag_blocks(&self) -> Result<Vec<(cid::Cid, Vec<u8>)>, DagError<F>>
+    where
+        F: Serialize,
+    {
+        let mut cids: BTreeMap<UPtr<F>, cid::Cid> = BTreeMap::new();
+        let mut blocks = Vec::new();
+        let roots = self
+            .scalar_map
+            .keys()
+            .map(uptr)
+            .chain(self.scalar_cont_map.keys().map(uptr));
+
+        for root in roots {
+            if cids.contains_key(&root) {
+                continue;
+            }
+            let mut on_path = BTreeSet::from([root]);
+            let mut path = vec![(root, self.dag_children(&root))];
+
+            while let Some((_, remaining)) = path.last_mut() {
+                if let Some(child) = remaining.pop() {
+                    if on_path.contains(&child) {
+                        return Err(DagError::Cycle(child));
+                    } else if !cids.contains_key(&child) {
+                        on_path.insert(child);
+                        path.push((child, self.dag_children(&child)));
+                    }
+                } else {
+                    let (ptr, _) = path.pop().unwrap();
+                    on_path.remove(&ptr);
+                    let block = self.dag_block(&ptr, &cids)?;
+                    cids.insert(ptr, block.0);
+                    blocks.push(block);
+                }
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Rebuild a store from blocks produced by `to_dag_blocks`, starting from the expression block `root` and following
+    /// the `children` links of each block. Blocks which are not reachable from `root` are ignored. Returns the store
+    /// along with the `ScalarPtr` of `root`, or `DagError::MissingBlock` if a reachable block is not among `blocks`.
+    #[cfg(feature = "ipld")]
+    pub fn from_dag_blocks(
+        blocks: impl IntoIterator<Item = (cid::Cid, Vec<u8>)>,
+        root: cid::Cid,
+    ) -> Result<(Self, ScalarPtr<F>), DagError<F>>
+    where
+        F: for<'de> Deserialize<'de>,
+    {
+        use libipld::{cbor::DagCborCodec, codec::Codec, serde::from_ipld, Ipld};
+        use multihash::MultihashDigest;
+
+        let blocks: BTreeMap<cid::Cid, Vec<u8>> = blocks.into_iter().collect();
+        let mut store = Self::default();
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![root];
+        let mut root_ptr = None;
+
+        while let Some(cid) = pending.pop() {
+            if !visited.insert(cid) {
+                continue;
+            }
+            let bytes = blocks.get(&cid).ok_or(DagError::MissingBlock(cid))?;
+            let hasher = multihash::Code::try_from(cid.hash().code())
+                .map_err(|_| DagError::InvalidBlock(cid))?;
+            if hasher.digest(bytes) != *cid.hash() {
+                return Err(DagError::InvalidBlock(cid));
+            }
+
+            let block: Ipld = DagCborCodec
+                .decode(bytes)
+                .map_err(|e| IpldError::Decode(e.to_string()))?;
+            let field = |key: &str| {
+                block
+                    .get(key)
+                    .ok()
+                    .cloned()
+                    .ok_or(DagError::InvalidBlock(cid))
+            };
+            let decode_error = |e: libipld::error::SerdeError| IpldError::Decode(e.to_string());
+
+            if let Ok(expr) = field("expr") {
+                let ptr: ScalarPtr<F> = from_ipld(field("ptr")?).map_err(decode_error)?;
+                store
+                    .scalar_map
+                    .insert(ptr, from_ipld(expr).map_err(decode_error)?);
+                if cid == root {
+                    root_ptr = Some(ptr);
+                }
+            } else {
+                let ptr: ScalarContPtr<F> = from_ipld(field("ptr")?).map_err(decode_error)?;
+                store
+                    .scalar_cont_map
+                    .insert(ptr, from_ipld(field("cont")?).map_err(decode_error)?);
+            }
+
+            match field("children")? {
+                Ipld::List(children) => {
+                    for child in children {
+                        match child {
+                            Ipld::Link(child) => pending.push(child),
+                            _ => return Err(DagError::InvalidBlock(cid)),
+                        }
+                    }
+                }
+                _ => return Err(DagError::InvalidBlock(cid)),
+            }
+        }
+
+        let root_ptr = root_ptr.ok_or(DagError::InvalidBlock(root))?;
+        Ok((store, root_ptr))
+    }
+
+    /// The children of the entry for `ptr` which are themselves in the store, in order.
+    #[cfg(feature = "ipld")]
+    fn dag_children(&self, ptr: &UPtr<F>) -> Vec<UPtr<F>> {
+        let children: Vec<UPtr<F>> = if ptr.is_cont() {
+            match self.get_cont(&ScalarContPtr::from_parts(ptr.0, ptr.1)) {
+                Some(cont) => {
+                    let (scalar_ptrs, scalar_cont_ptrs) = Self::child_scalar_cont_ptrs(cont);
+                    scalar_ptrs
+                        .iter()
+                        .map(uptr)
+                        .chain(scalar_cont_ptrs.iter().map(uptr))
+                        .collect()
+                }
+                None => Vec::new(),
+            }
+        } else {
+            match self.get_expr(&ScalarPtr::from_parts(ptr.0, ptr.1)) {
+                Some(expr) => {
+                    let mut children: Vec<UPtr<F>> = Self::child_scalar_ptrs(expr)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(uptr)
+                        .collect();
+                    if let ScalarExpression::Thunk(thunk) = expr {
+                        children.push(uptr(&thunk.continuation));
+                    }
+                    children
+                }
+                None => Vec::new(),
+            }
+        };
+        children
+            .into_iter()
+            .filter(|child| {
+                if child.is_cont() {
+                    self.scalar_cont_map
+                        .contains_key(&ScalarContPtr::from_parts(child.0, child.1))
+                } else {
+                    self.scalar_map
+                        .contains_key(&ScalarPtr::from_parts(child.0, child.1))
+                }
+            })
+            .collect()
+    }
+
+    /// Encode the block for `ptr`, whose children in the store must already have CIDs in `cids`.
+    #[cfg(feature = "ipld")]
+    fn dag_block(
+        &self,
+        ptr: &UPtr<F>,
+        cids: &BTreeMap<UPtr<F>, cid::Cid>,
+    ) -> Result<(cid::Cid, Vec<u8>), IpldError>
+    where
+        F: Serialize,
+    {
+        use libipld::{cbor::DagCborCodec, codec::Codec, serde::to_ipld, Ipld};
+        use multihash::MultihashDigest;
+
+        let encode_error = |e: libipld::error::SerdeError| IpldError::Encode(e.to_string());
+        let (ptr_ipld, key, entry_ipld) = if ptr.is_cont() {
+            let ptr = ScalarContPtr::from_parts(ptr.0, ptr.1);
+            (
+                to_ipld(ptr).map_err(encode_error)?,
+                "cont",
+                to_ipld(self.scalar_cont_map[&ptr]).map_err(encode_error)?,
+            )
+        } else {
+            let ptr = ScalarPtr::from_parts(ptr.0, ptr.1);
+            (
+                to_ipld(ptr).map_err(encode_error)?,
+                "expr",
+                to_ipld(self.scalar_map[&ptr].clone()).map_err(encode_error)?,
+            )
+        };
+        let children = self
+            .dag_children(ptr)
+            .iter()
+            .map(|child| Ipld::Link(cids[child]))
+            .collect();
+        let block = Ipld::Map(BTreeMap::from([
+            ("ptr".to_string(), ptr_ipld),
+            (key.to_string(), entry_ipld),
+            ("children".to_string(), Ipld::List(children)),
+        ]));
+
+        let bytes = DagCborCodec
+            .encode(&block)
+            .map_err(|e| IpldError::Encode(e.to_string()))?;
+        let cid = cid::Cid::new_v1(
+            DagCborCodec.into(),
+            multihash::Code::Blake3_256.digest(&bytes),
+        );
+        Ok((cid, bytes))
+    }
+
+    /// Convert the `ScalarStore` to human-readable JSON: an object with the `version` of the schema and objects
+    /// `exprs` and `conts`, mapping each pointer to its expression or continuation, or to null if it is opaque. Pointers
+    /// are rendered as the hex of their tag followed by the hex of their value, field elements as the fixed-width hex of
+    /// their `to_repr`, and expressions and continuations as objects tagged by `type`.
+    pub fn to_json(&self) -> serde_json::Value {
+        let exprs = self
+            .scalar_map
+            .iter()
+            .map(|(ptr, expr)| {
+                let expr = expr.as_ref().map(ScalarExpression::to_json);
+                (ptr_to_hex(ptr), expr.unwrap_or_default())
+            })
+            .collect::<serde_json::Map<_, _>>();
+        let conts = self
+            .scalar_cont_map
+            .iter()
+            .map(|(ptr, cont)| {
+                let cont = cont.map(ScalarContinuation::to_json);
+                (ptr_to_hex(ptr), cont.unwrap_or_default())
+            })
+            .collect::<serde_json::Map<_, _>>();
+        serde_json::json!({ "version": SCALAR_STORE_VERSION, "exprs": exprs, "conts": conts })
+    }
+
+    /// Convert the output of `to_json` back into a `ScalarStore`.
+    pub fn from_json(v: &serde_json::Value) -> Result<Self, InvalidJson> {
+        let version = v.get("version").and_then(serde_json::Value::as_u64);
+        if version != Some(SCALAR_STORE_VERSION.into()) {
+            return Err(InvalidJson(format!("unsupported version {:?}", version)));
+        }
+        let entries = |key| {
+            v.get(key)
+                .and_then(serde_json::Value::as_object)
+                .ok_or_else(|| InvalidJson(format!("missing {}", key)))
+        };
+
+        let mut store = Self::default();
+        for (ptr, expr) in entries("exprs")? {
+            let invalid = || InvalidJson(format!("invalid expression {}", ptr));
+            let scalar_ptr = ptr_from_hex(ptr).ok_or_else(invalid)?;
+            let expr = if expr.is_null() {
+                None
+            } else {
+                Some(ScalarExpression::from_json(expr).ok_or_else(invalid)?)
+            };
+            store.scalar_map.insert(scalar_ptr, expr);
+        }
+        for (ptr, cont) in entries("conts")? {
+            let invalid = || InvalidJson(format!("invalid continuation {}", ptr));
+            let scalar_cont_ptr = ptr_from_hex(ptr).ok_or_else(invalid)?;
+            let cont = if cont.is_null() {
+                None
+            } else {
+                Some(ScalarContinuation::from_json(cont).ok_or_else(invalid)?)
+            };
+            store.scalar_cont_map.insert(scalar_cont_ptr, cont);
+        }
+        Ok(store)
+    }
+
+    /// The entries of both maps, in `UPtr` order, each with the body which follows its header in `ser_f` output, or the
+    /// error encoding it.
+    #[allow(clippy::type_complexity, clippy::let_and_return)]
+    fn ser_entries(
+        &self,
+    ) -> impl Iterator<Item = (UPtr<F>, Result<Vec<F>, ScalarStoreError<F>>)> + '_ {
+        let exprs = self.scalar_map.iter().map(|(ptr, expr)| {
+            let body = expr
+                .as_ref()
+                .map(|expr| expr.ser_f())
+                .transpose()
+                .map(Self::ser_entry);
+            (uptr(ptr), body)
+        });
+        let conts = self.scalar_cont_map.iter().map(|(ptr, cont)| {
+            let body = Self::ser_entry(cont.as_ref().map(|cont| cont.ser_f()));
+            (uptr(ptr), Ok(body))
+        });
+        let entries = exprs.merge_by(conts, |(a, _), (b, _)| a <= b);
+        // Both maps are ordered by `to_repr` bytes, which only agrees with `UPtr` order without `canonical`.
+        #[cfg(feature = "canonical")]
+        let entries = entries.sorted_by(|(a, _), (b, _)| a.cmp(b));
+        entries
+    }
+
+    fn ser_entry(payload: Option<Vec<F>>) -> Vec<F> {
+        match payload {
+            Some(payload) => {
+                let mut body = Vec::with_capacity(payload.len() + 2);
+                body.push(F::from(payload.len() as u64 + 1));
+                body.push(F::one());
+                body.extend(payload);
+                body
+            }
+            None => vec![F::one(), F::zero()],
+        }
+    }
+
+    /// Deserialize a `ScalarStore` from the flat vector of field elements produced by `ser_f`. Entries whose tag is
+    /// neither a `Tag` nor a `ContTag`, as written by a newer version, are skipped.
+    pub fn de_f(input: &[F]) -> Result<Self, DeserializeError> {
+        let mut store = Self::default();
+        let mut reader = FieldReader::new(input);
+
+        while !reader.is_empty() {
+            let start = reader.position();
+            let tag = reader.read()?;
+            let value = reader.read()?;
+            let len = reader
+                .read()?
+                .to_u64()
+                .and_then(|len| usize::try_from(len).ok())
+                .ok_or(DeserializeError::InvalidPayload(start))?;
+            let body = &mut reader.sub_reader(len)?;
+
+            if Tag::from_field(tag).is_none() && ContTag::from_field(tag).is_none() {
+                continue;
+            }
+            let known = body.read()?;
+            let known = if known == F::zero() {
+                false
+            } else if known == F::one() {
+                true
+            } else {
+                return Err(DeserializeError::InvalidPayload(start));
+            };
+
+            if let Some(tag) = Tag::from_field(tag) {
+                let expr = if known {
+                    Some(ScalarExpression::de_f(tag, body)?)
+                } else {
+                    None
+                };
+                store
+                    .scalar_map
+                    .insert(ScalarPtr::from_parts(tag.as_field(), value), expr);
+            } else if let Some(tag) = ContTag::from_field(tag) {
+                let cont = if known {
+                    Some(ScalarContinuation::de_f(tag, body)?)
+                } else {
+                    None
+                };
+                store
+                    .scalar_cont_map
+                    .insert(ScalarContPtr::from_parts(tag.as_field(), value), cont);
+            }
+            if !body.is_empty() {
+                return Err(DeserializeError::InvalidPayload(start));
+            }
+        }
+        Ok(store)
+    }
+}
+
+/// Builds a `ScalarStore` directly from scalar pointers and their expressions and continuations, without a `Store`.
+/// This is useful when the hashes were computed elsewhere.
+#[derive(Debug, Default, Clone)]
+pub struct ScalarStoreBuilder<F: LurkField> {
+    store: ScalarStore<F>,
+}
+
+impl<F: LurkField> ScalarStoreBuilder<F> {
+    pub fn new() -> Self {
+        Self {
+            store: ScalarStore::default(),
+        }
+    }
+
+    /// Insert `expr` as the expression `ptr` represents, replacing any previous entry.
+    pub fn insert_expr(&mut self, ptr: ScalarPtr<F>, expr: ScalarExpression<F>) -> &mut Self {
+        self.store.scalar_map.insert(ptr, Some(expr));
+        self
+    }
+
+    /// Insert `ptr` as opaque, unless its expression has already been inserted.
+    pub fn insert_opaque(&mut self, ptr: ScalarPtr<F>) -> &mut Self {
+        self.store.scalar_map.entry(ptr).or_insert(None);
+        self
+    }
+
+    /// Insert `cont` as the continuation `ptr` represents, replacing any previous entry.
+    pub fn insert_cont(&mut self, ptr: ScalarContPtr<F>, cont: ScalarContinuation<F>) -> &mut Self {
+        self.store.scalar_cont_map.insert(ptr, Some(cont));
+        self
+    }
+
+    /// Return the `ScalarStore`, or all the children which are referenced but were never inserted.
+    pub fn build(self) -> Result<ScalarStore<F>, MissingChildren<F>> {
+        let missing = self.store.missing_children();
+        if missing.is_empty() {
+            Ok(self.store)
+        } else {
+            Err(MissingChildren(missing))
+        }
+    }
+}
+
+/// Returned by `ScalarStoreBuilder::build` when referenced children are absent, in order and without duplicates.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Child pointers missing: {0:?}")]
+pub struct MissingChildren<F: LurkField>(pub Vec<UPtr<F>>);
+
+/// Returned by `ScalarStore::health_check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HealthWarning<F: LurkField> {
+    /// The store has no entries.
+    Empty,
+    /// Every entry in the store is opaque.
+    FullyOpaque,
+    /// Children referenced by the store but absent from it, in order and without duplicates.
+    DanglingChildren(Vec<UPtr<F>>),
+}
+
+/// Errors which can occur in this module: when converting a `ScalarStore` into a `Store`, when decoding, and when
+/// packing strings and chars into field elements. Variants concerning a pointer carry it as a `UPtr`, since it may refer
+/// to either an expression or a continuation.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ScalarStoreError<F: LurkField> {
+    #[error("Scalar pointer not present in map: {0:?}")]
+    MissingPtr(UPtr<F>),
+    #[error("Child pointer missing: {0:?}")]
+    MissingChild(UPtr<F>),
+    #[error("Failed to intern scalar pointer: {0:?}")]
+    InternFailed(UPtr<F>),
+    #[error("ScalarStore has pending pointers and must be finalized")]
+    Pending,
+    #[error("Store scalar cache has not been hydrated")]
+    NotHydrated,
+    #[error("No scalar hash in the store for the {0}")]
+    Unhashed(&'static str),
+    #[error("Unknown tag for scalar pointer: {0:?}")]
+    UnknownTag(UPtr<F>),
+    #[error("Bytes are not the repr of a canonical field element")]
+    NonCanonicalField,
+    #[error("Invalid UTF-8 in string")]
+    InvalidUtf8,
+    #[error("String of {0} bytes does not fit in a field element")]
+    StringTooLong(usize),
+    #[error("Field element does not encode a string: {0:?}")]
+    InvalidString(F),
+    #[error("Field element does not encode a char: {0:?}")]
+    InvalidChar(F),
+    #[error(transparent)]
+    Deserialize(#[from] DeserializeError),
+    #[error("Cycle through scalar pointer: {0:?}")]
+    Cycle(UPtr<F>),
+}
+
+/// Returned by `ScalarStore::to_store_verified`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError<F: LurkField> {
+    #[error(transparent)]
+    Store(#[from] ScalarStoreError<F>),
+    #[error("Pointer {claimed:?} does not match the hash of its contents, {actual:?}")]
+    HashMismatch { claimed: UPtr<F>, actual: UPtr<F> },
+}
+
+/// Returned by `ScalarStore::merge` when both stores hold different values for the same pointer.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Conflicting values for pointer: {0:?}")]
+pub struct MergeConflict<F: LurkField>(pub UPtr<F>);
+
+/// Returned by `ScalarStore::status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerStatus<'a, F: LurkField> {
+    /// The pointer is not in the store.
+    Unknown,
+    /// The pointer is in the store, but its expression is not.
+    Opaque,
+    /// The pointer and its expression are in the store.
+    Known(&'a ScalarExpression<F>),
+}
+
+/// Returned by `ScalarStore::validate` for an entry holding a non-canonical field element.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Non-canonical field element in entry: {0:?}")]
+pub struct ValidationError<F: LurkField>(pub UPtr<F>);
+
+/// Returned by `ScalarStore::from_json` for input which is not the output of `ScalarStore::to_json`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid JSON ScalarStore: {0}")]
+pub struct InvalidJson(pub String);
+
+/// Returned by `ScalarStore::topo_order` when the graph has a cycle.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Cycle in expression graph: {0:?}")]
+pub struct Cycle<F: LurkField>(pub Vec<ScalarPtr<F>>);
+
+/// The roots of one step of a trace compressed by `ScalarStore::compress_trace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RootRefs<F: LurkField> {
+    pub expr: ScalarPtr<F>,
+    pub cont: ScalarContPtr<F>,
+}
+
+/// Returned by `ScalarStore::list_elements` when a list does not end in `nil`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Improper list with tail: {0:?}")]
+pub struct ImproperList<F: LurkField>(pub ScalarPtr<F>);
+
+/// The result of `ScalarStore::dedup_stats`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DedupStats {
+    /// The number of times a `ScalarPtr` was reached, counting repeats.
+    pub visits: usize,
+    /// The number of distinct `ScalarPtr`s newly added.
+    pub unique: usize,
+}
+
+impl DedupStats {
+    /// The number of visits which found their `ScalarPtr` already present.
+    pub fn hits(&self) -> usize {
+        self.visits - self.unique
+    }
+}
+
+/// Approximate per-node constraint costs used by `ScalarStore::estimate_constraints_with`. Each field is the cost of
+/// one expression of that kind, except `per_char`, which is added for every character of a `Sym` or `Str`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConstraintWeights {
+    pub nil: usize,
+    pub cons: usize,
+    pub comm: usize,
+    pub sym: usize,
+    pub fun: usize,
+    pub num: usize,
+    pub str: usize,
+    pub char: usize,
+    pub u64: usize,
+    pub thunk: usize,
+    pub per_char: usize,
+}
+
+/// Coarse defaults for `ConstraintWeights`, counting roughly 300 constraints per Poseidon hash a node costs the
+/// circuit, and a few for allocating an immediate.
+pub const DEFAULT_CONSTRAINT_WEIGHTS: ConstraintWeights = ConstraintWeights {
+    nil: 4,
+    cons: 300,
+    comm: 300,
+    sym: 300,
+    fun: 350,
+    num: 4,
+    str: 300,
+    char: 4,
+    u64: 70,
+    thunk: 300,
+    per_char: 300,
+};
+
+impl Default for ConstraintWeights {
+    fn default() -> Self {
+        DEFAULT_CONSTRAINT_WEIGHTS
+    }
+}
+
+impl ConstraintWeights {
+    /// The estimated cost of `expr` alone, not counting its children.
+    pub fn weight<F: LurkField>(&self, expr: &ScalarExpression<F>) -> usize {
+        match expr {
+            ScalarExpression::Nil => self.nil,
+            ScalarExpression::Cons(..) => self.cons,
+            ScalarExpression::Comm(..) => self.comm,
+            ScalarExpression::Sym(s) => self.sym + self.per_char * s.chars().count(),
+            ScalarExpression::Fun { .. } => self.fun,
+            ScalarExpression::Num(_) => self.num,
+            ScalarExpression::Str(s) => self.str + self.per_char * s.chars().count(),
+            ScalarExpression::Char(_) => self.char,
+            ScalarExpression::UInt(_) => self.u64,
+            ScalarExpression::Thunk(_) => self.thunk,
+        }
+    }
+}
+
+/// The result of `ScalarStore::diff`. Pointers from both maps are reported together as `UPtr`s, each list in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScalarStoreDiff<F: LurkField> {
+    pub only_in_self: Vec<UPtr<F>>,
+    pub only_in_other: Vec<UPtr<F>>,
+    pub differing: Vec<UPtr<F>>,
+}
+
+impl<F: LurkField> Default for ScalarStoreDiff<F> {
+    fn default() -> Self {
+        Self {
+            only_in_self: Vec::new(),
+            only_in_other: Vec::new(),
+            differing: Vec::new(),
+        }
+    }
+}
+
+impl<F: LurkField> ScalarStoreDiff<F> {
+    /// True if the compared stores are identical.
+    pub fn is_empty(&self) -> bool {
+        self.only_in_self.is_empty() && self.only_in_other.is_empty() && self.differing.is_empty()
+    }
+}
+
+fn diff_maps<F: LurkField, P: ScalarPointer<F> + Ord, V: PartialEq>(
+    a: &BTreeMap<P, V>,
+    b: &BTreeMap<P, V>,
+    diff: &mut ScalarStoreDiff<F>,
+) {
+    for (ptr, value) in a {
+        match b.get(ptr) {
+            None => diff.only_in_self.push(uptr(ptr)),
+            Some(other_value) if other_value != value => diff.differing.push(uptr(ptr)),
+            Some(_) => (),
+        }
+    }
+    for ptr in b.keys() {
+        if !a.contains_key(ptr) {
+            diff.only_in_other.push(uptr(ptr));
+        }
+    }
+}
+
+/// Read the `to_repr` bytes of a single field element from `r`, or `None` if `r` is already at the end of input.
+fn read_field<F: LurkField, R: Read>(r: &mut R) -> io::Result<Option<F>> {
+    let mut repr = F::Repr::default();
+    let buf = repr.as_mut();
+    let mut filled = 0;
+    while filled < buf.len() {
+        match r.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Option::from(F::from_repr(repr))
+        .map(Some)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid field element"))
+}
+
+/// Pass through the result of hashing a pointer, unless it failed because `store.scalar_cache` has not been hydrated, in
+/// which case the pointer may have been skipped rather than being unhashable.
+fn hashed<F: LurkField, P>(
+    store: &Store<F>,
+    ptr: Option<P>,
+) -> Result<Option<P>, ScalarStoreError<F>> {
+    if ptr.is_none() && !store.is_hydrated() {
+        Err(ScalarStoreError::NotHydrated)
+    } else {
+        Ok(ptr)
+    }
+}
+
+fn uptr<F: LurkField, P: ScalarPointer<F>>(ptr: &P) -> UPtr<F> {
+    UPtr::from_parts(*ptr.tag(), *ptr.value())
+}
+
+/// Errors which can occur when deserializing a `ScalarStore` from field elements with `ScalarStore::de_f`, or from
+/// bytes with `ScalarStore::from_bytes`. Positions are indices of field elements in the input.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DeserializeError {
+    #[error("Unexpected end of input at position {0}")]
+    UnexpectedEnd(usize),
+    #[error("Invalid payload at position {0}")]
+    InvalidPayload(usize),
+    #[error("Invalid field element at position {0}")]
+    InvalidFieldElement(usize),
+    #[error("Input does not start with the expected magic bytes")]
+    InvalidMagic,
+    #[error("Unsupported format version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Input was serialized with a different field, with codec {0:#x}")]
+    FieldMismatch(u64),
+}
+
+/// Errors which can occur when reading a `ScalarStore` from a file with `ScalarStore::read_fr_file`.
+#[derive(Error, Debug)]
+pub enum FrFileError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Failed to decode at byte offset {offset}: {error}")]
+    Decode {
+        offset: usize,
+        error: DeserializeError,
+    },
+}
+
+/// Errors which can occur when computing the CID of a `ScalarStore` with `ScalarStore::to_cid`.
+#[cfg(feature = "ipld")]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CidError {
+    #[error(transparent)]
+    Ipld(#[from] IpldError),
+    #[error("Unsupported multihash code: {0:#x}")]
+    UnsupportedCode(u64),
+}
+
+/// Errors which can occur when encoding a `ScalarStore` with `ScalarStore::to_ipld_bytes`, or decoding one with
+/// `ScalarStore::from_ipld_bytes`.
+#[cfg(feature = "ipld")]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum IpldError {
+    #[error("Failed to encode ScalarStore as DAG-CBOR: {0}")]
+    Encode(String),
+    #[error("Failed to decode ScalarStore from DAG-CBOR: {0}")]
+    Decode(String),
+}
+
+/// Errors which can occur when encoding a `ScalarStore` as blocks with `ScalarStore::to_dag_blocks`, or rebuilding one
+/// with `ScalarStore::from_dag_blocks`.
+#[cfg(feature = "ipld")]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DagError<F: LurkField> {
+    #[error(transparent)]
+    Ipld(#[from] IpldError),
+    #[error("Cycle in expression graph through {0:?}")]
+    Cycle(UPtr<F>),
+    #[error("Missing block {0}")]
+    MissingBlock(cid::Cid),
+    #[error("Invalid block {0}")]
+    InvalidBlock(cid::Cid),
+}
+
+/// The multihash code used by `ScalarStore::to_cid` when none is given: BLAKE3-256.
+#[cfg(feature = "ipld")]
+pub const DEFAULT_CID_CODE: u64 = 0x1e;
+
+/// The magic bytes which begin the output of `ScalarStore::to_bytes`.
+const BYTES_MAGIC: [u8; 4] = *b"LRKS";
+/// The version of the format produced by `ScalarStore::to_bytes`, to be bumped whenever it changes.
+const BYTES_VERSION: u8 = 2;
+
+/// A cursor over a slice of field elements, used to decode `ser_f` output. Positions are always reported relative to the
+/// start of the whole input, including for readers created by `sub_reader`.
+struct FieldReader<'a, F: LurkField> {
+    input: &'a [F],
+    position: usize,
+    end: usize,
+}
+
+impl<'a, F: LurkField> FieldReader<'a, F> {
+    fn new(input: &'a [F]) -> Self {
+        Self {
+            input,
+            position: 0,
+            end: input.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.end
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn read(&mut self) -> Result<F, DeserializeError> {
+        if self.is_empty() {
+            return Err(DeserializeError::UnexpectedEnd(self.position));
+        }
+        let f = self.input[self.position];
+        self.position += 1;
+        Ok(f)
+    }
+
+    fn read_n(&mut self, n: usize) -> Result<&'a [F], DeserializeError> {
+        let end = match self.position.checked_add(n) {
+            Some(end) if end <= self.end => end,
+            _ => return Err(DeserializeError::UnexpectedEnd(self.end)),
+        };
+        let res = &self.input[self.position..end];
+        self.position = end;
+        Ok(res)
+    }
+
+    /// Split off a reader over the next `n` elements, advancing past them.
+    fn sub_reader(&mut self, n: usize) -> Result<Self, DeserializeError> {
+        let position = self.position;
+        self.read_n(n)?;
+        Ok(Self {
+            input: self.input,
+            position,
+            end: self.position,
+        })
+    }
+
+    fn read_scalar_ptr(&mut self) -> Result<ScalarPtr<F>, DeserializeError> {
+        let tag = self.read()?;
+        let value = self.read()?;
+        Ok(ScalarPtr::from_parts(tag, value))
+    }
+
+    fn read_scalar_cont_ptr(&mut self) -> Result<ScalarContPtr<F>, DeserializeError> {
+        let tag = self.read()?;
+        let value = self.read()?;
+        Ok(ScalarContPtr::from_parts(tag, value))
+    }
+}
+
+/// `UPtr` unifies `ScalarPtr` and `ScalarContPtr`, so expressions and continuations can be ordered together when
+/// serializing a `ScalarStore` to field elements. Expression and continuation tags occupy disjoint ranges, so the tag
+/// alone determines which kind of pointer a `UPtr` represents.
+///
+/// `UPtr`s are ordered by the bytes of `to_repr` of the tag, then of the value. This is the order in which `ser_f`
+/// emits entries. Since the byte order of `to_repr` is defined by the field, so is this ordering. With the `canonical`
+/// feature, `UPtr`s are instead ordered by `canonical_bytes`, numerically, which is the same for every field. The cost
+/// is a bit decomposition per comparison and an extra sort in `ser_f`, and `ser_f` output, and so `digest`, differs
+/// from that of a build without the feature, although each decodes the other's output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UPtr<F: LurkField>(F, F);
+
+impl<F: LurkField> Copy for UPtr<F> {}
+
+impl<F: LurkField> UPtr<F> {
+    pub fn new(tag: F, value: F) -> Self {
+        UPtr(tag, value)
+    }
+
+    pub fn tag(&self) -> &F {
+        &self.0
+    }
+
+    pub fn value(&self) -> &F {
+        &self.1
+    }
+
+    /// Returns true if the tag is a continuation tag.
+    pub fn is_cont(&self) -> bool {
+        ContTag::from_field(self.0).is_some()
+    }
+}
+
+impl<F: LurkField> From<ScalarPtr<F>> for UPtr<F> {
+    fn from(ptr: ScalarPtr<F>) -> Self {
+        UPtr(*ptr.tag(), *ptr.value())
+    }
+}
+
+impl<F: LurkField> From<ScalarContPtr<F>> for UPtr<F> {
+    fn from(ptr: ScalarContPtr<F>) -> Self {
+        UPtr(*ptr.tag(), *ptr.value())
+    }
+}
+
+impl<F: LurkField> PartialOrd for UPtr<F> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<F: LurkField> Ord for UPtr<F> {
+    #[cfg(not(feature = "canonical"))]
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        (self.0.to_repr().as_ref(), self.1.to_repr().as_ref())
+            .cmp(&(other.0.to_repr().as_ref(), other.1.to_repr().as_ref()))
+    }
+
+    #[cfg(feature = "canonical")]
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        (canonical_bytes(&self.0), canonical_bytes(&self.1))
+            .cmp(&(canonical_bytes(&other.0), canonical_bytes(&other.1)))
+    }
+}
+
+#[allow(clippy::derive_hash_xor_eq)]
+impl<F: LurkField> Hash for UPtr<F> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_repr().as_ref().hash(state);
+        self.1.to_repr().as_ref().hash(state);
+    }
+}
+
+impl<F: LurkField> ScalarPointer<F> for UPtr<F> {
+    fn from_parts(tag: F, value: F) -> Self {
+        UPtr(tag, value)
+    }
+
+    fn tag(&self) -> &F {
+        &self.0
+    }
+
+    fn value(&self) -> &F {
+        &self.1
+    }
+}
+
+/// The number of field elements which follow the header of each expression in `ser_f` output. `Sym` and `Str` are
+/// length-prefixed: their single element is the string's length in bytes, and is followed by the string's chunks.
+/// This and `cont_tag_arity` define the frame sizes of the format, for `de_f` and external readers alike.
+pub fn tag_arity(tag: Tag) -> usize {
+    match tag {
+        Tag::Nil => 0,
+        Tag::Cons => 4,
+        Tag::Sym => 1,
+        Tag::Fun => 6,
+        Tag::Num => 1,
+        Tag::Thunk => 4,
+        Tag::Str => 1,
+        Tag::Char => 1,
+        Tag::Comm => 3,
+        Tag::U64 => 1,
+    }
+}
+
+/// The number of field elements which follow the header of each continuation in `ser_f` output.
+pub fn cont_tag_arity(tag: ContTag) -> usize {
+    match tag {
+        ContTag::Outermost => 0,
+        ContTag::Call0 => 4,
+        ContTag::Call => 6,
+        ContTag::Call2 => 6,
+        ContTag::Tail => 4,
+        ContTag::Error => 0,
+        ContTag::Lookup => 4,
+        ContTag::Unop => 3,
+        ContTag::Binop => 7,
+        ContTag::Binop2 => 5,
+        ContTag::If => 4,
+        ContTag::Let => 8,
+        ContTag::LetRec => 8,
+        ContTag::Dummy => 0,
+        ContTag::Terminal => 0,
+        ContTag::Emit => 2,
+    }
+}
+
+/// The shortest run of equal field elements which `rle_encode` encodes as a run rather than as literals. A run block
+/// takes two elements, so shorter runs do not shrink.
+const RLE_MIN_RUN: usize = 3;
+
+/// Run-length encode `input` as a sequence of blocks, each starting with a header `h`. If `h` is `2n`, it is followed by
+/// `n` literal elements; if `h` is `2n + 1`, it is followed by a single element, which is repeated `n` times. `n` is
+/// never zero, so the low bit of each header unambiguously tells a literal block from a run.
+fn rle_encode<F: LurkField>(input: &[F]) -> Vec<F> {
+    let mut res = Vec::new();
+    let mut literals: &[F] = &[];
+    let mut i = 0;
+    while i < input.len() {
+        let run = input[i..].iter().take_while(|f| **f == input[i]).count();
+        if run >= RLE_MIN_RUN {
+            if !literals.is_empty() {
+                res.push(F::from(2 * literals.len() as u64));
+                res.extend_from_slice(literals);
+            }
+            res.push(F::from(2 * run as u64 + 1));
+            res.push(input[i]);
+            i += run;
+            literals = &input[i..i];
+        } else {
+            i += run;
+            literals = &input[i - literals.len() - run..i];
+        }
+    }
+    if !literals.is_empty() {
+        res.push(F::from(2 * literals.len() as u64));
+        res.extend_from_slice(literals);
+    }
+    res
+}
+
+/// Decode the output of `rle_encode`, failing on a block which would take the output past `max_len` elements. Errors
+/// report the position in `input` of the offending block.
+fn rle_decode<F: LurkField>(input: &[F], max_len: usize) -> Result<Vec<F>, DeserializeError> {
+    let mut res = Vec::new();
+    let mut reader = FieldReader::new(input);
+    while !reader.is_empty() {
+        let start = reader.position();
+        let header = reader
+            .read()?
+            .to_u64()
+            .and_then(|h| usize::try_from(h).ok())
+            .ok_or(DeserializeError::InvalidPayload(start))?;
+        let n = header / 2;
+        if n == 0 || n > max_len - res.len() {
+            return Err(DeserializeError::InvalidPayload(start));
+        }
+        if header % 2 == 0 {
+            res.extend_from_slice(reader.read_n(n)?);
+        } else {
+            let f = reader.read()?;
+            res.extend(std::iter::repeat(f).take(n));
+        }
+    }
+    Ok(res)
+}
+
+/// The number of bytes of a string packed into each field element.
+fn string_chunk_len<F: LurkField>() -> usize {
+    (F::CAPACITY / 8) as usize
+}
+
+/// Pack a string of at most `F::CAPACITY / 8` bytes into a single field element, placing its UTF-8 bytes at the start
+/// of the element's repr.
+pub fn small_string_to_f<F: LurkField>(s: &str) -> Result<F, ScalarStoreError<F>> {
+    if s.len() > string_chunk_len::<F>() {
+        return Err(ScalarStoreError::StringTooLong(s.len()));
+    }
+    small_bytes_to_f(s.as_bytes()).ok_or(ScalarStoreError::NonCanonicalField)
+}
+
+/// Unpack a string packed by `small_string_to_f`, dropping the zero padding which follows it. Since the padding is
+/// indistinguishable from trailing NUL characters, those are not preserved. Fail if `f` has bytes beyond
+/// `F::CAPACITY / 8`, or if its bytes are not valid UTF-8.
+pub fn small_string_from_f<F: LurkField>(f: F) -> Result<String, ScalarStoreError<F>> {
+    let mut bytes = small_bytes_from_f(f).ok_or(ScalarStoreError::InvalidString(f))?;
+    let len = bytes.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+    bytes.truncate(len);
+    String::from_utf8(bytes).map_err(|_| ScalarStoreError::InvalidUtf8)
+}
+
+fn small_bytes_to_f<F: LurkField>(bytes: &[u8]) -> Option<F> {
+    if bytes.len() > string_chunk_len::<F>() {
+        return None;
+    }
+    let mut repr = F::default().to_repr();
+    repr.as_mut()[..bytes.len()].copy_from_slice(bytes);
+    F::from_repr(repr).into()
+}
+
+fn small_bytes_from_f<F: LurkField>(f: F) -> Option<Vec<u8>> {
+    let repr = f.to_repr();
+    let (bytes, padding) = repr.as_ref().split_at(string_chunk_len::<F>());
+    if padding.iter().any(|b| *b != 0) {
+        return None;
+    }
+    Some(bytes.to_vec())
+}
+
+/// Encode a char as a field element, placing the little-endian bytes of its code point at the start of the element's
+/// repr. This is the field element whose value is the code point, as `Store` uses for the value of a `Char`'s
+/// `ScalarPtr`, for every field whose repr is little-endian.
+pub fn char_to_f<F: LurkField>(c: char) -> Result<F, ScalarStoreError<F>> {
+    let mut repr = F::default().to_repr();
+    repr.as_mut()[..4].copy_from_slice(&u32::from(c).to_le_bytes());
+    Option::from(F::from_repr(repr)).ok_or(ScalarStoreError::NonCanonicalField)
+}
+
+/// Decode a char encoded by `char_to_f`. Fail if `f` has nonzero bytes beyond the first four of its repr, or if they
+/// are not a Unicode scalar value, such as a surrogate or a value above `char::MAX`.
+pub fn char_from_f<F: LurkField>(f: F) -> Result<char, ScalarStoreError<F>> {
+    let repr = f.to_repr();
+    let (code_point, rest) = repr.as_ref().split_at(4);
+    if rest.iter().any(|b| *b != 0) {
+        return Err(ScalarStoreError::InvalidChar(f));
+    }
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(code_point);
+    char::from_u32(u32::from_le_bytes(bytes)).ok_or(ScalarStoreError::InvalidChar(f))
+}
+
+/// The number of field elements which hold the bytes of a string of `len` bytes.
+fn string_chunk_count<F: LurkField>(len: usize) -> usize {
+    let chunk_len = string_chunk_len::<F>();
+    len / chunk_len + usize::from(len % chunk_len != 0)
+}
+
+/// Serialize a string as its length in bytes, followed by its bytes packed `F::CAPACITY / 8` at a time into field
+/// elements.
+fn ser_string<F: LurkField>(s: &str) -> Vec<F> {
+    let bytes = s.as_bytes();
+    let mut res = Vec::with_capacity(1 + bytes.len() / string_chunk_len::<F>() + 1);
+    res.push(F::from(bytes.len() as u64));
+    for chunk in bytes.chunks(string_chunk_len::<F>()) {
+        res.push(small_bytes_to_f(chunk).expect("chunk fits in a field element"));
+    }
+    res
+}
+
+fn de_string<F: LurkField>(
+    fields: &mut FieldReader<F>,
+    reader: &mut FieldReader<F>,
+) -> Result<String, DeserializeError> {
+    let start = fields.position();
+    let len = fields
+        .read()?
+        .to_u64()
+        .ok_or(DeserializeError::InvalidPayload(start))? as usize;
+    let chunk_len = string_chunk_len::<F>();
+    let chunks = reader.read_n(string_chunk_count::<F>(len))?;
+
+    let mut bytes = Vec::with_capacity(len + chunk_len);
+    for chunk in chunks {
+        bytes.extend(small_bytes_from_f(*chunk).ok_or(DeserializeError::InvalidPayload(start))?);
+    }
+    if bytes[len..].iter().any(|b| *b != 0) {
+        return Err(DeserializeError::InvalidPayload(start));
+    }
+    bytes.truncate(len);
+    String::from_utf8(bytes).map_err(|_| DeserializeError::InvalidPayload(start))
+}
+
+impl<'a, F: LurkField> ScalarExpression<F> {
+    fn from_ptr(store: &Store<F>, ptr: &Ptr<F>) -> Option<Self> {
+        // The store knows only the hash of an opaque pointer.
+        if ptr.is_opaque() {
+            return None;
+        }
+        match ptr.tag() {
+            Tag::Nil => Some(ScalarExpression::Nil),
+            Tag::Cons => store.fetch_cons(ptr).and_then(|(car, cdr)| {
+                store.get_expr_hash(car).and_then(|car| {
+                    store
+                        .get_expr_hash(cdr)
+                        .map(|cdr| ScalarExpression::Cons(car, cdr))
+                })
+            }),
+            Tag::Comm => store.fetch_comm(ptr).and_then(|(secret, payload)| {
+                store
+                    .get_expr_hash(payload)
+                    .map(|payload| ScalarExpression::Comm(secret.0, payload))
+            }),
+            Tag::Sym => store
+                .fetch_sym(ptr)
+                .map(|str| ScalarExpression::Sym(str.into())),
+            Tag::Fun => store.fetch_fun(ptr).and_then(|(arg, body, closed_env)| {
+                store.get_expr_hash(arg).and_then(|arg| {
+                    store.get_expr_hash(body).and_then(|body| {
+                        store
+                            .get_expr_hash(closed_env)
+                            .map(|closed_env| ScalarExpression::Fun {
+                                arg,
+                                body,
+                                closed_env,
+                            })
+                    })
+                })
+            }),
+            // `Num::U64` and `Num::Scalar` hash alike, and `Store::intern_num` represents any scalar which fits in
+            // a u64 as `Num::U64`, so the distinction is recovered when converting back with `to_store`.
+            Tag::Num => store.fetch_num(ptr).map(|num| match num {
+                Num::U64(x) => ScalarExpression::Num((*x).into()),
+                Num::Scalar(x) => ScalarExpression::Num(*x),
+            }),
+
+            Tag::Str => store
+                .fetch_str(ptr)
+                .map(|str| ScalarExpression::Str(str.to_string())),
+            Tag::Char => store.fetch_char(ptr).map(ScalarExpression::Char),
+            Tag::U64 => store.fetch_uint(ptr).map(ScalarExpression::UInt),
+            Tag::Thunk => store.fetch_scalar_thunk(ptr).map(|(value, continuation)| {
+                ScalarExpression::Thunk(ScalarThunk {
+                    value,
+                    continuation,
+                })
+            }),
+        }
+    }
+
+    /// The expression which `ptr` represents, if it is determined by `ptr` alone, as for `ScalarStore::canonicalize`.
+    fn from_immediate(ptr: &ScalarPtr<F>) -> Option<Self> {
+        let value = *ptr.value();
+        match Tag::from_field(*ptr.tag())? {
+            Tag::Num => Some(ScalarExpression::Num(value)),
+            Tag::Char => char_from_f(value).ok().map(ScalarExpression::Char),
+            Tag::U64 => value.to_u64().map(|x| ScalarExpression::UInt(UInt::U64(x))),
+            Tag::Str if value == F::zero() => Some(ScalarExpression::Str(String::new())),
+            Tag::Sym if value == F::zero() => Some(ScalarExpression::Sym(String::new())),
+            _ => None,
+        }
+    }
+
+    /// The payload of this expression in `ser_f` output, which follows the header of its `ScalarPtr`. This fails only if a
+    /// `Char` cannot be encoded in `F`.
+    fn ser_f(&self) -> Result<Vec<F>, ScalarStoreError<F>> {
+        Ok(match self {
+            ScalarExpression::Nil => vec![],
+            ScalarExpression::Cons(car, cdr) => {
+                vec![*car.tag(), *car.value(), *cdr.tag(), *cdr.value()]
+            }
+            ScalarExpression::Comm(secret, payload) => {
+                vec![*secret, *payload.tag(), *payload.value()]
+            }
+            ScalarExpression::Sym(s) => ser_string(s),
+            ScalarExpression::Fun {
+                arg,
+                body,
+                closed_env,
+            } => vec![
+                *arg.tag(),
+                *arg.value(),
+                *body.tag(),
+                *body.value(),
+                *closed_env.tag(),
+                *closed_env.value(),
+            ],
+            ScalarExpression::Num(x) => vec![*x],
+            ScalarExpression::Str(s) => ser_string(s),
+            ScalarExpression::Thunk(thunk) => vec![
+                *thunk.value.tag(),
+                *thunk.value.value(),
+                *thunk.continuation.tag(),
+                *thunk.continuation.value(),
+            ],
+            ScalarExpression::Char(c) => vec![char_to_f(*c)?],
+            ScalarExpression::UInt(UInt::U64(x)) => vec![F::from(*x)],
+        })
+    }
+
+    /// The length of the payload written by `ser_f`.
+    fn ser_len(&self) -> usize {
+        match self {
+            ScalarExpression::Nil => tag_arity(Tag::Nil),
+            ScalarExpression::Cons(..) => tag_arity(Tag::Cons),
+            ScalarExpression::Comm(..) => tag_arity(Tag::Comm),
+            ScalarExpression::Sym(s) | ScalarExpression::Str(s) => {
+                1 + string_chunk_count::<F>(s.len())
+            }
+            ScalarExpression::Fun { .. } => tag_arity(Tag::Fun),
+            ScalarExpression::Num(_) => tag_arity(Tag::Num),
+            ScalarExpression::Thunk(_) => tag_arity(Tag::Thunk),
+            ScalarExpression::Char(_) => tag_arity(Tag::Char),
+            ScalarExpression::UInt(UInt::U64(_)) => tag_arity(Tag::U64),
+        }
+    }
+
+    /// Decode the payload of an expression with the given `tag` from `ser_f` output.
+    fn de_f(tag: Tag, reader: &mut FieldReader<F>) -> Result<Self, DeserializeError> {
+        let start = reader.position();
+        let mut fields = reader.sub_reader(tag_arity(tag))?;
+        match tag {
+            Tag::Nil => Ok(ScalarExpression::Nil),
+            Tag::Cons => {
+                let car = fields.read_scalar_ptr()?;
+                let cdr = fields.read_scalar_ptr()?;
+                Ok(ScalarExpression::Cons(car, cdr))
+            }
+            Tag::Comm => {
+                let secret = fields.read()?;
+                let payload = fields.read_scalar_ptr()?;
+                Ok(ScalarExpression::Comm(secret, payload))
+            }
+            Tag::Sym => Ok(ScalarExpression::Sym(de_string(&mut fields, reader)?)),
+            Tag::Fun => {
+                let arg = fields.read_scalar_ptr()?;
+                let body = fields.read_scalar_ptr()?;
+                let closed_env = fields.read_scalar_ptr()?;
+                Ok(ScalarExpression::Fun {
+                    arg,
+                    body,
+                    closed_env,
+                })
+            }
+            Tag::Num => Ok(ScalarExpression::Num(fields.read()?)),
+            Tag::Str => Ok(ScalarExpression::Str(de_string(&mut fields, reader)?)),
+            Tag::Thunk => {
+                let value = fields.read_scalar_ptr()?;
+                let continuation = fields.read_scalar_cont_ptr()?;
+                Ok(ScalarExpression::Thunk(ScalarThunk {
+                    value,
+                    continuation,
+                }))
+            }
+            Tag::Char => {
+                let c = char_from_f(fields.read()?)
+                    .map_err(|_| DeserializeError::InvalidPayload(start))?;
+                Ok(ScalarExpression::Char(c))
+            }
+            Tag::U64 => {
+                let x = fields
+                    .read()?
+                    .to_u64()
+                    .ok_or(DeserializeError::InvalidPayload(start))?;
+                Ok(ScalarExpression::UInt(UInt::U64(x)))
+            }
+        }
+    }
+}
+
+impl<F: LurkField> ScalarContinuation<F> {
+    fn from_ptr(store: &Store<F>, ptr: &ContPtr<F>) -> Option<Self> {
+        let expr = |ptr: &Ptr<F>| store.get_expr_hash(ptr);
+        let cont = |ptr: &ContPtr<F>| store.get_cont_hash(ptr);
+
+        match store.fetch_cont(ptr)? {
+            Continuation::Outermost => Some(ScalarContinuation::Outermost),
+            Continuation::Call0 {
+                saved_env,
+                continuation,
+            } => Some(ScalarContinuation::Call0 {
+                saved_env: expr(&saved_env)?,
+                continuation: cont(&continuation)?,
+            }),
+            Continuation::Call {
+                unevaled_arg,
+                saved_env,
+                continuation,
+            } => Some(ScalarContinuation::Call {
+                unevaled_arg: expr(&unevaled_arg)?,
+                saved_env: expr(&saved_env)?,
+                continuation: cont(&continuation)?,
+            }),
+            Continuation::Call2 {
+                function,
+                saved_env,
+                continuation,
+            } => Some(ScalarContinuation::Call2 {
+                function: expr(&function)?,
+                saved_env: expr(&saved_env)?,
+                continuation: cont(&continuation)?,
+            }),
+            Continuation::Tail {
+                saved_env,
+                continuation,
+            } => Some(ScalarContinuation::Tail {
+                saved_env: expr(&saved_env)?,
+                continuation: cont(&continuation)?,
+            }),
+            Continuation::Error => Some(ScalarContinuation::Error),
+            Continuation::Lookup {
+                saved_env,
+                continuation,
+            } => Some(ScalarContinuation::Lookup {
+                saved_env: expr(&saved_env)?,
+                continuation: cont(&continuation)?,
+            }),
+            Continuation::Unop {
+                operator,
+                continuation,
+            } => Some(ScalarContinuation::Unop {
+                operator,
+                continuation: cont(&continuation)?,
+            }),
+            Continuation::Binop {
+                operator,
+                saved_env,
+                unevaled_args,
+                continuation,
+            } => Some(ScalarContinuation::Binop {
+                operator,
+                saved_env: expr(&saved_env)?,
+                unevaled_args: expr(&unevaled_args)?,
+                continuation: cont(&continuation)?,
+            }),
+            Continuation::Binop2 {
+                operator,
+                evaled_arg,
+                continuation,
+            } => Some(ScalarContinuation::Binop2 {
+                operator,
+                evaled_arg: expr(&evaled_arg)?,
+                continuation: cont(&continuation)?,
+            }),
+            Continuation::If {
+                unevaled_args,
+                continuation,
+            } => Some(ScalarContinuation::If {
+                unevaled_args: expr(&unevaled_args)?,
+                continuation: cont(&continuation)?,
+            }),
+            Continuation::Let {
+                var,
+                body,
+                saved_env,
+                continuation,
+            } => Some(ScalarContinuation::Let {
+                var: expr(&var)?,
+                body: expr(&body)?,
+                saved_env: expr(&saved_env)?,
+                continuation: cont(&continuation)?,
+            }),
+            Continuation::LetRec {
+                var,
+                saved_env,
+                body,
+                continuation,
+            } => Some(ScalarContinuation::LetRec {
+                var: expr(&var)?,
+                body: expr(&body)?,
+                saved_env: expr(&saved_env)?,
+                continuation: cont(&continuation)?,
+            }),
+            Continuation::Emit { continuation } => Some(ScalarContinuation::Emit {
+                continuation: cont(&continuation)?,
+            }),
+            Continuation::Dummy => Some(ScalarContinuation::Dummy),
+            Continuation::Terminal => Some(ScalarContinuation::Terminal),
+        }
+    }
+
+    /// The length of the payload written by `ser_f`.
+    fn ser_len(&self) -> usize {
+        cont_tag_arity(match self {
+            ScalarContinuation::Outermost => ContTag::Outermost,
+            ScalarContinuation::Call0 { .. } => ContTag::Call0,
+            ScalarContinuation::Call { .. } => ContTag::Call,
+            ScalarContinuation::Call2 { .. } => ContTag::Call2,
+            ScalarContinuation::Tail { .. } => ContTag::Tail,
+            ScalarContinuation::Error => ContTag::Error,
+            ScalarContinuation::Lookup { .. } => ContTag::Lookup,
+            ScalarContinuation::Unop { .. } => ContTag::Unop,
+            ScalarContinuation::Binop { .. } => ContTag::Binop,
+            ScalarContinuation::Binop2 { .. } => ContTag::Binop2,
+            ScalarContinuation::If { .. } => ContTag::If,
+            ScalarContinuation::Let { .. } => ContTag::Let,
+            ScalarContinuation::LetRec { .. } => ContTag::LetRec,
+            ScalarContinuation::Dummy => ContTag::Dummy,
+            ScalarContinuation::Terminal => ContTag::Terminal,
+            ScalarContinuation::Emit { .. } => ContTag::Emit,
+        })
+    }
+
+    /// The payload of this continuation in `ser_f` output, which follows the header of its `ScalarContPtr`.
+    fn ser_f(&self) -> Vec<F> {
+        match self {
+            ScalarContinuation::Outermost => vec![],
+            ScalarContinuation::Call0 {
+                saved_env,
+                continuation,
+            } => vec![
+                *saved_env.tag(),
+                *saved_env.value(),
+                *continuation.tag(),
+                *continuation.value(),
+            ],
+            ScalarContinuation::Call {
+                unevaled_arg,
+                saved_env,
+                continuation,
+            } => vec![
+                *unevaled_arg.tag(),
+                *unevaled_arg.value(),
+                *saved_env.tag(),
+                *saved_env.value(),
+                *continuation.tag(),
+                *continuation.value(),
+            ],
+            ScalarContinuation::Call2 {
+                function,
+                saved_env,
+                continuation,
+            } => vec![
+                *function.tag(),
+                *function.value(),
+                *saved_env.tag(),
+                *saved_env.value(),
+                *continuation.tag(),
+                *continuation.value(),
+            ],
+            ScalarContinuation::Tail {
+                saved_env,
+                continuation,
+            } => vec![
+                *saved_env.tag(),
+                *saved_env.value(),
+                *continuation.tag(),
+                *continuation.value(),
+            ],
+            ScalarContinuation::Error => vec![],
+            ScalarContinuation::Lookup {
+                saved_env,
+                continuation,
+            } => vec![
+                *saved_env.tag(),
+                *saved_env.value(),
+                *continuation.tag(),
+                *continuation.value(),
+            ],
+            ScalarContinuation::Unop {
+                operator,
+                continuation,
+            } => vec![
+                operator.as_field(),
+                *continuation.tag(),
+                *continuation.value(),
+            ],
+            ScalarContinuation::Binop {
+                operator,
+                saved_env,
+                unevaled_args,
+                continuation,
+            } => vec![
+                operator.as_field(),
+                *saved_env.tag(),
+                *saved_env.value(),
+                *unevaled_args.tag(),
+                *unevaled_args.value(),
+                *continuation.tag(),
+                *continuation.value(),
+            ],
+            ScalarContinuation::Binop2 {
+                operator,
+                evaled_arg,
+                continuation,
+            } => vec![
+                operator.as_field(),
+                *evaled_arg.tag(),
+                *evaled_arg.value(),
+                *continuation.tag(),
+                *continuation.value(),
+            ],
+            ScalarContinuation::If {
+                unevaled_args,
+                continuation,
+            } => vec![
+                *unevaled_args.tag(),
+                *unevaled_args.value(),
+                *continuation.tag(),
+                *continuation.value(),
+            ],
+            ScalarContinuation::Let {
+                var,
+                body,
+                saved_env,
+                continuation,
+            }
+            | ScalarContinuation::LetRec {
+                var,
+                body,
+                saved_env,
+                continuation,
+            } => vec![
+                *var.tag(),
+                *var.value(),
+                *body.tag(),
+                *body.value(),
+                *saved_env.tag(),
+                *saved_env.value(),
+                *continuation.tag(),
+                *continuation.value(),
+            ],
+            ScalarContinuation::Emit { continuation } => {
+                vec![*continuation.tag(), *continuation.value()]
+            }
+            ScalarContinuation::Dummy => vec![],
+            ScalarContinuation::Terminal => vec![],
+        }
+    }
+
+    /// Decode the payload of a continuation with the given `tag` from `ser_f` output.
+    fn de_f(tag: ContTag, reader: &mut FieldReader<F>) -> Result<Self, DeserializeError> {
+        let reader = &mut reader.sub_reader(cont_tag_arity(tag))?;
+        match tag {
+            ContTag::Outermost => Ok(ScalarContinuation::Outermost),
+            ContTag::Call0 => {
+                let saved_env = reader.read_scalar_ptr()?;
+                let continuation = reader.read_scalar_cont_ptr()?;
+                Ok(ScalarContinuation::Call0 {
+                    saved_env,
+                    continuation,
+                })
+            }
+            ContTag::Call => {
+                let unevaled_arg = reader.read_scalar_ptr()?;
+                let saved_env = reader.read_scalar_ptr()?;
+                let continuation = reader.read_scalar_cont_ptr()?;
+                Ok(ScalarContinuation::Call {
+                    unevaled_arg,
+                    saved_env,
+                    continuation,
+                })
+            }
+            ContTag::Call2 => {
+                let function = reader.read_scalar_ptr()?;
+                let saved_env = reader.read_scalar_ptr()?;
+                let continuation = reader.read_scalar_cont_ptr()?;
+                Ok(ScalarContinuation::Call2 {
+                    function,
+                    saved_env,
+                    continuation,
+                })
+            }
+            ContTag::Tail => {
+                let saved_env = reader.read_scalar_ptr()?;
+                let continuation = reader.read_scalar_cont_ptr()?;
+                Ok(ScalarContinuation::Tail {
+                    saved_env,
+                    continuation,
+                })
+            }
+            ContTag::Error => Ok(ScalarContinuation::Error),
+            ContTag::Lookup => {
+                let saved_env = reader.read_scalar_ptr()?;
+                let continuation = reader.read_scalar_cont_ptr()?;
+                Ok(ScalarContinuation::Lookup {
+                    saved_env,
+                    continuation,
+                })
+            }
+            ContTag::Unop => {
+                let operator = read_op1(reader)?;
+                let continuation = reader.read_scalar_cont_ptr()?;
+                Ok(ScalarContinuation::Unop {
+                    operator,
+                    continuation,
+                })
+            }
+            ContTag::Binop => {
+                let operator = read_op2(reader)?;
+                let saved_env = reader.read_scalar_ptr()?;
+                let unevaled_args = reader.read_scalar_ptr()?;
+                let continuation = reader.read_scalar_cont_ptr()?;
+                Ok(ScalarContinuation::Binop {
+                    operator,
+                    saved_env,
+                    unevaled_args,
+                    continuation,
+                })
+            }
+            ContTag::Binop2 => {
+                let operator = read_op2(reader)?;
+                let evaled_arg = reader.read_scalar_ptr()?;
+                let continuation = reader.read_scalar_cont_ptr()?;
+                Ok(ScalarContinuation::Binop2 {
+                    operator,
+                    evaled_arg,
+                    continuation,
+                })
+            }
+            ContTag::If => {
+                let unevaled_args = reader.read_scalar_ptr()?;
+                let continuation = reader.read_scalar_cont_ptr()?;
+                Ok(ScalarContinuation::If {
+                    unevaled_args,
+                    continuation,
+                })
+            }
+            ContTag::Let => {
+                let var = reader.read_scalar_ptr()?;
+                let body = reader.read_scalar_ptr()?;
+                let saved_env = reader.read_scalar_ptr()?;
+                let continuation = reader.read_scalar_cont_ptr()?;
+                Ok(ScalarContinuation::Let {
+                    var,
+                    body,
+                    saved_env,
+                    continuation,
+                })
+            }
+            ContTag::LetRec => {
+                let var = reader.read_scalar_ptr()?;
+                let body = reader.read_scalar_ptr()?;
+                let saved_env = reader.read_scalar_ptr()?;
+                let continuation = reader.read_scalar_cont_ptr()?;
+                Ok(ScalarContinuation::LetRec {
+                    var,
+                    body,
+                    saved_env,
+                    continuation,
+                })
+            }
+            ContTag::Emit => {
+                let continuation = reader.read_scalar_cont_ptr()?;
+                Ok(ScalarContinuation::Emit { continuation })
+            }
+            ContTag::Dummy => Ok(ScalarContinuation::Dummy),
+            ContTag::Terminal => Ok(ScalarContinuation::Terminal),
+        }
+    }
+}
+
+fn read_op1<F: LurkField>(reader: &mut FieldReader<F>) -> Result<Op1, DeserializeError> {
+    let start = reader.position();
+    Op1::from_field(reader.read()?).ok_or(DeserializeError::InvalidPayload(start))
+}
+
+fn read_op2<F: LurkField>(reader: &mut FieldReader<F>) -> Result<Op2, DeserializeError> {
+    let start = reader.position();
+    Op2::from_field(reader.read()?).ok_or(DeserializeError::InvalidPayload(start))
+}
+
+/// Read the field `key` of the JSON object `v` as a pointer.
+fn json_ptr<F: LurkField, P: ScalarPointer<F>>(v: &serde_json::Value, key: &str) -> Option<P> {
+    ptr_from_hex(v.get(key)?.as_str()?)
+}
+
+/// Read the field `key` of the JSON object `v` as an operator code.
+fn json_op(v: &serde_json::Value, key: &str) -> Option<u16> {
+    u16::try_from(v.get(key)?.as_u64()?).ok()
+}
+
+impl<F: LurkField> ScalarExpression<F> {
+    /// The JSON object representing the expression, tagged by `type`, as used by `ScalarStore::to_json`.
+    fn to_json(&self) -> serde_json::Value {
+        use serde_json::json;
+
+        match self {
+            ScalarExpression::Nil => json!({ "type": "Nil" }),
+            ScalarExpression::Cons(car, cdr) => {
+                json!({ "type": "Cons", "car": ptr_to_hex(car), "cdr": ptr_to_hex(cdr) })
+            }
+            ScalarExpression::Comm(secret, payload) => {
+                json!({ "type": "Comm", "secret": f_to_hex(secret), "payload": ptr_to_hex(payload) })
+            }
+            ScalarExpression::Sym(name) => json!({ "type": "Sym", "name": name }),
+            ScalarExpression::Fun {
+                arg,
+                body,
+                closed_env,
+            } => json!({
+                "type": "Fun",
+                "arg": ptr_to_hex(arg),
+                "body": ptr_to_hex(body),
+                "closed_env": ptr_to_hex(closed_env),
+            }),
+            ScalarExpression::Num(x) => json!({ "type": "Num", "value": f_to_hex(x) }),
+            ScalarExpression::Str(s) => json!({ "type": "Str", "value": s }),
+            ScalarExpression::Thunk(thunk) => json!({
+                "type": "Thunk",
+                "value": ptr_to_hex(&thunk.value),
+                "continuation": ptr_to_hex(&thunk.continuation),
+            }),
+            ScalarExpression::Char(c) => json!({ "type": "Char", "value": c.to_string() }),
+            ScalarExpression::UInt(n) => json!({ "type": "UInt", "value": u64::from(*n) }),
+        }
+    }
+
+    /// Parse the output of `to_json`.
+    fn from_json(v: &serde_json::Value) -> Option<Self> {
+        let string = |key| v.get(key)?.as_str().map(str::to_string);
+        match v.get("type")?.as_str()? {
+            "Nil" => Some(ScalarExpression::Nil),
+            "Cons" => Some(ScalarExpression::Cons(
+                json_ptr(v, "car")?,
+                json_ptr(v, "cdr")?,
+            )),
+            "Comm" => Some(ScalarExpression::Comm(
+                f_from_hex(v.get("secret")?.as_str()?)?,
+                json_ptr(v, "payload")?,
+            )),
+            "Sym" => Some(ScalarExpression::Sym(string("name")?)),
+            "Fun" => Some(ScalarExpression::Fun {
+                arg: json_ptr(v, "arg")?,
+                body: json_ptr(v, "body")?,
+                closed_env: json_ptr(v, "closed_env")?,
+            }),
+            "Num" => Some(ScalarExpression::Num(f_from_hex(
+                v.get("value")?.as_str()?,
+            )?)),
+            "Str" => Some(ScalarExpression::Str(string("value")?)),
+            "Thunk" => Some(ScalarExpression::Thunk(ScalarThunk {
+                value: json_ptr(v, "value")?,
+                continuation: json_ptr(v, "continuation")?,
+            })),
+            "Char" => {
+                let mut chars = v.get("value")?.as_str()?.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(ScalarExpression::Char(c)),
+                    _ => None,
+                }
+            }
+            "UInt" => Some(ScalarExpression::UInt(v.get("value")?.as_u64()?.into())),
+            _ => None,
+        }
+    }
+}
+
+impl<F: LurkField> ScalarContinuation<F> {
+    /// The JSON object representing the continuation, tagged by `type`, as used by `ScalarStore::to_json`. Operators
+    /// are represented by their `u16` codes.
+    fn to_json(self) -> serde_json::Value {
+        use serde_json::json;
+
+        match &self {
+            ScalarContinuation::Outermost => json!({ "type": "Outermost" }),
+            ScalarContinuation::Call0 {
+                saved_env,
+                continuation,
+            } => json!({
+                "type": "Call0",
+                "saved_env": ptr_to_hex(saved_env),
+                "continuation": ptr_to_hex(continuation),
+            }),
+            ScalarContinuation::Call {
+                unevaled_arg,
+                saved_env,
+                continuation,
+            } => json!({
+                "type": "Call",
+                "unevaled_arg": ptr_to_hex(unevaled_arg),
+                "saved_env": ptr_to_hex(saved_env),
+                "continuation": ptr_to_hex(continuation),
+            }),
+            ScalarContinuation::Call2 {
+                function,
+                saved_env,
+                continuation,
+            } => json!({
+                "type": "Call2",
+                "function": ptr_to_hex(function),
+                "saved_env": ptr_to_hex(saved_env),
+                "continuation": ptr_to_hex(continuation),
+            }),
+            ScalarContinuation::Tail {
+                saved_env,
+                continuation,
+            } => json!({
+                "type": "Tail",
+                "saved_env": ptr_to_hex(saved_env),
+                "continuation": ptr_to_hex(continuation),
+            }),
+            ScalarContinuation::Error => json!({ "type": "Error" }),
+            ScalarContinuation::Lookup {
+                saved_env,
+                continuation,
+            } => json!({
+                "type": "Lookup",
+                "saved_env": ptr_to_hex(saved_env),
+                "continuation": ptr_to_hex(continuation),
+            }),
+            ScalarContinuation::Unop {
+                operator,
+                continuation,
+            } => json!({
+                "type": "Unop",
+                "operator": *operator as u16,
+                "continuation": ptr_to_hex(continuation),
+            }),
+            ScalarContinuation::Binop {
+                operator,
+                saved_env,
+                unevaled_args,
+                continuation,
+            } => json!({
+                "type": "Binop",
+                "operator": *operator as u16,
+                "saved_env": ptr_to_hex(saved_env),
+                "unevaled_args": ptr_to_hex(unevaled_args),
+                "continuation": ptr_to_hex(continuation),
+            }),
+            ScalarContinuation::Binop2 {
+                operator,
+                evaled_arg,
+                continuation,
+            } => json!({
+                "type": "Binop2",
+                "operator": *operator as u16,
+                "evaled_arg": ptr_to_hex(evaled_arg),
+                "continuation": ptr_to_hex(continuation),
+            }),
+            ScalarContinuation::If {
+                unevaled_args,
+                continuation,
+            } => json!({
+                "type": "If",
+                "unevaled_args": ptr_to_hex(unevaled_args),
+                "continuation": ptr_to_hex(continuation),
+            }),
+            ScalarContinuation::Let {
+                var,
+                body,
+                saved_env,
+                continuation,
+            } => json!({
+                "type": "Let",
+                "var": ptr_to_hex(var),
+                "body": ptr_to_hex(body),
+                "saved_env": ptr_to_hex(saved_env),
+                "continuation": ptr_to_hex(continuation),
+            }),
+            ScalarContinuation::LetRec {
+                var,
+                body,
+                saved_env,
+                continuation,
+            } => json!({
+                "type": "LetRec",
+                "var": ptr_to_hex(var),
+                "body": ptr_to_hex(body),
+                "saved_env": ptr_to_hex(saved_env),
+                "continuation": ptr_to_hex(continuation),
+            }),
+            ScalarContinuation::Emit { continuation } => json!({
+                "type": "Emit",
+                "continuation": ptr_to_hex(continuation),
+            }),
+            ScalarContinuation::Dummy => json!({ "type": "Dummy" }),
+            ScalarContinuation::Terminal => json!({ "type": "Terminal" }),
+        }
+    }
+
+    /// Parse the output of `to_json`.
+    fn from_json(v: &serde_json::Value) -> Option<Self> {
+        match v.get("type")?.as_str()? {
+            "Outermost" => Some(ScalarContinuation::Outermost),
+            "Call0" => Some(ScalarContinuation::Call0 {
+                saved_env: json_ptr(v, "saved_env")?,
+                continuation: json_ptr(v, "continuation")?,
+            }),
+            "Call" => Some(ScalarContinuation::Call {
+                unevaled_arg: json_ptr(v, "unevaled_arg")?,
+                saved_env: json_ptr(v, "saved_env")?,
+                continuation: json_ptr(v, "continuation")?,
+            }),
+            "Call2" => Some(ScalarContinuation::Call2 {
+                function: json_ptr(v, "function")?,
+                saved_env: json_ptr(v, "saved_env")?,
+                continuation: json_ptr(v, "continuation")?,
+            }),
+            "Tail" => Some(ScalarContinuation::Tail {
+                saved_env: json_ptr(v, "saved_env")?,
+                continuation: json_ptr(v, "continuation")?,
+            }),
+            "Error" => Some(ScalarContinuation::Error),
+            "Lookup" => Some(ScalarContinuation::Lookup {
+                saved_env: json_ptr(v, "saved_env")?,
+                continuation: json_ptr(v, "continuation")?,
+            }),
+            "Unop" => Some(ScalarContinuation::Unop {
+                operator: Op1::from_u16(json_op(v, "operator")?)?,
+                continuation: json_ptr(v, "continuation")?,
+            }),
+            "Binop" => Some(ScalarContinuation::Binop {
+                operator: Op2::from_u16(json_op(v, "operator")?)?,
+                saved_env: json_ptr(v, "saved_env")?,
+                unevaled_args: json_ptr(v, "unevaled_args")?,
+                continuation: json_ptr(v, "continuation")?,
+            }),
+            "Binop2" => Some(ScalarContinuation::Binop2 {
+                operator: Op2::from_u16(json_op(v, "operator")?)?,
+                evaled_arg: json_ptr(v, "evaled_arg")?,
+                continuation: json_ptr(v, "continuation")?,
+            }),
+            "If" => Some(ScalarContinuation::If {
+                unevaled_args: json_ptr(v, "unevaled_args")?,
+                continuation: json_ptr(v, "continuation")?,
+            }),
+            "Let" => Some(ScalarContinuation::Let {
+                var: json_ptr(v, "var")?,
+                body: json_ptr(v, "body")?,
+                saved_env: json_ptr(v, "saved_env")?,
+                continuation: json_ptr(v, "continuation")?,
+            }),
+            "LetRec" => Some(ScalarContinuation::LetRec {
+                var: json_ptr(v, "var")?,
+                body: json_ptr(v, "body")?,
+                saved_env: json_ptr(v, "saved_env")?,
+                continuation: json_ptr(v, "continuation")?,
+            }),
+            "Emit" => Some(ScalarContinuation::Emit {
+                continuation: json_ptr(v, "continuation")?,
+            }),
+            "Dummy" => Some(ScalarContinuation::Dummy),
+            "Terminal" => Some(ScalarContinuation::Terminal),
+            _ => None,
+        }
+    }
+}
+
+// The variants and fields of `ScalarExpression`, `ScalarThunk` and `ScalarContinuation` are named explicitly for serde,
+// so that renaming them in Rust does not change the serialized schema. The IPLD encoding identifies variants by their
+// index, so new variants must only ever be appended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScalarExpression<F: LurkField> {
+    #[serde(rename = "Nil")]
+    Nil,
+    #[serde(rename = "Cons")]
+    Cons(ScalarPtr<F>, ScalarPtr<F>),
+    #[serde(rename = "Comm")]
+    Comm(F, ScalarPtr<F>),
+    #[serde(rename = "Sym")]
+    Sym(String),
+    #[serde(rename = "Fun")]
+    Fun {
+        #[serde(rename = "arg")]
+        arg: ScalarPtr<F>,
+        #[serde(rename = "body")]
+        body: ScalarPtr<F>,
+        #[serde(rename = "closed_env")]
+        closed_env: ScalarPtr<F>,
+    },
+    #[serde(rename = "Num")]
+    Num(F),
+    #[serde(rename = "Str")]
+    Str(String),
+    #[serde(rename = "Thunk")]
+    Thunk(ScalarThunk<F>),
+    #[serde(rename = "Char")]
+    Char(char),
+    #[serde(rename = "UInt")]
+    UInt(UInt),
+}
+
+impl<'a, F: LurkField> Default for ScalarExpression<F> {
+    fn default() -> Self {
+        Self::Nil
+    }
+}
+
+/// Convert a pointer to another field, as `ScalarStore::map_field`, by applying `p` to its tag and value together.
+fn map_ptr<F: LurkField, G: LurkField, P: ScalarPointer<F>, Q: ScalarPointer<G>>(
+    ptr: &P,
+    p: &impl Fn(&F, &F) -> (G, G),
+) -> Q {
+    let (tag, value) = p(ptr.tag(), ptr.value());
+    Q::from_parts(tag, value)
+}
+
+impl<F: LurkField> ScalarExpression<F> {
+    /// Convert the expression to another field, as `ScalarStore::map_field`.
+    fn map_field<G: LurkField>(&self, f: &impl Fn(&F) -> G) -> ScalarExpression<G> {
+        self.map_parts(f, &|tag, value| (f(tag), f(value)))
+    }
+
+    /// Like `map_field`, but convert the pointers the expression holds with `p`, which is given the tag and value of
+    /// each, so that pointers can be mapped as a whole.
+    fn map_parts<G: LurkField>(
+        &self,
+        f: &impl Fn(&F) -> G,
+        p: &impl Fn(&F, &F) -> (G, G),
+    ) -> ScalarExpression<G> {
+        match self {
+            ScalarExpression::Nil => ScalarExpression::Nil,
+            ScalarExpression::Cons(car, cdr) => {
+                ScalarExpression::Cons(map_ptr(car, p), map_ptr(cdr, p))
+            }
+            ScalarExpression::Comm(secret, payload) => {
+                ScalarExpression::Comm(f(secret), map_ptr(payload, p))
+            }
+            ScalarExpression::Sym(s) => ScalarExpression::Sym(s.clone()),
+            ScalarExpression::Fun {
+                arg,
+                body,
+                closed_env,
+            } => ScalarExpression::Fun {
+                arg: map_ptr(arg, p),
+                body: map_ptr(body, p),
+                closed_env: map_ptr(closed_env, p),
+            },
+            ScalarExpression::Num(x) => ScalarExpression::Num(f(x)),
+            ScalarExpression::Str(s) => ScalarExpression::Str(s.clone()),
+            ScalarExpression::Thunk(thunk) => ScalarExpression::Thunk(ScalarThunk {
+                value: map_ptr(&thunk.value, p),
+                continuation: map_ptr(&thunk.continuation, p),
+            }),
+            ScalarExpression::Char(c) => ScalarExpression::Char(*c),
+            ScalarExpression::UInt(n) => ScalarExpression::UInt(*n),
+        }
+    }
+
+    /// The position of the variant in the declaration of `ScalarExpression`, which orders expressions of different
+    /// variants.
+    fn variant_index(&self) -> usize {
+        match self {
+            ScalarExpression::Nil => 0,
+            ScalarExpression::Cons(..) => 1,
+            ScalarExpression::Comm(..) => 2,
+            ScalarExpression::Sym(_) => 3,
+            ScalarExpression::Fun { .. } => 4,
+            ScalarExpression::Num(_) => 5,
+            ScalarExpression::Str(_) => 6,
+            ScalarExpression::Thunk(_) => 7,
+            ScalarExpression::Char(_) => 8,
+            ScalarExpression::UInt(_) => 9,
+        }
+    }
+}
+
+impl<F: LurkField> PartialOrd for ScalarExpression<F> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Expressions are ordered first by variant, in declaration order, then field by field. Field elements are compared by
+/// their `to_repr` bytes, as in the `Ord` impl of `ScalarPtr`.
+impl<F: LurkField> Ord for ScalarExpression<F> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        match (self, other) {
+            (ScalarExpression::Cons(car1, cdr1), ScalarExpression::Cons(car2, cdr2)) => {
+                (car1, cdr1).cmp(&(car2, cdr2))
+            }
+            (
+                ScalarExpression::Comm(secret1, payload1),
+                ScalarExpression::Comm(secret2, payload2),
+            ) => {
+                (secret1.to_repr().as_ref(), payload1).cmp(&(secret2.to_repr().as_ref(), payload2))
+            }
+            (ScalarExpression::Sym(s1), ScalarExpression::Sym(s2))
+            | (ScalarExpression::Str(s1), ScalarExpression::Str(s2)) => s1.cmp(s2),
+            (
+                ScalarExpression::Fun {
+                    arg: arg1,
+                    body: body1,
+                    closed_env: closed_env1,
+                },
+                ScalarExpression::Fun {
+                    arg: arg2,
+                    body: body2,
+                    closed_env: closed_env2,
+                },
+            ) => (arg1, body1, closed_env1).cmp(&(arg2, body2, closed_env2)),
+            (ScalarExpression::Num(x1), ScalarExpression::Num(x2)) => {
+                x1.to_repr().as_ref().cmp(x2.to_repr().as_ref())
+            }
+            (ScalarExpression::Thunk(thunk1), ScalarExpression::Thunk(thunk2)) => {
+                (thunk1.value, thunk1.continuation).cmp(&(thunk2.value, thunk2.continuation))
+            }
+            (ScalarExpression::Char(c1), ScalarExpression::Char(c2)) => c1.cmp(c2),
+            (ScalarExpression::UInt(UInt::U64(n1)), ScalarExpression::UInt(UInt::U64(n2))) => {
+                n1.cmp(n2)
+            }
+            _ => self.variant_index().cmp(&other.variant_index()),
+        }
+    }
+}
+
+// Unused for now, but will be needed when we serialize Thunks to IPLD.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScalarThunk<F: LurkField> {
+    #[serde(rename = "value")]
+    pub(crate) value: ScalarPtr<F>,
+    #[serde(rename = "continuation")]
+    pub(crate) continuation: ScalarContPtr<F>,
+}
+
+impl<F: LurkField> Copy for ScalarThunk<F> {}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ScalarContinuation<F: LurkField> {
+    #[serde(rename = "Outermost")]
+    Outermost,
+    #[serde(rename = "Call0")]
+    Call0 {
+        #[serde(rename = "saved_env")]
+        saved_env: ScalarPtr<F>,
+        #[serde(rename = "continuation")]
+        continuation: ScalarContPtr<F>,
+    },
+    #[serde(rename = "Call")]
+    Call {
+        #[serde(rename = "unevaled_arg")]
+        unevaled_arg: ScalarPtr<F>,
+        #[serde(rename = "saved_env")]
+        saved_env: ScalarPtr<F>,
+        #[serde(rename = "continuation")]
+        continuation: ScalarContPtr<F>,
+    },
+    #[serde(rename = "Call2")]
+    Call2 {
+        #[serde(rename = "function")]
+        function: ScalarPtr<F>,
+        #[serde(rename = "saved_env")]
+        saved_env: ScalarPtr<F>,
+        #[serde(rename = "continuation")]
+        continuation: ScalarContPtr<F>,
+    },
+    #[serde(rename = "Tail")]
+    Tail {
+        #[serde(rename = "saved_env")]
+        saved_env: ScalarPtr<F>,
+        #[serde(rename = "continuation")]
+        continuation: ScalarContPtr<F>,
+    },
+    #[serde(rename = "Error")]
+    Error,
+    #[serde(rename = "Lookup")]
+    Lookup {
+        #[serde(rename = "saved_env")]
+        saved_env: ScalarPtr<F>,
+        #[serde(rename = "continuation")]
+        continuation: ScalarContPtr<F>,
+    },
+    #[serde(rename = "Unop")]
+    Unop {
+        #[serde(rename = "operator")]
+        operator: Op1,
+        #[serde(rename = "continuation")]
+        continuation: ScalarContPtr<F>,
+    },
+    #[serde(rename = "Binop")]
+    Binop {
+        #[serde(rename = "operator")]
+        operator: Op2,
+        #[serde(rename = "saved_env")]
+        saved_env: ScalarPtr<F>,
+        #[serde(rename = "unevaled_args")]
+        unevaled_args: ScalarPtr<F>,
+        #[serde(rename = "continuation")]
+        continuation: ScalarContPtr<F>,
+    },
+    #[serde(rename = "Binop2")]
+    Binop2 {
+        #[serde(rename = "operator")]
+        operator: Op2,
+        #[serde(rename = "evaled_arg")]
+        evaled_arg: ScalarPtr<F>,
+        #[serde(rename = "continuation")]
+        continuation: ScalarContPtr<F>,
+    },
+    #[serde(rename = "If")]
+    If {
+        #[serde(rename = "unevaled_args")]
+        unevaled_args: ScalarPtr<F>,
+        #[serde(rename = "continuation")]
+        continuation: ScalarContPtr<F>,
+    },
+    #[serde(rename = "Let")]
+    Let {
+        #[serde(rename = "var")]
+        var: ScalarPtr<F>,
+        #[serde(rename = "body")]
+        body: ScalarPtr<F>,
+        #[serde(rename = "saved_env")]
+        saved_env: ScalarPtr<F>,
+        #[serde(rename = "continuation")]
+        continuation: ScalarContPtr<F>,
+    },
+    #[serde(rename = "LetRec")]
+    LetRec {
+        #[serde(rename = "var")]
+        var: ScalarPtr<F>,
+        #[serde(rename = "body")]
+        body: ScalarPtr<F>,
+        #[serde(rename = "saved_env")]
+        saved_env: ScalarPtr<F>,
+        #[serde(rename = "continuation")]
+        continuation: ScalarContPtr<F>,
+    },
+    #[serde(rename = "Emit")]
+    Emit {
+        #[serde(rename = "continuation")]
+        continuation: ScalarContPtr<F>,
+    },
+    #[serde(rename = "Dummy")]
+    Dummy,
+    #[serde(rename = "Terminal")]
+    Terminal,
+}
+
+impl<F: LurkField> ScalarContinuation<F> {
+    /// Convert the continuation to another field, as `ScalarStore::map_field`.
+    fn map_field<G: LurkField>(&self, f: &impl Fn(&F) -> G) -> ScalarContinuation<G> {
+        self.map_ptrs(&|tag, value| (f(tag), f(value)))
+    }
+
+    /// Convert the continuation to another field by converting each pointer it holds with `p`, which is given the tag
+    /// and value of each, so that pointers can be mapped as a whole. A continuation holds no other field elements.
+    fn map_ptrs<G: LurkField>(&self, p: &impl Fn(&F, &F) -> (G, G)) -> ScalarContinuation<G> {
+        match self {
+            ScalarContinuation::Outermost => ScalarContinuation::Outermost,
+            ScalarContinuation::Call0 {
+                saved_env,
+                continuation,
+            } => ScalarContinuation::Call0 {
+                saved_env: map_ptr(saved_env, p),
+                continuation: map_ptr(continuation, p),
+            },
+            ScalarContinuation::Call {
+                unevaled_arg,
+                saved_env,
+                continuation,
+            } => ScalarContinuation::Call {
+                unevaled_arg: map_ptr(unevaled_arg, p),
+                saved_env: map_ptr(saved_env, p),
+                continuation: map_ptr(continuation, p),
+            },
+            ScalarContinuation::Call2 {
+                function,
+                saved_env,
+                continuation,
+            } => ScalarContinuation::Call2 {
+                function: map_ptr(function, p),
+                saved_env: map_ptr(saved_env, p),
+                continuation: map_ptr(continuation, p),
+            },
+            ScalarContinuation::Tail {
+                saved_env,
+                continuation,
+            } => ScalarContinuation::Tail {
+                saved_env: map_ptr(saved_env, p),
+                continuation: map_ptr(continuation, p),
+            },
+            ScalarContinuation::Error => ScalarContinuation::Error,
+            ScalarContinuation::Lookup {
+                saved_env,
+                continuation,
+            } => ScalarContinuation::Lookup {
+                saved_env: map_ptr(saved_env, p),
+                continuation: map_ptr(continuation, p),
+            },
+            ScalarContinuation::Unop {
+                operator,
+                continuation,
+            } => ScalarContinuation::Unop {
+                operator: *operator,
+                continuation: map_ptr(continuation, p),
+            },
+            ScalarContinuation::Binop {
+                operator,
+                saved_env,
+                unevaled_args,
+                continuation,
+            } => ScalarContinuation::Binop {
+                operator: *operator,
+                saved_env: map_ptr(saved_env, p),
+                unevaled_args: map_ptr(unevaled_args, p),
+                continuation: map_ptr(continuation, p),
+            },
+            ScalarContinuation::Binop2 {
+                operator,
+                evaled_arg,
+                continuation,
+            } => ScalarContinuation::Binop2 {
+                operator: *operator,
+                evaled_arg: map_ptr(evaled_arg, p),
+                continuation: map_ptr(continuation, p),
+            },
+            ScalarContinuation::If {
+                unevaled_args,
+                continuation,
+            } => ScalarContinuation::If {
+                unevaled_args: map_ptr(unevaled_args, p),
+                continuation: map_ptr(continuation, p),
+            },
+            ScalarContinuation::Let {
+                var,
+                body,
+                saved_env,
+                continuation,
+            } => ScalarContinuation::Let {
+                var: map_ptr(var, p),
+                body: map_ptr(body, p),
+                saved_env: map_ptr(saved_env, p),
+                continuation: map_ptr(continuation, p),
+            },
+            ScalarContinuation::LetRec {
+                var,
+                body,
+                saved_env,
+                continuation,
+            } => ScalarContinuation::LetRec {
+                var: map_ptr(var, p),
+                body: map_ptr(body, p),
+                saved_env: map_ptr(saved_env, p),
+                continuation: map_ptr(continuation, p),
+            },
+            ScalarContinuation::Emit { continuation } => ScalarContinuation::Emit {
+                continuation: map_ptr(continuation, p),
+            },
+            ScalarContinuation::Dummy => ScalarContinuation::Dummy,
+            ScalarContinuation::Terminal => ScalarContinuation::Terminal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval::{empty_sym_env, Evaluator};
+    use crate::field::FWrap;
+    use crate::store::ScalarPointer;
+    use blstrs::Scalar as Fr;
+    use ff::{Field, PrimeField};
+
+    use quickcheck::{Arbitrary, Gen};
+
+    use crate::test::frequency;
+
+    #[cfg(feature = "ipld")]
+    use libipld::serde::from_ipld;
+    #[cfg(feature = "ipld")]
+    use libipld::serde::to_ipld;
+    #[cfg(feature = "ipld")]
+    use libipld::Ipld;
+
+    impl Arbitrary for ScalarThunk<Fr> {
+        fn arbitrary(g: &mut Gen) -> Self {
+            ScalarThunk {
+                value: Arbitrary::arbitrary(g),
+                continuation: Arbitrary::arbitrary(g),
+            }
+        }
+    }
+
+    #[cfg(feature = "ipld")]
+    #[quickcheck]
+    fn prop_scalar_thunk_ipld(x: ScalarThunk<Fr>) -> bool {
+        if let Ok(ipld) = to_ipld(x) {
+            if let Ok(y) = from_ipld(ipld) {
+                x == y
+            } else {
+                false
+            }
+        } else {
+            false
+        }
+    }
+
+    impl Arbitrary for ScalarExpression<Fr> {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let input: Vec<(i64, Box<dyn Fn(&mut Gen) -> ScalarExpression<Fr>>)> = vec![
+                (100, Box::new(|_| Self::Nil)),
+                (
+                    100,
+                    Box::new(|g| Self::Cons(ScalarPtr::arbitrary(g), ScalarPtr::arbitrary(g))),
+                ),
+                (100, Box::new(|g| Self::Sym(String::arbitrary(g)))),
+                (100, Box::new(|g| Self::Str(String::arbitrary(g)))),
+                (
+                    100,
+                    Box::new(|g| {
+                        let f = FWrap::arbitrary(g);
+                        Self::Num(f.0)
+                    }),
+                ),
+                (
+                    100,
+                    Box::new(|g| Self::Fun {
+                        arg: ScalarPtr::arbitrary(g),
+                        body: ScalarPtr::arbitrary(g),
+                        closed_env: ScalarPtr::arbitrary(g),
+                    }),
+                ),
+                (100, Box::new(|g| Self::Thunk(ScalarThunk::arbitrary(g)))),
+                (
+                    100,
+                    Box::new(|g| Self::Comm(FWrap::arbitrary(g).0, ScalarPtr::arbitrary(g))),
+                ),
+                (100, Box::new(|g| Self::Char(char::arbitrary(g)))),
+                (100, Box::new(|g| Self::UInt(UInt::U64(u64::arbitrary(g))))),
+            ];
+            frequency(g, input)
+        }
+
+        fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+            let mut shrunk = vec![];
+            if *self != Self::Nil {
+                shrunk.push(Self::Nil);
+            }
+            match self {
+                Self::Nil => (),
+                Self::Cons(car, cdr) => {
+                    shrunk.extend(shrink_ptr(car).map(|car| Self::Cons(car, *cdr)));
+                    shrunk.extend(shrink_ptr(cdr).map(|cdr| Self::Cons(*car, cdr)));
+                }
+                Self::Sym(s) => {
+                    return Box::new(shrunk.into_iter().chain(s.shrink().map(Self::Sym)))
+                }
+                Self::Str(s) => {
+                    return Box::new(shrunk.into_iter().chain(s.shrink().map(Self::Str)))
+                }
+                Self::Fun {
+                    arg,
+                    body,
+                    closed_env,
+                } => {
+                    shrunk.extend(shrink_ptr(arg).map(|arg| Self::Fun {
+                        arg,
+                        body: *body,
+                        closed_env: *closed_env,
+                    }));
+                    shrunk.extend(shrink_ptr(body).map(|body| Self::Fun {
+                        arg: *arg,
+                        body,
+                        closed_env: *closed_env,
+                    }));
+                    shrunk.extend(shrink_ptr(closed_env).map(|closed_env| Self::Fun {
+                        arg: *arg,
+                        body: *body,
+                        closed_env,
+                    }));
+                }
+                Self::Num(f) => {
+                    if *f != Fr::zero() {
+                        shrunk.push(Self::Num(Fr::zero()));
+                    }
+                }
+                Self::Thunk(thunk) => {
+                    shrunk.extend(shrink_ptr(&thunk.value).map(|value| {
+                        Self::Thunk(ScalarThunk {
+                            value,
+                            continuation: thunk.continuation,
+                        })
+                    }));
+                    shrunk.extend(shrink_cont_ptr(&thunk.continuation).map(|continuation| {
+                        Self::Thunk(ScalarThunk {
+                            value: thunk.value,
+                            continuation,
+                        })
+                    }));
+                }
+                Self::Comm(secret, payload) => {
+                    if *secret != Fr::zero() {
+                        shrunk.push(Self::Comm(Fr::zero(), *payload));
+                    }
+                    shrunk.extend(shrink_ptr(payload).map(|payload| Self::Comm(*secret, payload)));
+                }
+                Self::Char(c) => shrunk.extend(c.shrink().map(Self::Char)),
+                Self::UInt(UInt::U64(n)) => {
+                    shrunk.extend(n.shrink().map(|n| Self::UInt(UInt::U64(n))))
+                }
+            }
+            Box::new(shrunk.into_iter())
+        }
+    }
+
+    impl Arbitrary for ScalarContinuation<Fr> {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let input: Vec<(i64, Box<dyn Fn(&mut Gen) -> ScalarContinuation<Fr>>)> = vec![
+                (100, Box::new(|_| Self::Outermost)),
+                (
+                    100,
+                    Box::new(|g| Self::Call0 {
+                        saved_env: ScalarPtr::arbitrary(g),
+                        continuation: ScalarContPtr::arbitrary(g),
+                    }),
+                ),
+                (
+                    100,
+                    Box::new(|g| Self::Call {
+                        unevaled_arg: ScalarPtr::arbitrary(g),
+                        saved_env: ScalarPtr::arbitrary(g),
+                        continuation: ScalarContPtr::arbitrary(g),
+                    }),
+                ),
+                (
+                    100,
+                    Box::new(|g| Self::Call2 {
+                        function: ScalarPtr::arbitrary(g),
+                        saved_env: ScalarPtr::arbitrary(g),
+                        continuation: ScalarContPtr::arbitrary(g),
+                    }),
+                ),
+                (
+                    100,
+                    Box::new(|g| Self::Tail {
+                        saved_env: ScalarPtr::arbitrary(g),
+                        continuation: ScalarContPtr::arbitrary(g),
+                    }),
+                ),
+                (100, Box::new(|_| Self::Error)),
+                (
+                    100,
+                    Box::new(|g| Self::Lookup {
+                        saved_env: ScalarPtr::arbitrary(g),
+                        continuation: ScalarContPtr::arbitrary(g),
+                    }),
+                ),
+                (
+                    100,
+                    Box::new(|g| Self::Unop {
+                        operator: Op1::arbitrary(g),
+                        continuation: ScalarContPtr::arbitrary(g),
+                    }),
+                ),
+                (
+                    100,
+                    Box::new(|g| Self::Binop {
+                        operator: Op2::arbitrary(g),
+                        saved_env: ScalarPtr::arbitrary(g),
+                        unevaled_args: ScalarPtr::arbitrary(g),
+                        continuation: ScalarContPtr::arbitrary(g),
+                    }),
+                ),
+                (
+                    100,
+                    Box::new(|g| Self::Binop2 {
+                        operator: Op2::arbitrary(g),
+                        evaled_arg: ScalarPtr::arbitrary(g),
+                        continuation: ScalarContPtr::arbitrary(g),
+                    }),
+                ),
+                (
+                    100,
+                    Box::new(|g| Self::If {
+                        unevaled_args: ScalarPtr::arbitrary(g),
+                        continuation: ScalarContPtr::arbitrary(g),
+                    }),
+                ),
+                (
+                    100,
+                    Box::new(|g| Self::Let {
+                        var: ScalarPtr::arbitrary(g),
+                        body: ScalarPtr::arbitrary(g),
+                        saved_env: ScalarPtr::arbitrary(g),
+                        continuation: ScalarContPtr::arbitrary(g),
+                    }),
+                ),
+                (
+                    100,
+                    Box::new(|g| Self::LetRec {
+                        var: ScalarPtr::arbitrary(g),
+                        saved_env: ScalarPtr::arbitrary(g),
+                        body: ScalarPtr::arbitrary(g),
+                        continuation: ScalarContPtr::arbitrary(g),
+                    }),
+                ),
+                (100, Box::new(|_| Self::Dummy)),
+                (100, Box::new(|_| Self::Terminal)),
+            ];
+            frequency(g, input)
+        }
+
+        fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+            let mut shrunk = vec![];
+            if *self != Self::Outermost {
+                shrunk.push(Self::Outermost);
+            }
+            // Replace one pointer field at a time, keeping the rest of the frame
+            // (including any operator) intact.
+            let (ptrs, conts) = ScalarStore::child_scalar_cont_ptrs(self);
+            for i in 0..ptrs.len() {
+                if let Some(nil) = shrink_ptr(&ptrs[i]) {
+                    let mut ptrs = ptrs.clone();
+                    ptrs[i] = nil;
+                    shrunk.push(with_children(self, &ptrs, &conts));
+                }
+            }
+            for i in 0..conts.len() {
+                if let Some(outermost) = shrink_cont_ptr(&conts[i]) {
+                    let mut conts = conts.clone();
+                    conts[i] = outermost;
+                    shrunk.push(with_children(self, &ptrs, &conts));
+                }
+            }
+            Box::new(shrunk.into_iter())
+        }
+    }
+
+    fn shrink_ptr(ptr: &ScalarPtr<Fr>) -> Option<ScalarPtr<Fr>> {
+        let nil = ScalarPtr::from_parts(Tag::Nil.as_field(), Fr::zero());
+        if *ptr != nil {
+            Some(nil)
+        } else {
+            None
+        }
+    }
+
+    fn shrink_cont_ptr(ptr: &ScalarContPtr<Fr>) -> Option<ScalarContPtr<Fr>> {
+        let outermost = ScalarContPtr::from_parts(ContTag::Outermost.as_field(), Fr::zero());
+        if *ptr != outermost {
+            Some(outermost)
+        } else {
+            None
+        }
+    }
+
+    /// Rebuild `cont` with its pointer fields replaced, in the order returned by
+    /// `child_scalar_cont_ptrs`.
+    fn with_children(
+        cont: &ScalarContinuation<Fr>,
+        ptrs: &[ScalarPtr<Fr>],
+        conts: &[ScalarContPtr<Fr>],
+    ) -> ScalarContinuation<Fr> {
+        use ScalarContinuation::*;
+        match *cont {
+            Outermost | Error | Dummy | Terminal => *cont,
+            Call0 { .. } => Call0 {
+                saved_env: ptrs[0],
+                continuation: conts[0],
+            },
+            Call { .. } => Call {
+                unevaled_arg: ptrs[0],
+                saved_env: ptrs[1],
+                continuation: conts[0],
+            },
+            Call2 { .. } => Call2 {
+                function: ptrs[0],
+                saved_env: ptrs[1],
+                continuation: conts[0],
+            },
+            Tail { .. } => Tail {
+                saved_env: ptrs[0],
+                continuation: conts[0],
+            },
+            Lookup { .. } => Lookup {
+                saved_env: ptrs[0],
+                continuation: conts[0],
+            },
+            Unop { operator, .. } => Unop {
+                operator,
+                continuation: conts[0],
+            },
+            Binop { operator, .. } => Binop {
+                operator,
+                saved_env: ptrs[0],
+                unevaled_args: ptrs[1],
+                continuation: conts[0],
+            },
+            Binop2 { operator, .. } => Binop2 {
+                operator,
+                evaled_arg: ptrs[0],
+                continuation: conts[0],
+            },
+            If { .. } => If {
+                unevaled_args: ptrs[0],
+                continuation: conts[0],
+            },
+            Let { .. } => Let {
+                var: ptrs[0],
+                body: ptrs[1],
+                saved_env: ptrs[2],
+                continuation: conts[0],
+            },
+            LetRec { .. } => LetRec {
+                var: ptrs[0],
+                body: ptrs[1],
+                saved_env: ptrs[2],
+                continuation: conts[0],
+            },
+            Emit { .. } => Emit {
+                continuation: conts[0],
+            },
+        }
+    }
+
+    #[cfg(feature = "ipld")]
+    #[quickcheck]
+    fn prop_scalar_expression_ipld(x: ScalarExpression<Fr>) -> bool {
+        match to_ipld(x.clone()) {
+            Ok(ipld) => match from_ipld(ipld.clone()) {
+                Ok(y) => {
+                    println!("x: {:?}", x);
+                    println!("y: {:?}", y);
+                    x == y
+                }
+                Err(e) => {
+                    println!("ser x: {:?}", x);
+                    println!("de ipld: {:?}", ipld);
+                    println!("err e: {:?}", e);
+                    false
+                }
+            },
+            Err(e) => {
+                println!("ser x: {:?}", x);
+                println!("err e: {:?}", e);
+                false
+            }
+        }
+    }
+
+    #[cfg(feature = "ipld")]
+    #[quickcheck]
+    fn prop_scalar_continuation_ipld(x: ScalarExpression<Fr>) -> bool {
+        if let Ok(ipld) = to_ipld(x.clone()) {
+            if let Ok(y) = from_ipld(ipld) {
+                x == y
+            } else {
+                false
+            }
+        } else {
+            false
+        }
+    }
+
+    // This doesn't create well-defined ScalarStores, so is only useful for
+    // testing ipld. Use `arbitrary_closed` for a store whose children are all present.
+    impl Arbitrary for ScalarStore<Fr> {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let map: Vec<(ScalarPtr<Fr>, Option<ScalarExpression<Fr>>)> = Arbitrary::arbitrary(g);
+            let cont_map: Vec<(ScalarContPtr<Fr>, Option<ScalarContinuation<Fr>>)> =
+                Arbitrary::arbitrary(g);
+            ScalarStore {
+                scalar_map: map.into_iter().collect(),
+                scalar_cont_map: cont_map.into_iter().collect(),
+                pending_scalar_ptrs: Vec::new(),
+                pending_scalar_cont_ptrs: Vec::new(),
+            }
+        }
+    }
+
+    #[cfg(feature = "ipld")]
+    #[quickcheck]
+    fn prop_scalar_store_ipld(x: ScalarStore<Fr>) -> bool {
+        if let Ok(ipld) = to_ipld(x.clone()) {
+            if let Ok(y) = from_ipld(ipld) {
+                x == y
+            } else {
+                false
+            }
+        } else {
+            false
+        }
+    }
+
+    #[quickcheck]
+    fn prop_scalar_store_bincode(x: ScalarStore<Fr>) -> bool {
+        if let Ok(bytes) = x.to_bincode() {
+            if let Ok(y) = ScalarStore::from_bincode(&bytes) {
+                x == y
+            } else {
+                false
+            }
+        } else {
+            false
+        }
+    }
+
+    fn expression_tag(expr: &ScalarExpression<Fr>) -> Tag {
+        match expr {
+            ScalarExpression::Nil => Tag::Nil,
+            ScalarExpression::Cons(..) => Tag::Cons,
+            ScalarExpression::Comm(..) => Tag::Comm,
+            ScalarExpression::Sym(_) => Tag::Sym,
+            ScalarExpression::Fun { .. } => Tag::Fun,
+            ScalarExpression::Num(_) => Tag::Num,
+            ScalarExpression::Str(_) => Tag::Str,
+            ScalarExpression::Thunk(_) => Tag::Thunk,
+            ScalarExpression::Char(_) => Tag::Char,
+            ScalarExpression::UInt(_) => Tag::U64,
+        }
+    }
+
+    fn continuation_tag(cont: &ScalarContinuation<Fr>) -> ContTag {
+        match cont {
+            ScalarContinuation::Outermost => ContTag::Outermost,
+            ScalarContinuation::Call0 { .. } => ContTag::Call0,
+            ScalarContinuation::Call { .. } => ContTag::Call,
+            ScalarContinuation::Call2 { .. } => ContTag::Call2,
+            ScalarContinuation::Tail { .. } => ContTag::Tail,
+            ScalarContinuation::Error => ContTag::Error,
+            ScalarContinuation::Lookup { .. } => ContTag::Lookup,
+            ScalarContinuation::Unop { .. } => ContTag::Unop,
+            ScalarContinuation::Binop { .. } => ContTag::Binop,
+            ScalarContinuation::Binop2 { .. } => ContTag::Binop2,
+            ScalarContinuation::If { .. } => ContTag::If,
+            ScalarContinuation::Let { .. } => ContTag::Let,
+            ScalarContinuation::LetRec { .. } => ContTag::LetRec,
+            ScalarContinuation::Emit { .. } => ContTag::Emit,
+            ScalarContinuation::Dummy => ContTag::Dummy,
+            ScalarContinuation::Terminal => ContTag::Terminal,
+        }
+    }
+
+    // Unlike the `Arbitrary` instance for `ScalarStore`, every pointer here is tagged consistently with the expression
+    // or continuation it maps to, which is what `de_f` relies on to decode each entry.
+    #[derive(Debug, Clone)]
+    struct TaggedScalarStore(ScalarStore<Fr>);
+
+    impl Arbitrary for TaggedScalarStore {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let store = ScalarStore::arbitrary(g);
+            let scalar_map = store
+                .scalar_map
+                .into_iter()
+                .map(|(ptr, expr)| {
+                    let tag = match &expr {
+                        Some(expr) => expression_tag(expr).as_field(),
+                        None => *ptr.tag(),
+                    };
+                    (ScalarPtr::from_parts(tag, *ptr.value()), expr)
+                })
+                .collect();
+            let scalar_cont_map = store
+                .scalar_cont_map
+                .into_iter()
+                .map(|(ptr, cont)| {
+                    let tag = match &cont {
+                        Some(cont) => continuation_tag(cont).as_field(),
+                        None => *ptr.tag(),
+                    };
+                    (ScalarContPtr::from_parts(tag, *ptr.value()), cont)
+                })
+                .collect();
+            TaggedScalarStore(ScalarStore {
+                scalar_map,
+                scalar_cont_map,
+                pending_scalar_ptrs: Vec::new(),
+                pending_scalar_cont_ptrs: Vec::new(),
+            })
+        }
+    }
+
+    /// A short random string, since `Store` hashes strings one character at a time.
+    fn arbitrary_short_string(g: &mut Gen) -> String {
+        String::arbitrary(g).chars().take(8).collect()
+    }
+
+    /// A random expression, of depth at most `depth`, interned in `store`.
+    fn arbitrary_ptr(g: &mut Gen, store: &mut Store<Fr>, depth: usize) -> Ptr<Fr> {
+        let leaves = 6;
+        let choices = if depth == 0 { leaves } else { leaves + 3 };
+        match usize::arbitrary(g) % choices {
+            0 => store.nil(),
+            1 => store.num(Num::Scalar(FWrap::<Fr>::arbitrary(g).0)),
+            2 => store.get_char(char::arbitrary(g)),
+            3 => store.str(arbitrary_short_string(g)),
+            4 => store.sym(arbitrary_short_string(g)),
+            5 => store.uint64(u64::arbitrary(g)),
+            6 => {
+                let car = arbitrary_ptr(g, store, depth - 1);
+                let cdr = arbitrary_ptr(g, store, depth - 1);
+                store.cons(car, cdr)
+            }
+            7 => {
+                let payload = arbitrary_ptr(g, store, depth - 1);
+                store.intern_comm(FWrap::<Fr>::arbitrary(g).0, payload)
+            }
+            _ => {
+                let arg = store.sym(arbitrary_short_string(g));
+                let body = arbitrary_ptr(g, store, depth - 1);
+                let closed_env = arbitrary_ptr(g, store, depth - 1);
+                store.intern_fun(arg, body, closed_env)
+            }
+        }
+    }
+
+    /// A well-formed `ScalarStore`, in which every child pointer is present, made by interning random expressions in a
+    /// `Store` and converting them.
+    fn arbitrary_closed(g: &mut Gen) -> ScalarStore<Fr> {
+        let mut store = Store::<Fr>::default();
+        let exprs: Vec<Ptr<Fr>> = (0..usize::arbitrary(g) % 4)
+            .map(|_| arbitrary_ptr(g, &mut store, 4))
+            .collect();
+        store.hydrate_scalar_cache();
+        ScalarStore::new_with_exprs(&store, &exprs).0
+    }
+
+    #[derive(Debug, Clone)]
+    struct ClosedScalarStore(ScalarStore<Fr>);
+
+    impl Arbitrary for ClosedScalarStore {
+        fn arbitrary(g: &mut Gen) -> Self {
+            ClosedScalarStore(arbitrary_closed(g))
+        }
+    }
+
+    #[quickcheck]
+    fn prop_scalar_store_closed(x: ClosedScalarStore) -> bool {
+        x.0.check_complete().is_ok()
+    }
+
+    #[test]
+    fn test_to_store_comm_u64() {
+        let mut store = Store::<Fr>::default();
+        let num = store.num(987);
+        let comm = store.intern_comm(Fr::from(123), num);
+        let uint = store.uint64(456);
+        let expr = store.cons(comm, uint);
+        store.hydrate_scalar_cache();
+
+        let (mut scalar_store, scalar_expr) = ScalarStore::new_with_expr(&store, &expr);
+        let (mut store2, expr2) = scalar_store
+            .to_store_with_expr(&scalar_expr.unwrap())
+            .unwrap();
+        store2.hydrate_scalar_cache();
+        assert_eq!(scalar_expr, store2.get_expr_hash(&expr2));
+    }
+
+    #[quickcheck]
+    fn prop_scalar_store_ser_f(x: TaggedScalarStore) -> bool {
+        let store = x.0;
+        ScalarStore::de_f(&store.clone().ser_f().unwrap()) == Ok(store)
+    }
+
+    #[test]
+    fn test_scalar_store_ser_f() {
+        let test = |src| {
+            let mut s = Store::<Fr>::default();
+            let expr = s.read(src).unwrap();
+            s.hydrate_scalar_cache();
+
+            let (scalar_store, _) = ScalarStore::new_with_expr(&s, &expr);
+            let fs = scalar_store.clone().ser_f().unwrap();
+            assert_eq!(Ok(scalar_store), ScalarStore::de_f(&fs));
+        };
+
+        test("symbol");
+        test("(1 . 2)");
+        test("(+ 1 2 (* 3 4) \"asdf\" )");
+        test("\"a string which is too long to fit in a single field element\"");
+        test("(let ((a 123)) (lambda (x) (+ x a)))");
+    }
+
+    #[test]
+    fn test_scalar_store_de_f_errors() {
+        let mut s = Store::<Fr>::default();
+        let expr = s.read("(1 . \"foo\")").unwrap();
+        s.hydrate_scalar_cache();
+        let (scalar_store, _) = ScalarStore::new_with_expr(&s, &expr);
+        let fs = scalar_store.ser_f().unwrap();
+
+        let truncated = &fs[..fs.len() - 1];
+        assert!(matches!(
+            ScalarStore::de_f(truncated),
+            Err(DeserializeError::UnexpectedEnd(_))
+        ));
+
+        let mut bad_flag = fs.clone();
+        bad_flag[3] = Fr::from(2);
+        assert_eq!(
+            Err(DeserializeError::InvalidPayload(0)),
+            ScalarStore::de_f(&bad_flag)
+        );
+
+        // A length which does not match the payload.
+        let mut bad_len = fs.clone();
+        bad_len[2] += Fr::from(1);
+        assert!(ScalarStore::de_f(&bad_len).is_err());
+        let mut bad_len = fs;
+        bad_len[2] = -Fr::from(1);
+        assert_eq!(
+            Err(DeserializeError::InvalidPayload(0)),
+            ScalarStore::de_f(&bad_len)
+        );
+    }
+
+    #[cfg(feature = "ipld")]
+    #[test]
+    fn test_expr_ipld() {
+        let test = |src| {
+            let mut store1 = Store::<Fr>::default();
+            let expr1 = store1.read(src).unwrap();
+            store1.hydrate_scalar_cache();
+
+            if let (scalar_store, Some(scalar_expr)) = ScalarStore::new_with_expr(&store1, &expr1) {
+                let ipld = to_ipld(scalar_store.clone()).unwrap();
+                let mut scalar_store2 = from_ipld(ipld).unwrap();
+                assert_eq!(scalar_store, scalar_store2);
+                if let Ok((mut store2, expr2)) = scalar_store2.to_store_with_expr(&scalar_expr) {
+                    store2.hydrate_scalar_cache();
+                    let (scalar_store3, _) = ScalarStore::new_with_expr(&store2, &expr2);
+                    assert_eq!(scalar_store2, scalar_store3)
+                } else {
+                    panic!()
+                }
+            } else {
+                panic!()
+            }
+        };
+
+        test("symbol");
+        test("1");
+        test("(1 . 2)");
+        test("\"foo\" . \"bar\")");
+        test("(foo . bar)");
+        test("(1 . 2)");
+        test("(+ 1 2 3)");
+        test("(+ 1 2 (* 3 4))");
+        test("(+ 1 2 (* 3 4) \"asdf\" )");
+        test("(+ 1 2 2 (* 3 4) \"asdf\" \"asdf\")");
+    }
+
+    #[cfg(feature = "ipld")]
+    #[test]
+    fn test_expr_eval_ipld() {
+        use crate::eval;
+        let test = |src| {
+            let mut s = Store::<Fr>::default();
+            let expr = s.read(src).unwrap();
+            s.hydrate_scalar_cache();
+
+            let env = empty_sym_env(&s);
+            let mut eval = eval::Evaluator::new(expr, env, &mut s, 100);
+            let (
+                eval::IO {
+                    expr,
+                    env: _,
+                    cont: _,
+                },
+                _lim,
+                _emitted,
+            ) = eval.eval().unwrap();
+            s.hydrate_scalar_cache();
+
+            let (scalar_store, _) = ScalarStore::new_with_expr(&s, &expr);
+            println!("{:?}", scalar_store);
+            let ipld = to_ipld(scalar_store.clone()).unwrap();
+            let scalar_store2 = from_ipld(ipld).unwrap();
+            println!("{:?}", scalar_store2);
+            assert_eq!(scalar_store, scalar_store2);
+        };
+
+        test("(let ((a 123)) (lambda (x) (+ x a)))");
+    }
+
+    #[test]
+    fn test_scalar_store() {
+        let test = |src, expected| {
+            let mut s = Store::<Fr>::default();
+            let expr = s.read(src).unwrap();
+            s.hydrate_scalar_cache();
+
+            let (scalar_store, _) = ScalarStore::new_with_expr(&s, &expr);
+            assert_eq!(expected, scalar_store.scalar_map.len());
+        };
+
+        // Four atoms, four conses (four-element list), and NIL.
+        test("symbol", 1);
+        test("(1 . 2)", 3);
+        test("(+ 1 2 3)", 9);
+        test("(+ 1 2 (* 3 4))", 14);
+        // String are handled.
+        test("(+ 1 2 (* 3 4) \"asdf\" )", 16);
+        // Duplicate strings or symbols appear only once.
+        test("(+ 1 2 2 (* 3 4) \"asdf\" \"asdf\")", 18);
+    }
+
+    #[test]
+    fn test_scalar_store_opaque_cons() {
+        let mut store = Store::<Fr>::default();
+
+        let num1 = store.num(123);
+        let num2 = store.num(987);
+        let cons = store.intern_cons(num1, num2);
+        let cons_hash = store.hash_expr(&cons).unwrap();
+        let opaque_cons = store.intern_maybe_opaque_cons(*cons_hash.value());
+
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &opaque_cons);
+        println!("{:?}", scalar_store.scalar_map);
+        println!("{:?}", scalar_store.scalar_map.len());
+        // If a non-opaque version has been found when interning opaque, children appear in `ScalarStore`.
+        assert_eq!(3, scalar_store.scalar_map.len());
+    }
+    #[test]
+    fn test_scalar_store_opaque_fun() {
+        let mut store = Store::<Fr>::default();
+
+        let arg = store.sym("A");
+        let body = store.num(123);
+        let empty_env = empty_sym_env(&store);
+        let fun = store.intern_fun(arg, body, empty_env);
+        let fun_hash = store.hash_expr(&fun).unwrap();
+        let opaque_fun = store.intern_maybe_opaque_fun(*fun_hash.value());
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &opaque_fun);
+        // If a non-opaque version has been found when interning opaque, children appear in `ScalarStore`.
+        assert_eq!(4, scalar_store.scalar_map.len());
+    }
+    #[test]
+    fn test_scalar_store_opaque_sym() {
+        let mut store = Store::<Fr>::default();
+
+        let sym = store.sym(&"sym");
+        let sym_hash = store.hash_expr(&sym).unwrap();
+        let opaque_sym = store.intern_maybe_opaque_sym(*sym_hash.value());
+
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &opaque_sym);
+        // Only the symbol's string itself, not all of its substrings, appears in `ScalarStore`.
+        assert_eq!(1, scalar_store.scalar_map.len());
+    }
+    #[test]
+    fn test_scalar_store_opaque_str() {
+        let mut store = Store::<Fr>::default();
+
+        let str = store.str(&"str");
+        let str_hash = store.hash_expr(&str).unwrap();
+        let opaque_str = store.intern_maybe_opaque_str(*str_hash.value());
+        // The string is known, so it is returned instead of an opaque one.
+        assert_eq!(str, opaque_str);
+
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &opaque_str);
+        // Only the string itself, not all of its substrings, appears in `ScalarStore`.
+        assert_eq!(1, scalar_store.scalar_map.len());
+        assert_eq!(
+            Some(&ScalarExpression::Str("str".into())),
+            scalar_store.get_expr(&str_hash)
+        );
+
+        let really_opaque_str = store.intern_opaque_str(*str_hash.value());
+        assert!(really_opaque_str.is_opaque());
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &really_opaque_str);
+        assert_eq!(1, scalar_store.scalar_map.len());
+        assert_eq!(Some(&None), scalar_store.scalar_map.get(&str_hash));
+    }
+
+    #[test]
+    fn test_scalar_store_short_strings() {
+        let mut store = Store::<Fr>::default();
+        let nil = store.nil();
+        store.hydrate_scalar_cache();
+        let scalar_nil = store.get_expr_hash(&nil).unwrap();
+
+        for s in ["", "a"] {
+            let str = store.str(s);
+            store.hydrate_scalar_cache();
+            let (mut scalar_store, scalar_str) = ScalarStore::new_with_expr(&store, &str);
+            let scalar_str = scalar_str.unwrap();
+            assert_eq!(Tag::Str.as_field::<Fr>(), *scalar_str.tag());
+            assert_ne!(scalar_nil, scalar_str);
+            assert_eq!(
+                Some(&ScalarExpression::Str(s.into())),
+                scalar_store.get_expr(&scalar_str)
+            );
+
+            // The tag prefix of each entry distinguishes the string from nil, even though the empty string hashes to
+            // zero and its packed bytes are also zero.
+            let ser = scalar_store.clone().ser_f().unwrap();
+            assert_eq!(Tag::Str.as_field::<Fr>(), ser[0]);
+            let de = ScalarStore::de_f(&ser).unwrap();
+            assert_eq!(scalar_store, de);
+
+            let (store2, str2) = scalar_store.to_store_with_expr(&scalar_str).unwrap();
+            assert_eq!(Some(crate::store::Expression::Str(s)), store2.fetch(&str2));
+
+            // An opaque string is still a string.
+            let opaque = store.intern_opaque_str(*scalar_str.value());
+            assert_eq!(Tag::Str, opaque.tag());
+            let (scalar_store, scalar_opaque) = ScalarStore::new_with_expr(&store, &opaque);
+            assert_eq!(Some(scalar_str), scalar_opaque);
+            assert_eq!(Some(&None), scalar_store.scalar_map.get(&scalar_str));
+        }
+        assert_eq!(
+            Fr::from(0),
+            *store
+                .get_expr_hash(&store.get_str("").unwrap())
+                .unwrap()
+                .value()
+        );
+    }
+    #[test]
+    fn test_scalar_store_opaque_comm() {
+        let mut store = Store::<Fr>::default();
+
+        let num = store.num(987);
+        let comm = store.intern_comm(Fr::from(123), num);
+        let comm_hash = store.hash_expr(&comm).unwrap();
+        let opaque_comm = store.intern_maybe_opaque_comm(*comm_hash.value());
+
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &opaque_comm);
+        println!("{:?}", scalar_store.scalar_map);
+        println!("{:?}", scalar_store.scalar_map.len());
+        // If a non-opaque version has been found when interning opaque, children appear in `ScalarStore`.
+        assert_eq!(2, scalar_store.scalar_map.len());
+    }
+    #[test]
+    fn test_scalar_store_thunk() {
+        let mut store = Store::<Fr>::default();
+
+        let num = store.num(123);
+        let outermost = store.intern_cont_outermost();
+        let thunk = store.intern_thunk(crate::store::Thunk {
+            value: num,
+            continuation: outermost,
+        });
+
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, scalar_ptr) = ScalarStore::new_with_expr(&store, &thunk);
+        let scalar_ptr = scalar_ptr.unwrap();
+        let value = store.get_expr_hash(&num).unwrap();
+        let continuation = store.get_cont_hash(&outermost).unwrap();
+
+        assert_eq!(
+            Some(&ScalarExpression::Thunk(ScalarThunk {
+                value,
+                continuation
+            })),
+            scalar_store.get_expr(&scalar_ptr)
+        );
+        // The thunk's value is reachable, so it appears in `ScalarStore` too.
+        assert_eq!(2, scalar_store.scalar_map.len());
+        assert_eq!(
+            Some(&ScalarExpression::Num(Fr::from(123))),
+            scalar_store.get_expr(&value)
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_thunk_children() {
+        let mut store = Store::<Fr>::default();
+
+        let num = store.num(123);
+        let outermost = store.intern_cont_outermost();
+        let emit = store.intern_cont_emit(outermost);
+        let thunk = store.intern_thunk(crate::store::Thunk {
+            value: num,
+            continuation: emit,
+        });
+
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &thunk);
+        let value = store.get_expr_hash(&num).unwrap();
+        let continuation = store.get_cont_hash(&emit).unwrap();
+        let outermost = store.get_cont_hash(&outermost).unwrap();
+
+        assert!(scalar_store.get_expr(&value).is_some());
+        assert_eq!(
+            Some(&ScalarContinuation::Emit {
+                continuation: outermost
+            }),
+            scalar_store.get_cont(&continuation)
+        );
+
+        let fs = scalar_store.clone().ser_f().unwrap();
+        assert_eq!(Ok(&scalar_store), ScalarStore::de_f(&fs).as_ref());
+
+        #[cfg(feature = "ipld")]
+        {
+            let ipld = to_ipld(scalar_store.clone()).unwrap();
+            let scalar_store2: ScalarStore<Fr> = from_ipld(ipld).unwrap();
+            assert_eq!(scalar_store, scalar_store2);
+        }
+    }
+
+    #[test]
+    fn test_scalar_store_new_with_io() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(let ((a 1) (b 2)) (+ a (* b 3)))").unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 10)
+            .get_frames()
+            .unwrap();
+        let io = frames.last().unwrap().output;
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, _, _, cont) = ScalarStore::new_with_io(&store, &io);
+        assert!(scalar_store.get_cont(&cont).is_some());
+        assert!(scalar_store.scalar_cont_map.len() > 1);
+
+        // Every pointer reachable from a continuation has been added.
+        for scalar_continuation in scalar_store.scalar_cont_map.values().flatten() {
+            let (scalar_ptrs, scalar_cont_ptrs) =
+                ScalarStore::child_scalar_cont_ptrs(scalar_continuation);
+            for scalar_ptr in scalar_ptrs {
+                assert!(scalar_store.scalar_map.contains_key(&scalar_ptr));
+            }
+            for scalar_cont_ptr in scalar_cont_ptrs {
+                assert!(scalar_store.get_cont(&scalar_cont_ptr).is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_scalar_store_io_snapshot() {
+        let mut store = Store::<Fr>::default();
+        let expr = store
+            .read("(letrec ((f (lambda (x) (if (= x 0) 1 (* x (f (- x 1))))))) (f 3))")
+            .unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 20)
+            .get_frames()
+            .unwrap();
+        let io = frames.last().unwrap().output;
+        store.hydrate_scalar_cache();
+
+        let (mut scalar_store, expr, env, cont) = ScalarStore::new_with_io(&store, &io);
+        assert_eq!(store.get_expr_hash(&io.expr), Some(expr));
+        assert_eq!(store.get_expr_hash(&io.env), Some(env));
+        assert_eq!(store.get_cont_hash(&io.cont), Some(cont));
+
+        let mut store2 = scalar_store.to_store().unwrap();
+        store2.hydrate_scalar_cache();
+
+        let io2 = IO {
+            expr: store2.fetch_scalar(&expr).unwrap(),
+            env: store2.fetch_scalar(&env).unwrap(),
+            cont: store2.fetch_scalar_cont(&cont).unwrap(),
+        };
+        let (scalar_store2, expr2, env2, cont2) = ScalarStore::new_with_io(&store2, &io2);
+        assert_eq!((expr, env, cont), (expr2, env2, cont2));
+        assert_eq!(scalar_store, scalar_store2);
+    }
+
+    #[test]
+    fn test_to_store_errors() {
+        let mut store = Store::<Fr>::default();
+        let one = store.num(1);
+        let two = store.num(2);
+        let cons = store.cons(one, two);
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, scalar_cons) = ScalarStore::new_with_expr(&store, &cons);
+        let scalar_cons = scalar_cons.unwrap();
+        let scalar_one = store.get_expr_hash(&one).unwrap();
+        assert!(scalar_store
+            .clone()
+            .to_store_with_expr(&scalar_cons)
+            .is_ok());
+
+        // A pointer which is not in the store.
+        let missing = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(3));
+        assert_eq!(
+            Some(ScalarStoreError::MissingPtr(uptr(&missing))),
+            scalar_store.clone().to_store_with_expr(&missing).err()
+        );
+
+        // A child which has been removed.
+        let mut dangling = scalar_store.clone();
+        dangling.scalar_map.remove(&scalar_one);
+        assert_eq!(
+            Some(ScalarStoreError::MissingChild(uptr(&scalar_one))),
+            dangling.to_store().err()
+        );
+
+        // A pointer whose tag is not a `Tag`.
+        let mut unknown = scalar_store.clone();
+        let bad = ScalarPtr::from_parts(Fr::from(987), Fr::from(3));
+        unknown.scalar_map.insert(bad, None);
+        assert_eq!(
+            Some(ScalarStoreError::UnknownTag(uptr(&bad))),
+            unknown.to_store().err()
+        );
+
+        // An expression which does not match its pointer's tag.
+        let mut mismatched = scalar_store;
+        let bad = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(3));
+        mismatched
+            .scalar_map
+            .insert(bad, Some(ScalarExpression::Num(Fr::from(3))));
+        assert_eq!(
+            Some(ScalarStoreError::InternFailed(uptr(&bad))),
+            mismatched.to_store().err()
+        );
+
+        // Pointers which have been added but not finalized.
+        let mut pending = ScalarStore::default();
+        pending.add_ptr(&store, &cons);
+        assert_eq!(Some(ScalarStoreError::Pending), pending.to_store().err());
+
+        // Decoding errors convert into `ScalarStoreError`.
+        let decode = || -> Result<ScalarStore<Fr>, ScalarStoreError<Fr>> {
+            Ok(ScalarStore::de_f(&[Fr::from(Tag::Nil as u64)])?)
+        };
+        assert_eq!(
+            Err(ScalarStoreError::Deserialize(
+                DeserializeError::UnexpectedEnd(1)
+            )),
+            decode()
+        );
+    }
+
+    #[test]
+    fn test_check_complete() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(+ 1 2 (* 3 4) \"asdf\")").unwrap();
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &expr);
+        assert_eq!(Ok(()), scalar_store.check_complete());
+
+        let three = store.num(3);
+        let scalar_three = store.get_expr_hash(&three).unwrap();
+        let mut incomplete = scalar_store;
+        assert!(incomplete.scalar_map.remove(&scalar_three).is_some());
+        assert_eq!(Err(vec![uptr(&scalar_three)]), incomplete.check_complete());
+
+        // Missing continuations are reported too.
+        let expr = store.read("(+ 1 2)").unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 1)
+            .get_frames()
+            .unwrap();
+        store.hydrate_scalar_cache();
+        let cont = frames[0].output.cont;
+        let mut scalar_store = ScalarStore::default();
+        let scalar_cont = scalar_store.add_one_cont_ptr(&store, &cont).unwrap();
+        assert_eq!(Ok(()), scalar_store.check_complete());
+        let outermost = store.intern_cont_outermost();
+        let outermost = store.get_cont_hash(&outermost).unwrap();
+        assert_ne!(scalar_cont, outermost);
+        assert!(scalar_store.scalar_cont_map.remove(&outermost).is_some());
+        assert_eq!(Err(vec![uptr(&outermost)]), scalar_store.check_complete());
+        assert_eq!(
+            Err(ScalarStoreError::MissingChild(uptr(&outermost))),
+            scalar_store.to_store().map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_iter() {
+        assert!(ScalarStore::<Fr>::default().is_empty());
+
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(1 . 2)").unwrap();
+        let env = empty_sym_env(&store);
+        let cont = store.intern_cont_outermost();
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, expr, _, cont) =
+            ScalarStore::new_with_io(&store, &IO { expr, env, cont });
+        assert!(!scalar_store.is_empty());
+        assert_eq!(
+            scalar_store.iter_exprs().count() + scalar_store.iter_conts().count(),
+            scalar_store.len()
+        );
+        assert!(scalar_store
+            .iter_exprs()
+            .any(|(ptr, e)| *ptr == expr && e == scalar_store.get_expr(&expr)));
+        assert_eq!(
+            vec![(&cont, Some(&ScalarContinuation::Outermost))],
+            scalar_store.iter_conts().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_merge() {
+        let mut store = Store::<Fr>::default();
+        let expr1 = store.read("(+ 1 2)").unwrap();
+        let expr2 = store.read("(* 3 4)").unwrap();
+        let expr3 = store.read("(+ 1 2 (* 3 4))").unwrap();
+        store.hydrate_scalar_cache();
+
+        let (scalar_store1, _) = ScalarStore::new_with_expr(&store, &expr1);
+        let (scalar_store2, _) = ScalarStore::new_with_expr(&store, &expr2);
+        let (scalar_store3, _) = ScalarStore::new_with_expr(&store, &expr3);
+
+        // Disjoint (apart from shared atoms) merge.
+        let mut merged = scalar_store1.clone();
+        merged.merge(&scalar_store2).unwrap();
+        assert!(merged.check_complete().is_ok());
+        for (ptr, expr) in scalar_store1.iter_exprs().chain(scalar_store2.iter_exprs()) {
+            assert_eq!(expr, merged.get_expr(ptr));
+        }
+
+        // Identical overlap.
+        let mut merged2 = merged.clone();
+        merged2.merge(&merged).unwrap();
+        assert_eq!(merged, merged2);
+
+        // Merging is order-independent.
+        let mut merged3 = scalar_store3.clone();
+        merged3.merge(&merged).unwrap();
+        merged.merge(&scalar_store3).unwrap();
+        assert_eq!(merged, merged3);
+
+        // Conflicting values for the same pointer.
+        let (ptr, _) = scalar_store1
+            .iter_exprs()
+            .find(|(_, expr)| matches!(expr, Some(ScalarExpression::Num(_))))
+            .unwrap();
+        let mut conflicting = ScalarStore::default();
+        conflicting
+            .scalar_map
+            .insert(*ptr, Some(ScalarExpression::Num(Fr::from(987))));
+        let mut before = scalar_store1.clone();
+        assert_eq!(Err(MergeConflict(uptr(ptr))), before.merge(&conflicting));
+        assert_eq!(scalar_store1, before);
+    }
+
+    #[test]
+    fn test_scalar_store_diff() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(+ 1 2 (* 3 4))").unwrap();
+        let sub_expr = store.read("(* 3 4)").unwrap();
+        let env = empty_sym_env(&store);
+        let cont = store.intern_cont_outermost();
+        let five = store.num(5);
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, _, _, _) = ScalarStore::new_with_io(&store, &IO { expr, env, cont });
+        assert!(scalar_store.diff(&scalar_store).is_empty());
+
+        // Mutate exactly one entry.
+        let five = store.get_expr_hash(&five).unwrap();
+        let three = store.num(3);
+        let three = store.get_expr_hash(&three).unwrap();
+        let mut mutated = scalar_store.clone();
+        mutated
+            .scalar_map
+            .insert(three, Some(ScalarExpression::Num(Fr::from(987))));
+        mutated.scalar_map.insert(five, None);
+        let cont = store.get_cont_hash(&cont).unwrap();
+        mutated
+            .scalar_cont_map
+            .insert(cont, Some(ScalarContinuation::Terminal));
+
+        let diff = scalar_store.diff(&mutated);
+        assert_eq!(Vec::<UPtr<Fr>>::new(), diff.only_in_self);
+        assert_eq!(vec![uptr(&five)], diff.only_in_other);
+        let mut differing = vec![uptr(&three), uptr(&cont)];
+        differing.sort();
+        assert_eq!(differing, diff.differing);
+
+        let (smaller, _) = ScalarStore::new_with_expr(&store, &sub_expr);
+        let diff = smaller.diff(&scalar_store);
+        assert!(diff.only_in_self.is_empty());
+        assert!(diff.differing.is_empty());
+        assert_eq!(scalar_store.len() - smaller.len(), diff.only_in_other.len());
+    }
+
+    #[test]
+    fn test_scalar_store_gc() {
+        let mut store = Store::<Fr>::default();
+        let expr1 = store.read("(+ 1 2 (* 3 4))").unwrap();
+        let expr2 = store.read("(lambda (x) (+ x 1))").unwrap();
+        let num = store.num(123);
+        let outermost = store.intern_cont_outermost();
+        let emit = store.intern_cont_emit(outermost);
+        let thunk = store.intern_thunk(crate::store::Thunk {
+            value: num,
+            continuation: emit,
+        });
+        store.hydrate_scalar_cache();
+
+        let (scalar_store1, scalar_expr1) = ScalarStore::new_with_expr(&store, &expr1);
+        let (scalar_store2, scalar_expr2) = ScalarStore::new_with_expr(&store, &expr2);
+        let (scalar_store3, scalar_thunk) = ScalarStore::new_with_expr(&store, &thunk);
+        let mut merged = scalar_store1.clone();
+        merged.merge(&scalar_store2).unwrap();
+        merged.merge(&scalar_store3).unwrap();
+
+        let mut collected = merged.clone();
+        collected.gc(&[scalar_expr1.unwrap()]);
+        assert_eq!(scalar_store1, collected);
+
+        let mut collected = merged.clone();
+        collected.gc(&[scalar_expr2.unwrap(), scalar_thunk.unwrap()]);
+        let mut expected = scalar_store2;
+        expected.merge(&scalar_store3).unwrap();
+        assert_eq!(expected, collected);
+
+        // Roots which are not present are ignored.
+        let mut collected = merged;
+        let missing = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(987));
+        collected.gc(&[missing]);
+        assert!(collected.is_empty());
+
+        // Cycles terminate.
+        let a = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(1));
+        let b = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(2));
+        let unreachable = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(3));
+        let mut cyclic = ScalarStore::default();
+        cyclic
+            .scalar_map
+            .insert(a, Some(ScalarExpression::Cons(b, a)));
+        cyclic
+            .scalar_map
+            .insert(b, Some(ScalarExpression::Cons(a, b)));
+        cyclic
+            .scalar_map
+            .insert(unreachable, Some(ScalarExpression::Num(Fr::from(3))));
+        cyclic.gc(&[a]);
+        assert_eq!(2, cyclic.len());
+        assert!(cyclic.get_expr(&unreachable).is_none());
+    }
+
+    #[test]
+    fn test_find_cycles() {
+        let mut store = Store::<Fr>::default();
+        let expr = store
+            .read("(letrec ((f (lambda (x) (if (= x 0) 1 (f (- x 1)))))) (f 3))")
+            .unwrap();
+        store.hydrate_scalar_cache();
+        let (mut scalar_store, _) = ScalarStore::new_with_expr(&store, &expr);
+        assert!(scalar_store.find_cycles().is_empty());
+
+        // A function whose closed environment binds a symbol to the function itself.
+        let ptr = |tag: Tag, n| ScalarPtr::from_parts(tag.as_field(), Fr::from(n));
+        let fun = ptr(Tag::Fun, 1);
+        let env = ptr(Tag::Cons, 2);
+        let binding = ptr(Tag::Cons, 3);
+        let sym = ptr(Tag::Sym, 4);
+        let nil = ptr(Tag::Nil, 5);
+        scalar_store.scalar_map.insert(
+            fun,
+            Some(ScalarExpression::Fun {
+                arg: sym,
+                body: sym,
+                closed_env: env,
+            }),
+        );
+        scalar_store
+            .scalar_map
+            .insert(env, Some(ScalarExpression::Cons(binding, nil)));
+        scalar_store
+            .scalar_map
+            .insert(binding, Some(ScalarExpression::Cons(sym, fun)));
+        scalar_store
+            .scalar_map
+            .insert(sym, Some(ScalarExpression::Sym("f".into())));
+        scalar_store
+            .scalar_map
+            .insert(nil, Some(ScalarExpression::Nil));
+
+        let cycles = scalar_store.find_cycles();
+        assert_eq!(1, cycles.len());
+        let cycle = &cycles[0];
+        assert_eq!(3, cycle.len());
+        for p in [fun, env, binding].iter() {
+            assert!(cycle.contains(p));
+        }
+
+        // A self-loop is a cycle of length one.
+        let mut self_loop = ScalarStore::default();
+        self_loop
+            .scalar_map
+            .insert(env, Some(ScalarExpression::Cons(env, env)));
+        assert_eq!(vec![vec![env]], self_loop.find_cycles());
+    }
+
+    #[test]
+    fn test_scalar_store_digest() {
+        let mut store = Store::<Fr>::default();
+        let expr1 = store.read("(+ 1 2 (* 3 4) \"asdf\")").unwrap();
+        let expr2 = store.read("(lambda (x) (+ x 1))").unwrap();
+        store.hydrate_scalar_cache();
+
+        let (scalar_store1, _) = ScalarStore::new_with_expr(&store, &expr1);
+        let (scalar_store2, _) = ScalarStore::new_with_expr(&store, &expr2);
+        assert_ne!(
+            scalar_store1.digest().unwrap(),
+            scalar_store2.digest().unwrap()
+        );
+
+        // Insertion order does not matter.
+        let mut merged1 = scalar_store1;
+        merged1.merge(&scalar_store2).unwrap();
+        let mut merged2 = ScalarStore::default();
+        for (ptr, expr) in merged1.iter_exprs().collect::<Vec<_>>().into_iter().rev() {
+            merged2.scalar_map.insert(*ptr, expr.cloned());
+        }
+        assert_eq!(merged1.digest().unwrap(), merged2.digest().unwrap());
+
+        // The digest survives a serialization round-trip.
+        let deserialized = ScalarStore::de_f(&merged1.clone().ser_f().unwrap()).unwrap();
+        assert_eq!(merged1.digest().unwrap(), deserialized.digest().unwrap());
+
+        // It folds the `ser_f` encoding in chunks of three, whatever the entry boundaries.
+        let constants = HashConstants::<Fr>::default();
+        let fs = merged1.clone().ser_f().unwrap();
+        let expected = fs.chunks(3).fold(Fr::from(fs.len() as u64), |acc, chunk| {
+            let mut preimage = [acc, Fr::zero(), Fr::zero(), Fr::zero()];
+            preimage[1..=chunk.len()].copy_from_slice(chunk);
+            Poseidon::new_with_preimage(&preimage, constants.c4()).hash()
+        });
+        assert_eq!(expected, merged1.digest().unwrap());
+
+        // Any value change is detected.
+        let (ptr, _) = merged1
+            .iter_exprs()
+            .find(|(_, expr)| matches!(expr, Some(ScalarExpression::Str(_))))
+            .unwrap();
+        let ptr = *ptr;
+        merged2
+            .scalar_map
+            .insert(ptr, Some(ScalarExpression::Str("asdg".into())));
+        assert_ne!(merged1.digest().unwrap(), merged2.digest().unwrap());
+        merged2.scalar_map.insert(ptr, None);
+        assert_ne!(merged1.digest().unwrap(), merged2.digest().unwrap());
+    }
+
+    #[test]
+    fn test_scalar_store_serialize_to() {
+        let mut store = Store::<Fr>::default();
+        let expr = store
+            .read("(let ((a 123)) (lambda (x) (+ x a \"asdf\")))")
+            .unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 5)
+            .get_frames()
+            .unwrap();
+        let io = frames.last().unwrap().output;
+        store.hydrate_scalar_cache();
+        let (scalar_store, _, _, _) = ScalarStore::new_with_io(&store, &io);
+
+        let mut bytes = Vec::new();
+        scalar_store.serialize_to(&mut bytes).unwrap();
+
+        let fs = scalar_store.clone().ser_f().unwrap();
+        let expected: Vec<u8> = fs
+            .iter()
+            .flat_map(|f| f.to_repr().as_ref().to_vec())
+            .collect();
+        assert_eq!(expected, bytes);
+
+        let deserialized = ScalarStore::deserialize_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(scalar_store, deserialized);
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert_eq!(
+            io::ErrorKind::UnexpectedEof,
+            ScalarStore::<Fr>::deserialize_from(&mut &truncated[..])
+                .unwrap_err()
+                .kind()
+        );
+        let truncated = &bytes[..bytes.len() - 32];
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            ScalarStore::<Fr>::deserialize_from(&mut &truncated[..])
+                .unwrap_err()
+                .kind()
+        );
+    }
+
+    #[quickcheck]
+    fn prop_scalar_store_bytes(x: TaggedScalarStore) -> bool {
+        let store = x.0;
+        ScalarStore::from_bytes(&store.to_bytes().unwrap()) == Ok(store)
+    }
+
+    #[test]
+    fn test_scalar_store_bytes_header() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(+ 1 2 (* 3 4) \"asdf\")").unwrap();
+        store.hydrate_scalar_cache();
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &expr);
+
+        let bytes = scalar_store.to_bytes().unwrap();
+        assert_eq!(b"LRKS", &bytes[..4]);
+        assert_eq!(Ok(scalar_store), ScalarStore::from_bytes(&bytes));
+
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert_eq!(
+            Err(DeserializeError::InvalidMagic),
+            ScalarStore::<Fr>::from_bytes(&bad)
+        );
+        let mut bad = bytes.clone();
+        bad[4] = 1;
+        assert_eq!(
+            Err(DeserializeError::UnsupportedVersion(1)),
+            ScalarStore::<Fr>::from_bytes(&bad)
+        );
+        assert_eq!(
+            Ok(ScalarStore::default()),
+            ScalarStore::<pasta_curves::pallas::Scalar>::from_bytes(
+                &ScalarStore::<pasta_curves::pallas::Scalar>::default()
+                    .to_bytes()
+                    .unwrap()
+            )
+        );
+        assert!(matches!(
+            ScalarStore::<pasta_curves::pallas::Scalar>::from_bytes(&bytes),
+            Err(DeserializeError::FieldMismatch(_))
+        ));
+        assert!(matches!(
+            ScalarStore::<Fr>::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DeserializeError::UnexpectedEnd(_))
+        ));
+        let mut bad = bytes;
+        let len = bad.len();
+        for b in &mut bad[len - 32..] {
+            *b = 0xff;
+        }
+        assert!(matches!(
+            ScalarStore::<Fr>::from_bytes(&bad),
+            Err(DeserializeError::InvalidFieldElement(_))
+        ));
+    }
+
+    #[cfg(feature = "ipld")]
+    #[test]
+    fn test_scalar_store_ipld_version() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(+ 1 2 (* 3 4) \"asdf\")").unwrap();
+        store.hydrate_scalar_cache();
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &expr);
+
+        // Structs are serialized as lists of their fields, the first of which is the version.
+        let ipld = to_ipld(scalar_store.clone()).unwrap();
+        let mut bumped = match ipld {
+            Ipld::List(fields) => fields,
+            _ => panic!("ScalarStore should serialize to a list"),
+        };
+        assert_eq!(Ipld::Integer(SCALAR_STORE_VERSION.into()), bumped[0]);
+        assert_eq!(scalar_store, from_ipld(Ipld::List(bumped.clone())).unwrap());
+
+        bumped[0] = Ipld::Integer((SCALAR_STORE_VERSION + 1).into());
+        let versioned: VersionedScalarStore<Fr> = from_ipld(Ipld::List(bumped.clone())).unwrap();
+        let mismatch = VersionMismatch {
+            expected: SCALAR_STORE_VERSION,
+            found: SCALAR_STORE_VERSION + 1,
+        };
+        assert_eq!(Err(mismatch.clone()), ScalarStore::try_from(versioned));
+
+        let err = from_ipld::<ScalarStore<Fr>>(Ipld::List(bumped)).unwrap_err();
+        assert!(err.to_string().contains(&mismatch.to_string()));
+    }
+
+    #[quickcheck]
+    fn prop_scalar_expression_shrink_terminates(x: ScalarExpression<Fr>) -> bool {
+        let mut current = x;
+        for _ in 0..1000 {
+            match current.shrink().next() {
+                Some(next) if next == current => return false,
+                Some(next) => current = next,
+                None => return current == ScalarExpression::Nil,
+            }
+        }
+        false
+    }
+
+    #[quickcheck]
+    fn prop_scalar_continuation_shrink_terminates(x: ScalarContinuation<Fr>) -> bool {
+        let mut current = x;
+        for _ in 0..1000 {
+            match current.shrink().next() {
+                Some(next) if next == current => return false,
+                Some(next) => current = next,
+                None => return current == ScalarContinuation::Outermost,
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn test_scalar_continuation_shrink_keeps_operator() {
+        let cont = ScalarContinuation::Binop2 {
+            operator: Op2::Sum,
+            evaled_arg: ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(7)),
+            continuation: ScalarContPtr::from_parts(ContTag::Tail.as_field(), Fr::from(9)),
+        };
+        let shrunk: Vec<_> = cont.shrink().collect();
+        assert_eq!(shrunk[0], ScalarContinuation::Outermost);
+        assert_eq!(shrunk.len(), 3);
+        for s in &shrunk[1..] {
+            match s {
+                ScalarContinuation::Binop2 { operator, .. } => assert_eq!(*operator, Op2::Sum),
+                _ => panic!("shrink changed the frame kind"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_uptr_conversions() {
+        let ptr = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(3));
+        let cont = ScalarContPtr::from_parts(ContTag::Tail.as_field(), Fr::from(3));
+
+        let u = UPtr::from(ptr);
+        assert_eq!(u, UPtr::new(Tag::Num.as_field(), Fr::from(3)));
+        assert_eq!(*u.tag(), Tag::Num.as_field::<Fr>());
+        assert_eq!(*u.value(), Fr::from(3));
+        assert!(!u.is_cont());
+
+        let c = UPtr::from(cont);
+        assert_eq!(*c.tag(), ContTag::Tail.as_field::<Fr>());
+        assert!(c.is_cont());
+        assert_ne!(u, c);
+    }
+
+    #[test]
+    fn test_scalar_store_ser_f_insertion_order() {
+        let sources = [
+            "(+ 1 2 (* 3 4))",
+            "\"a string which is too long to fit in a single field element\"",
+            "(let ((a 123)) (lambda (x) (+ x a)))",
+            "(cons #\\a 5u64)",
+        ];
+
+        let build = |order: &[usize]| {
+            let mut s = Store::<Fr>::default();
+            let exprs: Vec<_> = order.iter().map(|i| s.read(sources[*i]).unwrap()).collect();
+            s.hydrate_scalar_cache();
+
+            let mut scalar_store = ScalarStore::default();
+            for expr in &exprs {
+                scalar_store.add_ptr(&s, expr).unwrap();
+            }
+            scalar_store.finalize(&s);
+            scalar_store
+        };
+
+        let forward = build(&[0, 1, 2, 3]);
+        let backward = build(&[3, 2, 1, 0]);
+        let shuffled = build(&[2, 0, 3, 1]);
+
+        let fs = forward.clone().ser_f().unwrap();
+        assert_eq!(fs, backward.clone().ser_f().unwrap());
+        assert_eq!(fs, shuffled.clone().ser_f().unwrap());
+        assert_eq!(forward.to_bytes().unwrap(), backward.to_bytes().unwrap());
+        assert_eq!(forward.to_bytes().unwrap(), shuffled.to_bytes().unwrap());
+
+        // Entries are emitted in strictly increasing `UPtr` order.
+        let keys: Vec<_> = forward.ser_entries().map(|(uptr, _)| uptr).collect();
+        assert!(keys.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_scalar_store_opaque_round_trip() {
+        let cons_hash = {
+            let mut store = Store::<Fr>::default();
+            let num1 = store.num(123);
+            let num2 = store.num(987);
+            let cons = store.intern_cons(num1, num2);
+            store.hash_expr(&cons).unwrap()
+        };
+
+        // In a store which does not know the children of the cons, it is genuinely opaque.
+        let mut store = Store::<Fr>::default();
+        let opaque_cons = store.intern_opaque_cons(*cons_hash.value());
+        let num = store.num(123);
+        let outer = store.intern_cons(opaque_cons, num);
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, outer_hash) = ScalarStore::new_with_expr(&store, &outer);
+        let outer_hash = outer_hash.unwrap();
+        assert!(scalar_store.scalar_map.contains_key(&cons_hash));
+        assert_eq!(None, scalar_store.get_expr(&cons_hash));
+        assert_eq!(3, scalar_store.scalar_map.len());
+
+        let mut scalar_store = ScalarStore::de_f(&scalar_store.ser_f().unwrap()).unwrap();
+        let (mut new_store, new_outer) = scalar_store.to_store_with_expr(&outer_hash).unwrap();
+        let new_opaque = new_store
+            .intern_scalar_ptr(cons_hash, &scalar_store)
+            .unwrap();
+
+        assert!(new_opaque.is_opaque());
+        assert_eq!(Tag::Cons, new_opaque.tag());
+        assert_eq!(Some(cons_hash), new_store.hash_expr(&new_opaque));
+        assert_eq!(Some(outer_hash), new_store.hash_expr(&new_outer));
+        // The opaque pointer is interned once, and shared by every reference to it.
+        assert_eq!(new_opaque, new_store.car(&new_outer));
+    }
+
+    #[test]
+    fn test_scalar_store_status() {
+        let mut store = Store::<Fr>::default();
+        let num = store.num(123);
+        let opaque = store.intern_opaque_cons(Fr::from(456));
+        let cons = store.intern_cons(num, opaque);
+        let num_hash = store.hash_expr(&num).unwrap();
+        let opaque_hash = store.hash_expr(&opaque).unwrap();
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &cons);
+
+        assert_eq!(
+            PointerStatus::Known(&ScalarExpression::Num(Fr::from(123))),
+            scalar_store.status(&num_hash)
+        );
+        assert_eq!(PointerStatus::Opaque, scalar_store.status(&opaque_hash));
+        assert_eq!(None, scalar_store.get_expr(&opaque_hash));
+
+        let unknown = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(789));
+        assert_eq!(PointerStatus::Unknown, scalar_store.status(&unknown));
+        assert_eq!(None, scalar_store.get_expr(&unknown));
+    }
+
+    #[test]
+    fn test_scalar_store_builder() {
+        let mut s = Store::<Fr>::default();
+        let expr = s.read("(1 . \"foo\")").unwrap();
+        s.hydrate_scalar_cache();
+        let (scalar_store, root) = ScalarStore::new_with_expr(&s, &expr);
+        let root = root.unwrap();
+
+        // Inserting everything from an existing store rebuilds it exactly.
+        let mut builder = ScalarStoreBuilder::new();
+        for (ptr, expr) in scalar_store.iter_exprs() {
+            builder.insert_expr(*ptr, expr.unwrap().clone());
+        }
+        assert_eq!(Ok(scalar_store.clone()), builder.build());
+
+        let one = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(1));
+        let thunk_cont = ScalarContPtr::from_parts(ContTag::Outermost.as_field(), Fr::from(2));
+        let thunk = ScalarPtr::from_parts(Tag::Thunk.as_field(), Fr::from(3));
+        let mut builder = ScalarStoreBuilder::new();
+        builder
+            .insert_expr(root, scalar_store.get_expr(&root).unwrap().clone())
+            .insert_expr(
+                thunk,
+                ScalarExpression::Thunk(ScalarThunk {
+                    value: one,
+                    continuation: thunk_cont,
+                }),
+            );
+        let (car, cdr) = match scalar_store.get_expr(&root) {
+            Some(ScalarExpression::Cons(car, cdr)) => (*car, *cdr),
+            _ => unreachable!(),
+        };
+        let mut expected = vec![car.into(), cdr.into(), thunk_cont.into()];
+        expected.sort();
+        assert_eq!(Err(MissingChildren(expected)), builder.clone().build());
+
+        // Opaque entries satisfy references.
+        builder
+            .insert_opaque(car)
+            .insert_opaque(cdr)
+            .insert_cont(thunk_cont, ScalarContinuation::Outermost);
+        let built = builder.build().unwrap();
+        assert_eq!(PointerStatus::Opaque, built.status(&cdr));
+        assert_eq!(
+            Some(&ScalarContinuation::Outermost),
+            built.get_cont(&thunk_cont)
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_fold() {
+        let mut s = Store::<Fr>::default();
+        let expr = s.read("(+ 1 2 (* 3 4))").unwrap();
+        s.hydrate_scalar_cache();
+        let (scalar_store, root) = ScalarStore::new_with_expr(&s, &expr);
+        let root = root.unwrap();
+
+        let count_conses = |acc: usize, _: &ScalarPtr<Fr>, expr: &ScalarExpression<Fr>| {
+            if let ScalarExpression::Cons(..) = expr {
+                acc + 1
+            } else {
+                acc
+            }
+        };
+        // Four conses in the outer list and three in `(* 3 4)`.
+        assert_eq!(7, scalar_store.fold(&root, 0, count_conses));
+
+        // Children are visited before their parents, and the root last.
+        let order = scalar_store.fold(&root, Vec::new(), |mut acc, ptr, _| {
+            acc.push(*ptr);
+            acc
+        });
+        assert_eq!(Some(&root), order.last());
+        for (i, ptr) in order.iter().enumerate() {
+            for child in ScalarStore::child_scalar_ptrs(scalar_store.get_expr(ptr).unwrap())
+                .unwrap_or_default()
+            {
+                assert!(order[..i].contains(&child));
+            }
+        }
+        // Each expression is visited once, and the shared `nil` and symbols are not repeated.
+        let unique: BTreeSet<_> = order.iter().collect();
+        assert_eq!(unique.len(), order.len());
+
+        // A cycle does not prevent termination.
+        let a = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(1));
+        let b = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(2));
+        let mut builder = ScalarStoreBuilder::new();
+        builder
+            .insert_expr(a, ScalarExpression::Cons(b, b))
+            .insert_expr(b, ScalarExpression::Cons(a, a));
+        let cyclic = builder.build().unwrap();
+        assert_eq!(2, cyclic.fold(&a, 0, count_conses));
+    }
+
+    #[test]
+    fn test_scalar_store_collect_symbols() {
+        let mut s = Store::<Fr>::default();
+        let expr = s.read("(let ((a 1) (b 2)) (+ a b))").unwrap();
+        s.hydrate_scalar_cache();
+        let (scalar_store, root) = ScalarStore::new_with_expr(&s, &expr);
+
+        let expected: BTreeSet<String> = ["LET", "A", "B", "+"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(expected, scalar_store.collect_symbols(&root.unwrap()));
+
+        // Symbols in the body and closed environment of a function are found.
+        let limit = 10;
+        let env = empty_sym_env(&s);
+        let fun_src = s.read("(let ((x 9)) (lambda (y) (+ x y)))").unwrap();
+        let (output, _, _) = Evaluator::new(fun_src, env, &mut s, limit).eval().unwrap();
+        s.hydrate_scalar_cache();
+        let (scalar_store, root) = ScalarStore::new_with_expr(&s, &output.expr);
+        let root = root.unwrap();
+        assert!(matches!(
+            scalar_store.get_expr(&root),
+            Some(ScalarExpression::Fun { .. })
+        ));
+        let expected: BTreeSet<String> = ["X", "Y", "+"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(expected, scalar_store.collect_symbols(&root));
+    }
+
+    #[test]
+    fn test_scalar_store_depth_and_size() {
+        let test = |src, depth, size| {
+            let mut s = Store::<Fr>::default();
+            let expr = s.read(src).unwrap();
+            s.hydrate_scalar_cache();
+            let (scalar_store, root) = ScalarStore::new_with_expr(&s, &expr);
+            let root = root.unwrap();
+            assert_eq!(depth, scalar_store.depth(&root));
+            assert_eq!(size, scalar_store.size(&root));
+        };
+
+        test("1", 1, 1);
+        test("(1)", 2, 3);
+        test("(1 2 3)", 4, 7);
+        // The nested list adds two levels below its cons.
+        test("((1 2))", 4, 6);
+        // The repeated `1` and `nil` are counted once.
+        test("(1 1 (1))", 5, 6);
+
+        let unknown = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(1));
+        assert_eq!(0, ScalarStore::default().depth(&unknown));
+        assert_eq!(0, ScalarStore::default().size(&unknown));
+
+        // A cycle does not prevent termination.
+        let a = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(1));
+        let b = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(2));
+        let mut builder = ScalarStoreBuilder::new();
+        builder
+            .insert_expr(a, ScalarExpression::Cons(b, b))
+            .insert_expr(b, ScalarExpression::Cons(a, a));
+        let cyclic = builder.build().unwrap();
+        assert_eq!(2, cyclic.depth(&a));
+        assert_eq!(2, cyclic.size(&a));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_scalar_store_finalize_parallel() {
+        let test = |src| {
+            let mut s = Store::<Fr>::default();
+            let expr = s.read(src).unwrap();
+            let env = empty_sym_env(&s);
+            let (output, _, _) = Evaluator::new(expr, env, &mut s, 3).eval().unwrap();
+            s.hydrate_scalar_cache();
+
+            let mut serial = ScalarStore::default();
+            serial.add_ptr(&s, &expr).unwrap();
+            serial.add_cont_ptr(&s, &output.cont).unwrap();
+            let mut parallel = serial.clone();
+
+            serial.finalize(&s);
+            parallel.finalize_parallel(&s);
+            assert!(parallel.is_finalized());
+            assert_eq!(serial, parallel);
+        };
+
+        test("(+ 1 2 (* 3 4) \"asdf\")");
+        test("(let ((a 123)) (lambda (x) (+ x a)))");
+        test("(letrec ((f (lambda (x) (if (= x 0) 1 (f (- x 1)))))) (f 5))");
+
+        let mut s = Store::<Fr>::default();
+        let expr = s.read("(1 2 3)").unwrap();
+        let mut scalar_store = ScalarStore::default();
+        scalar_store
+            .pending_scalar_ptrs
+            .push(ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(1)));
+        assert_eq!(
+            Err(ScalarStoreError::NotHydrated),
+            scalar_store.try_finalize_parallel(&s)
+        );
+        assert!(scalar_store.scalar_map.is_empty());
+        s.hydrate_scalar_cache();
+        let (expected, root) = ScalarStore::new_with_expr(&s, &expr);
+        let mut scalar_store = ScalarStore::default();
+        scalar_store.pending_scalar_ptrs.push(root.unwrap());
+        assert_eq!(Ok(()), scalar_store.try_finalize_parallel(&s));
+        assert_eq!(expected, scalar_store);
+    }
+
+    #[test]
+    fn test_scalar_store_adjacency() {
+        let mut s = Store::<Fr>::default();
+        let expr = s.read("(let ((a 1) (b 2)) (+ a b))").unwrap();
+        s.hydrate_scalar_cache();
+        let (scalar_store, root) = ScalarStore::new_with_expr(&s, &expr);
+        let root = root.unwrap();
+
+        let adjacency = scalar_store.adjacency();
+        assert_eq!(scalar_store.scalar_map.len(), adjacency.len());
+        for (ptr, children) in &adjacency {
+            let expected = ScalarStore::child_scalar_ptrs(scalar_store.get_expr(ptr).unwrap())
+                .unwrap_or_default();
+            assert_eq!(&expected, children);
+        }
+
+        let count = |acc: usize, _: &ScalarPtr<Fr>, _: &ScalarExpression<Fr>| acc + 1;
+        assert_eq!(
+            scalar_store.fold(&root, 0, count),
+            scalar_store.fold_with_adjacency(&adjacency, &root, 0, count)
+        );
+        assert_eq!(
+            scalar_store.depth(&root),
+            scalar_store.depth_with_adjacency(&adjacency, &root)
+        );
+        assert_eq!(
+            scalar_store.size(&root),
+            scalar_store.size_with_adjacency(&adjacency, &root)
+        );
+
+        let a = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(1));
+        let b = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(2));
+        let mut builder = ScalarStoreBuilder::new();
+        builder
+            .insert_expr(a, ScalarExpression::Cons(b, b))
+            .insert_expr(b, ScalarExpression::Cons(a, a));
+        let cyclic = builder.build().unwrap();
+        assert_eq!(
+            cyclic.find_cycles(),
+            cyclic.find_cycles_with_adjacency(&cyclic.adjacency())
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_topo_order() {
+        let mut store = Store::<Fr>::default();
+        let num = store.num(123);
+        let nil = store.nil();
+        let left = store.intern_cons(num, nil);
+        let right = store.intern_cons(nil, num);
+        let root = store.intern_cons(left, right);
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, root_hash) = ScalarStore::new_with_expr(&store, &root);
+        let root_hash = root_hash.unwrap();
+        let hash = |ptr| store.hash_expr(&ptr).unwrap();
+
+        let order = scalar_store.topo_order(&root_hash).unwrap();
+        // The shared `num` and `nil` appear once.
+        assert_eq!(5, order.len());
+        assert_eq!(Some(&root_hash), order.last());
+        let position = |ptr| order.iter().position(|p| *p == hash(ptr)).unwrap();
+        assert!(position(num) < position(left));
+        assert!(position(num) < position(right));
+        assert!(position(nil) < position(left));
+        assert!(position(nil) < position(right));
+        assert!(position(left) < position(root));
+        assert!(position(right) < position(root));
+
+        let a = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(1));
+        let b = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(2));
+        let mut builder = ScalarStoreBuilder::new();
+        builder
+            .insert_expr(a, ScalarExpression::Cons(b, a))
+            .insert_expr(b, ScalarExpression::Nil);
+        let cyclic = builder.build().unwrap();
+        assert_eq!(Err(Cycle(vec![a])), cyclic.topo_order(&a));
+        assert_eq!(Ok(vec![b]), cyclic.topo_order(&b));
+    }
+
+    #[test]
+    fn test_scalar_expression_ord() {
+        let ptr = |tag: Tag, n: u64| ScalarPtr::from_parts(tag.as_field(), Fr::from(n));
+        let cont = ScalarContPtr::from_parts(ContTag::Outermost.as_field(), Fr::zero());
+
+        let sorted: Vec<ScalarExpression<Fr>> = vec![
+            ScalarExpression::Nil,
+            ScalarExpression::Cons(ptr(Tag::Num, 1), ptr(Tag::Num, 2)),
+            ScalarExpression::Cons(ptr(Tag::Num, 2), ptr(Tag::Num, 1)),
+            ScalarExpression::Comm(Fr::from(1), ptr(Tag::Num, 2)),
+            ScalarExpression::Sym("A".into()),
+            ScalarExpression::Sym("B".into()),
+            ScalarExpression::Fun {
+                arg: ptr(Tag::Sym, 1),
+                body: ptr(Tag::Num, 2),
+                closed_env: ptr(Tag::Nil, 0),
+            },
+            // `to_repr` is little-endian, so 256 sorts before 2.
+            ScalarExpression::Num(Fr::from(256)),
+            ScalarExpression::Num(Fr::from(2)),
+            ScalarExpression::Str("".into()),
+            ScalarExpression::Str("a".into()),
+            ScalarExpression::Thunk(ScalarThunk {
+                value: ptr(Tag::Num, 1),
+                continuation: cont,
+            }),
+            ScalarExpression::Char('a'),
+            ScalarExpression::Char('b'),
+            ScalarExpression::UInt(UInt::U64(0)),
+            ScalarExpression::UInt(UInt::U64(7)),
+        ];
+
+        let mut shuffled = sorted.clone();
+        shuffled.reverse();
+        shuffled.swap(0, 7);
+        shuffled.swap(3, 12);
+        shuffled.sort();
+        assert_eq!(sorted, shuffled);
+    }
+
+    #[quickcheck]
+    fn prop_scalar_expression_ord_consistent_with_eq(
+        x: ScalarExpression<Fr>,
+        y: ScalarExpression<Fr>,
+    ) -> bool {
+        (x.cmp(&y) == core::cmp::Ordering::Equal) == (x == y) && x.cmp(&y) == y.cmp(&x).reverse()
+    }
+
+    #[test]
+    fn test_scalar_store_to_source() {
+        let test = |src, expected: &str| {
+            let mut s = Store::<Fr>::default();
+            let expr = s.read(src).unwrap();
+            s.hydrate_scalar_cache();
+            let (scalar_store, root) = ScalarStore::new_with_expr(&s, &expr);
+            let source = scalar_store.to_source(&root.unwrap()).unwrap();
+            assert_eq!(expected, source);
+            // The rendering agrees with that of the `Store`.
+            assert_eq!(crate::writer::Write::fmt_to_string(&expr, &s), source);
+        };
+
+        test("(+ 1 2 (* 3 4))", "(+ 1 2 (* 3 4))");
+        test("(1 . 2)", "(1 . 2)");
+        test("(1 2 . 3)", "(1 2 . 3)");
+        test("(\"asdf\" #\\a 5u64 nil)", "(\"asdf\" #\\a 5u64 NIL)");
+        test("(|a b| c)", "(|a b| C)");
+
+        let mut store = Store::<Fr>::default();
+        let opaque = store.intern_opaque_cons(Fr::from(255));
+        let num = store.num(1);
+        let cons = store.intern_cons(num, opaque);
+        store.hydrate_scalar_cache();
+        let (scalar_store, root) = ScalarStore::new_with_expr(&store, &cons);
+        assert_eq!(
+            format!("(1 . <opaque:{}>)", crate::Num::Scalar(Fr::from(255))),
+            scalar_store.to_source(&root.unwrap()).unwrap()
+        );
+
+        let unknown = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(77));
+        assert_eq!(None, scalar_store.to_source(&unknown));
+    }
+
+    #[test]
+    fn test_scalar_store_to_dot() {
+        let mut store = Store::<Fr>::default();
+        let num = store.num(123);
+        let shared = store.intern_cons(num, num);
+        let root = store.intern_cons(shared, shared);
+        let outermost = store.intern_cont_outermost();
+        let thunk = store.intern_thunk(crate::store::Thunk {
+            value: root,
+            continuation: outermost,
+        });
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, root_hash) = ScalarStore::new_with_expr(&store, &root);
+        let dot = scalar_store.to_dot(&root_hash.unwrap());
+        assert!(dot.starts_with("digraph {\n"));
+        assert!(dot.ends_with("}\n"));
+        // One node each for the root, the shared cons and the num, which have two edges each into them.
+        assert_eq!(3, dot.matches("[label=").count());
+        assert_eq!(4, dot.matches(" -> ").count());
+        assert_eq!(1, dot.matches("label=\"Num ").count());
+        assert_eq!(2, dot.matches("label=\"Cons ").count());
+
+        let (scalar_store, thunk_hash) = ScalarStore::new_with_expr(&store, &thunk);
+        let dot = scalar_store.to_dot(&thunk_hash.unwrap());
+        assert_eq!(5, dot.matches("[label=").count());
+        assert_eq!(1, dot.matches("shape=box, style=dashed").count());
+        assert_eq!(1, dot.matches("label=\"Outermost ").count());
+    }
+
+    #[test]
+    fn test_scalar_store_new_with_exprs() {
+        let mut s = Store::<Fr>::default();
+        let expr1 = s.read("(+ 1 (* 3 4))").unwrap();
+        let expr2 = s.read("(- 2 (* 3 4))").unwrap();
+        s.hydrate_scalar_cache();
+
+        let (store1, root1) = ScalarStore::new_with_expr(&s, &expr1);
+        let (store2, root2) = ScalarStore::new_with_expr(&s, &expr2);
+        let (shared, roots) = ScalarStore::new_with_exprs(&s, &[expr1, expr2]);
+
+        assert_eq!(vec![root1, root2], roots);
+        assert!(shared.len() < store1.len() + store2.len());
+
+        let mut merged = store1;
+        merged.merge(&store2).unwrap();
+        assert_eq!(merged, shared);
+
+        let (empty, roots) = ScalarStore::new_with_exprs(&s, &[]);
+        assert!(empty.is_empty());
+        assert!(roots.is_empty());
+    }
+
+    #[test]
+    fn test_scalar_store_step() {
+        let mut s = Store::<Fr>::default();
+        let expr = s.read("(let ((a 123)) (lambda (x) (+ x a)))").unwrap();
+        s.hydrate_scalar_cache();
+        let (finalized, _) = ScalarStore::new_with_expr(&s, &expr);
+
+        let mut scalar_store = ScalarStore::default();
+        scalar_store.add_ptr(&s, &expr).unwrap();
+        assert!(!scalar_store.is_finalized());
+
+        let mut steps = 0;
+        loop {
+            let consumed = scalar_store.step(&s, 3);
+            assert!(consumed <= 3);
+            if consumed < 3 {
+                break;
+            }
+            steps += 1;
+        }
+        assert!(steps > 1);
+        assert!(scalar_store.is_finalized());
+        assert_eq!(0, scalar_store.step(&s, 3));
+        assert_eq!(finalized, scalar_store);
+    }
+
+    #[test]
+    fn test_small_string_from_f() {
+        let round_trip = |s: &str| {
+            let f = small_string_to_f::<Fr>(s).unwrap();
+            assert_eq!(Ok(s.to_string()), small_string_from_f(f));
+        };
+        round_trip("foo");
+        round_trip("");
+        round_trip("λ");
+        let max = "x".repeat(string_chunk_len::<Fr>());
+        round_trip(&max);
+        assert_eq!(
+            Err(ScalarStoreError::StringTooLong(max.len() + 1)),
+            small_string_to_f::<Fr>(&format!("{}x", max))
+        );
+
+        // Invalid UTF-8.
+        let mut repr = Fr::zero().to_repr();
+        repr.as_mut()[0] = 0xff;
+        assert_eq!(
+            Err(ScalarStoreError::InvalidUtf8),
+            small_string_from_f(Fr::from_repr(repr).unwrap())
+        );
+        // Bytes beyond the capacity of a small string.
+        assert_eq!(
+            Err(ScalarStoreError::InvalidString(-Fr::one())),
+            small_string_from_f(-Fr::one())
+        );
+    }
+
+    #[test]
+    fn test_char_from_f() {
+        for c in ['a', 'λ', '🦀', '\0', char::MAX] {
+            assert_eq!(Ok(c), char_from_f(char_to_f::<Fr>(c).unwrap()));
+        }
+
+        // Surrogates and values above `char::MAX` are not chars.
+        for f in [Fr::from(0xd800), Fr::from(0x110000)] {
+            assert_eq!(Err(ScalarStoreError::InvalidChar(f)), char_from_f(f));
+        }
+        // Nor is anything with bytes beyond the first four.
+        let f = Fr::from(1 << 32);
+        assert_eq!(Err(ScalarStoreError::InvalidChar(f)), char_from_f(f));
+    }
+
+    #[test]
+    fn test_char_to_f_layout() {
+        for code_point in 0..=0xffff {
+            if let Some(c) = char::from_u32(code_point) {
+                let f = char_to_f::<Fr>(c).unwrap();
+                assert_eq!(Fr::from(code_point as u64), f);
+                assert_eq!(&code_point.to_le_bytes(), &f.to_repr().as_ref()[..4]);
+                assert_eq!(Ok(c), char_from_f(f));
+            }
+        }
+
+        // The encoding agrees with the value of a `Char` in a `Store`.
+        let store = Store::<Fr>::default();
+        let c = store.get_char('λ');
+        let scalar_ptr = store.hash_expr(&c).unwrap();
+        assert_eq!(char_to_f::<Fr>('λ').unwrap(), *scalar_ptr.value());
+    }
+
+    #[test]
+    fn test_small_string_to_f_capacity() {
+        // BLS12-381 scalars have a capacity of 254 bits, so 31 whole bytes fit.
+        let max = string_chunk_len::<Fr>();
+        assert_eq!(31, max);
+
+        for len in [max - 1, max] {
+            let s = "z".repeat(len);
+            let f = small_string_to_f::<Fr>(&s).unwrap();
+            assert_eq!(Ok(s), small_string_from_f(f));
+            // The top byte of the repr, which would overflow the field, is never used.
+            assert_eq!(0, f.to_repr().as_ref()[max]);
+        }
+        assert_eq!(
+            Err(ScalarStoreError::StringTooLong(max + 1)),
+            small_string_to_f::<Fr>(&"z".repeat(max + 1))
+        );
+
+        // A multi-byte char counts all of its bytes.
+        let s = format!("{}λ", "z".repeat(max - 2));
+        assert_eq!(max, s.len());
+        assert_eq!(
+            Ok(s.clone()),
+            small_string_from_f(small_string_to_f::<Fr>(&s).unwrap())
+        );
+        assert_eq!(
+            Err(ScalarStoreError::StringTooLong(max + 1)),
+            small_string_to_f::<Fr>(&format!("z{}", s))
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_verify_commitments() {
+        let mut store = Store::<Fr>::default();
+        let num = store.num(987);
+        let comm = store.intern_comm(Fr::from(123), num);
+        let cons = store.intern_cons(comm, num);
+        store.hydrate_scalar_cache();
+
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &cons);
+        let comm_hash = store.hash_expr(&comm).unwrap();
+        assert!(matches!(
+            scalar_store.get_expr(&comm_hash),
+            Some(ScalarExpression::Comm(..))
+        ));
+        assert_eq!(Ok(()), scalar_store.verify_commitments());
+
+        // Tamper with the secret.
+        let mut tampered = scalar_store.clone();
+        let payload = store.hash_expr(&num).unwrap();
+        tampered.scalar_map.insert(
+            comm_hash,
+            Some(ScalarExpression::Comm(Fr::from(124), payload)),
+        );
+        assert_eq!(Err(vec![comm_hash]), tampered.verify_commitments());
+
+        // Opaque commitments are not checked.
+        let mut opaque = scalar_store;
+        opaque.scalar_map.insert(comm_hash, None);
+        assert_eq!(Ok(()), opaque.verify_commitments());
+    }
+
+    #[test]
+    fn test_scalar_store_redact_secrets() {
+        let mut store = Store::<Fr>::default();
+        let secret = Fr::from(123);
+        let num = store.num(987);
+        let comm = store.intern_comm(secret, num);
+        let cons = store.intern_cons(comm, num);
+        store.hydrate_scalar_cache();
+
+        let (mut scalar_store, root) = ScalarStore::new_with_expr(&store, &cons);
+        let root = root.unwrap();
+        let comm_hash = store.hash_expr(&comm).unwrap();
+        let payload = store.hash_expr(&num).unwrap();
+        let len = scalar_store.len();
+
+        scalar_store.redact_secrets();
+        assert_eq!(PointerStatus::Opaque, scalar_store.status(&comm_hash));
+        assert_eq!(len, scalar_store.len());
+        assert!(scalar_store.get_expr(&payload).is_some());
+        // The secret appears nowhere in the serialized store.
+        assert!(!scalar_store.clone().ser_f().unwrap().contains(&secret));
+
+        // The commitment still opens to the known secret and payload.
+        assert_eq!(*comm_hash.value(), store.commitment_hash(secret, payload));
+
+        // Interning treats the commitment as opaque.
+        let (new_store, new_root) = scalar_store.to_store_with_expr(&root).unwrap();
+        let new_comm = new_store.car(&new_root);
+        assert!(new_comm.is_opaque());
+        assert_eq!(Some(comm_hash), new_store.hash_expr(&new_comm));
+    }
+
+    #[test]
+    fn test_scalar_store_from_source() {
+        let src = "(let ((a 123)) (lambda (x) (+ x a)))";
+        let (scalar_store, root) = ScalarStore::<Fr>::from_source(src).unwrap();
+
+        let mut s = Store::<Fr>::default();
+        let expr = s.read(src).unwrap();
+        s.hydrate_scalar_cache();
+        let (expected, expected_root) = ScalarStore::new_with_expr(&s, &expr);
+        assert_eq!(expected, scalar_store);
+        assert_eq!(expected_root, Some(root));
+        assert_eq!(Some(src.to_uppercase()), scalar_store.to_source(&root));
+
+        assert!(matches!(
+            ScalarStore::<Fr>::from_source("  "),
+            Err(ParserError::NoInput)
+        ));
+    }
+
+    #[test]
+    fn test_scalar_store_not_hydrated() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(1 (2 . \"three\") four)").unwrap();
+        assert!(!store.is_hydrated());
+
+        let mut scalar_store = ScalarStore::default();
+        scalar_store.add_ptr(&store, &expr).unwrap();
+        assert_eq!(
+            Err(ScalarStoreError::NotHydrated),
+            scalar_store.try_finalize(&store)
+        );
+        assert!(!scalar_store.is_finalized());
+
+        store.hydrate_scalar_cache();
+        assert!(store.is_hydrated());
+        assert_eq!(Ok(()), scalar_store.try_finalize(&store));
+        assert!(scalar_store.is_finalized());
+        assert_eq!(Ok(()), scalar_store.check_complete());
+    }
+
+    #[test]
+    fn test_scalar_store_try_constructors_not_hydrated() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(1 (2 . \"three\") four)").unwrap();
+        let env = empty_sym_env(&store);
+        let io = IO {
+            expr,
+            env,
+            cont: store.intern_cont_outermost(),
+        };
+        assert!(!store.is_hydrated());
+
+        let not_hydrated = Err(ScalarStoreError::NotHydrated);
+        assert_eq!(
+            not_hydrated,
+            ScalarStore::try_new_with_expr(&store, &expr).map(|_| ())
+        );
+        assert_eq!(
+            not_hydrated,
+            ScalarStore::try_new_with_exprs(&store, &[expr, env]).map(|_| ())
+        );
+        assert_eq!(
+            not_hydrated,
+            ScalarStore::try_new_with_io(&store, &io).map(|_| ())
+        );
+        let mut scalar_store = ScalarStore::default();
+        assert_eq!(
+            not_hydrated,
+            scalar_store.try_add_one_ptr(&store, &expr).map(|_| ())
+        );
+        assert_eq!(
+            not_hydrated,
+            scalar_store
+                .try_add_one_cont_ptr(&store, &io.cont)
+                .map(|_| ())
+        );
+
+        store.hydrate_scalar_cache();
+        let (expected, root) = ScalarStore::new_with_expr(&store, &expr);
+        assert_eq!(
+            Ok((expected, root)),
+            ScalarStore::try_new_with_expr(&store, &expr)
+        );
+        assert!(ScalarStore::try_new_with_exprs(&store, &[expr, env]).is_ok());
+        assert!(ScalarStore::try_new_with_io(&store, &io).is_ok());
+        let mut scalar_store = ScalarStore::default();
+        assert_eq!(Ok(root), scalar_store.try_add_one_ptr(&store, &expr));
+        assert!(scalar_store
+            .try_add_one_cont_ptr(&store, &io.cont)
+            .unwrap()
+            .is_some());
+
+        // A pointer which `store` cannot hash, here one from another store, is reported rather than panicking.
+        let mut other = Store::<Fr>::default();
+        let foreign = other.read("(1 2 3 4 5 6 7 8 9 10 11 12)").unwrap();
+        let io = IO {
+            expr: foreign,
+            ..io
+        };
+        assert_eq!(
+            Err(ScalarStoreError::Unhashed("expression")),
+            ScalarStore::try_new_with_io(&store, &io).map(|_| ())
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_scalar_store_new_with_expr_not_hydrated() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(1 . 2)").unwrap();
+        ScalarStore::new_with_expr(&store, &expr);
+    }
+
+    #[test]
+    fn test_scalar_store_num_u64_round_trip() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(+ 1 2 0x1000000000000000000000000)").unwrap();
+        store.hydrate_scalar_cache();
+
+        let (mut scalar_store, root) = ScalarStore::new_with_expr(&store, &expr);
+        let (store2, expr2) = scalar_store.to_store_with_expr(&root.unwrap()).unwrap();
+
+        let mut nums = Vec::new();
+        let mut rest = store2.cdr(&expr2);
+        while !rest.is_nil() {
+            nums.push(*store2.fetch_num(&store2.car(&rest)).unwrap());
+            rest = store2.cdr(&rest);
+        }
+
+        assert_eq!(Num::U64(1), nums[0]);
+        assert_eq!(Num::U64(2), nums[1]);
+        assert!(matches!(nums[2], Num::Scalar(_)));
+        assert_eq!(
+            store.fetch_num(&store.car(&store.cdr(&expr))),
+            store2.fetch_num(&store2.car(&store2.cdr(&expr2)))
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_validate() {
+        let (scalar_store, _) =
+            ScalarStore::<Fr>::from_source("(cons \"a\" (lambda (x) 0x1234567890abcdef0))")
+                .unwrap();
+        assert_eq!(Ok(()), scalar_store.validate());
+
+        // A non-canonical repr injected into serialized bytes never reaches a `ScalarStore`.
+        let mut bytes = scalar_store.to_bytes().unwrap();
+        let header_len = BYTES_MAGIC.len() + 1 + 8;
+        let repr_len = Fr::zero().to_repr().as_ref().len();
+        bytes[header_len + repr_len..header_len + 2 * repr_len].fill(0xff);
+        assert_eq!(
+            Err(DeserializeError::InvalidFieldElement(1)),
+            ScalarStore::<Fr>::from_bytes(&bytes)
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_equiv() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(1 (2 3) 4)").unwrap();
+        let inner = store.read("(2 3)").unwrap();
+        let other = store.read("(1 (2 3) 5)").unwrap();
+        let unrelated = store.num(987);
+        store.hydrate_scalar_cache();
+
+        let (full, root) = ScalarStore::new_with_expr(&store, &expr);
+        let root = root.unwrap();
+        let inner = store.get_expr_hash(&inner).unwrap();
+
+        // Opaque versus expanded.
+        let mut partial = full.clone();
+        partial.scalar_map.insert(inner, None);
+        partial.gc(&[root]);
+        assert_ne!(full, partial);
+        assert!(full.equiv(&partial));
+        assert!(partial.equiv(&full));
+        assert!(full.equiv(&full));
+
+        // Different content.
+        let (other, _) = ScalarStore::new_with_expr(&store, &other);
+        assert!(!full.equiv(&other));
+        assert!(!partial.equiv(&other));
+
+        // Conflicting values for the same pointer.
+        let mut conflicting = full.clone();
+        conflicting
+            .scalar_map
+            .insert(inner, Some(ScalarExpression::Num(Fr::from(987))));
+        assert!(!full.equiv(&conflicting));
+
+        // An extra entry not hidden behind an opaque pointer.
+        let mut extra = full.clone();
+        extra.add_one_ptr(&store, &unrelated);
+        assert!(!full.equiv(&extra));
+        assert!(!extra.equiv(&partial));
+    }
+
+    #[test]
+    fn test_scalar_store_hydrate_from() {
+        let mut store = Store::<Fr>::default();
+        let num1 = store.num(123);
+        let num2 = store.num(987);
+        let cons = store.intern_cons(num1, num2);
+        let num3 = store.num(5);
+        let outer = store.intern_cons(cons, num3);
+        store.hydrate_scalar_cache();
+        let cons_hash = store.get_expr_hash(&cons).unwrap();
+        let (complete, _) = ScalarStore::new_with_expr(&store, &cons);
+        let (expected, _) = ScalarStore::new_with_expr(&store, &outer);
+
+        // Build the partial store in a separate `Store`, which knows only the hash of `cons`.
+        let mut partial_store = Store::<Fr>::default();
+        let opaque_cons = partial_store.intern_maybe_opaque_cons(*cons_hash.value());
+        let num3 = partial_store.num(5);
+        let partial_outer = partial_store.intern_cons(opaque_cons, num3);
+        partial_store.hydrate_scalar_cache();
+        let (mut partial, _) = ScalarStore::new_with_expr(&partial_store, &partial_outer);
+        assert_eq!(PointerStatus::Opaque, partial.status(&cons_hash));
+
+        partial.hydrate_from(&complete).unwrap();
+        assert_eq!(expected, partial);
+        assert_eq!(Ok(()), partial.check_complete());
+
+        // A different value for the same pointer is a conflict, and leaves the store unchanged.
+        let (mut partial, _) = ScalarStore::new_with_expr(&partial_store, &partial_outer);
+        let before = partial.clone();
+        let num3_hash = partial_store.get_expr_hash(&num3).unwrap();
+        let mut conflicting = complete.clone();
+        conflicting
+            .scalar_map
+            .insert(num3_hash, Some(ScalarExpression::Num(Fr::from(6))));
+        assert_eq!(
+            Err(MergeConflict(uptr(&num3_hash))),
+            partial.hydrate_from(&conflicting)
+        );
+        assert_eq!(before, partial);
+    }
+
+    #[test]
+    fn test_child_scalar_cont_ptrs_binop() {
+        let saved_env = ScalarPtr::from_parts(Fr::from(Tag::Cons as u64), Fr::from(1));
+        let unevaled_args = ScalarPtr::from_parts(Fr::from(Tag::Cons as u64), Fr::from(2));
+        let continuation =
+            ScalarContPtr::from_parts(Fr::from(ContTag::Outermost as u64), Fr::from(3));
+        let binop = ScalarContinuation::Binop {
+            operator: Op2::Sum,
+            saved_env,
+            unevaled_args,
+            continuation,
+        };
+
+        // The operator is not a pointer; the other three fields are all reported.
+        assert_eq!(
+            (vec![saved_env, unevaled_args], vec![continuation]),
+            ScalarStore::child_scalar_cont_ptrs(&binop)
+        );
+    }
+
+    #[quickcheck]
+    fn prop_tag_arity(x: ScalarExpression<Fr>) -> bool {
+        let chunks = match &x {
+            ScalarExpression::Sym(s) | ScalarExpression::Str(s) => {
+                (s.len() + string_chunk_len::<Fr>() - 1) / string_chunk_len::<Fr>()
+            }
+            _ => 0,
+        };
+        x.ser_f().unwrap().len() == tag_arity(expression_tag(&x)) + chunks
+    }
+
+    #[quickcheck]
+    fn prop_cont_tag_arity(x: ScalarContinuation<Fr>) -> bool {
+        x.ser_f().len() == cont_tag_arity(continuation_tag(&x))
+    }
+
+    #[cfg(feature = "ipld")]
+    #[test]
+    fn test_scalar_store_to_cid() {
+        let (scalar_store, _) = ScalarStore::<Fr>::from_source("(cons 1 (quote sym))").unwrap();
+        let (other, _) = ScalarStore::<Fr>::from_source("(cons 2 (quote sym))").unwrap();
+
+        let (again, _) = ScalarStore::<Fr>::from_source("(cons 1 (quote sym))").unwrap();
+
+        let cid = scalar_store.to_cid(None).unwrap();
+        assert_eq!(cid, again.to_cid(None).unwrap());
+        assert_eq!(cid, scalar_store.to_cid(Some(DEFAULT_CID_CODE)).unwrap());
+        assert_eq!(DEFAULT_CID_CODE, cid.hash().code());
+        assert_ne!(cid, other.to_cid(None).unwrap());
+
+        // SHA2-256.
+        let sha2_cid = scalar_store.to_cid(Some(0x12)).unwrap();
+        assert_eq!(0x12, sha2_cid.hash().code());
+        assert_ne!(cid, sha2_cid);
+
+        assert_eq!(
+            Err(CidError::UnsupportedCode(0xdead)),
+            scalar_store.to_cid(Some(0xdead))
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_add_one_ptr_reporting() {
+        let mut store = Store::<Fr>::default();
+        let expr1 = store.read("(1 2)").unwrap();
+        let expr2 = store.read("(0 1 2)").unwrap();
+        let zero = store.num(0);
+        store.hydrate_scalar_cache();
+
+        let mut scalar_store = ScalarStore::default();
+        let (root1, inserted) = scalar_store.add_one_ptr_reporting(&store, &expr1);
+        assert_eq!(root1, store.get_expr_hash(&expr1));
+        assert_eq!(Some(&root1.unwrap()), inserted.first());
+        assert_eq!(
+            scalar_store
+                .scalar_map
+                .keys()
+                .copied()
+                .collect::<BTreeSet<_>>(),
+            inserted.iter().copied().collect::<BTreeSet<_>>()
+        );
+        assert_eq!(scalar_store.scalar_map.len(), inserted.len());
+
+        // Only the new cons and `0` are inserted; the tail `(1 2)` was already present.
+        let (root2, inserted) = scalar_store.add_one_ptr_reporting(&store, &expr2);
+        assert_eq!(
+            vec![root2.unwrap(), store.get_expr_hash(&zero).unwrap()],
+            inserted
+        );
+
+        let (root3, inserted) = scalar_store.add_one_ptr_reporting(&store, &expr2);
+        assert_eq!(root2, root3);
+        assert!(inserted.is_empty());
+    }
+
+    #[cfg(feature = "ipld")]
+    #[test]
+    fn test_scalar_store_ipld_bytes() {
+        let (scalar_store, _) = ScalarStore::<Fr>::from_source("(cons 1 (quote sym))").unwrap();
+        let (again, _) = ScalarStore::<Fr>::from_source("(cons 1 (quote sym))").unwrap();
+
+        let bytes = scalar_store.to_ipld_bytes().unwrap();
+        assert_eq!(bytes, again.to_ipld_bytes().unwrap());
+        // The CID of the bytes pins the encoding across runs and platforms.
+        assert_eq!(
+            "bafyr4ie3vtbgs474ocstvl372lj7ptg75o7ibyba2lxv2ehac6a7dxy7bu",
+            scalar_store.to_cid(None).unwrap().to_string()
+        );
+        assert_eq!(Ok(scalar_store), ScalarStore::from_ipld_bytes(&bytes));
+
+        assert!(matches!(
+            ScalarStore::<Fr>::from_ipld_bytes(&bytes[1..]),
+            Err(IpldError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn test_scalar_store_de_f_skips_unknown_tag() {
+        let (scalar_store, _) = ScalarStore::<Fr>::from_source("(1 . \"foo\")").unwrap();
+        let fs = scalar_store.clone().ser_f().unwrap();
+
+        // Insert a synthetic entry with an unknown tag and a three-element body after the first entry.
+        let first_len = 3 + fs[2].to_u64().unwrap() as usize;
+        let unknown = [
+            Fr::from(0xffff),
+            Fr::from(1),
+            Fr::from(3),
+            Fr::from(7),
+            Fr::from(8),
+            Fr::from(9),
+        ];
+        let mut with_unknown = fs[..first_len].to_vec();
+        with_unknown.extend(unknown);
+        with_unknown.extend(&fs[first_len..]);
+
+        assert_eq!(Ok(scalar_store), ScalarStore::de_f(&with_unknown));
+        assert_eq!(Ok(ScalarStore::default()), ScalarStore::de_f(&unknown));
+    }
+
+    #[quickcheck]
+    fn prop_scalar_store_json(x: ScalarStore<Fr>) -> bool {
+        Ok(x.clone()) == ScalarStore::from_json(&x.to_json())
+    }
+
+    #[test]
+    fn test_scalar_store_json() {
+        let mut s = Store::<Fr>::default();
+        let expr = s
+            .read("(let ((a #\\x) (b \"str\") (c 7u64)) (lambda (x) (+ x a)))")
+            .unwrap();
+        let num = s.num(5);
+        let outermost = s.intern_cont_outermost();
+        let cont = s.intern_cont_unop(Op1::Car, outermost);
+        let thunk = s.intern_thunk(crate::store::Thunk {
+            value: num,
+            continuation: cont,
+        });
+        let list = s.list(&[expr, thunk]);
+        s.hydrate_scalar_cache();
+
+        let (scalar_store, root) = ScalarStore::new_with_expr(&s, &list);
+        let json = scalar_store.to_json();
+        assert_eq!(Ok(scalar_store.clone()), ScalarStore::from_json(&json));
+        assert_eq!(
+            Ok(scalar_store),
+            ScalarStore::from_json(&serde_json::from_str(&json.to_string()).unwrap())
+        );
+
+        let root = root.unwrap();
+        let entry = &json["exprs"][ptr_to_hex(&root)];
+        assert_eq!("Cons", entry["type"]);
+        assert_eq!(
+            f_to_hex(&Fr::from(5)),
+            json["exprs"][ptr_to_hex(&s.get_expr_hash(&num).unwrap())]["value"]
+        );
+        assert_eq!(128, ptr_to_hex(&root).len());
+
+        let mut bad = json.clone();
+        bad["exprs"][ptr_to_hex(&root)]["type"] = "Bogus".into();
+        assert!(ScalarStore::<Fr>::from_json(&bad).is_err());
+        let mut bad = json;
+        bad["version"] = 0.into();
+        assert!(ScalarStore::<Fr>::from_json(&bad).is_err());
+    }
+
+    #[test]
+    fn test_scalar_store_string_table() {
+        let mut s = Store::<Fr>::default();
+        let expr = s.read("(cons foo (cons \"bar\" baz))").unwrap();
+        let foo = s.sym("foo");
+        let bar = s.str("bar");
+        let baz = s.sym("baz");
+        s.hydrate_scalar_cache();
+
+        let (scalar_store, _) = ScalarStore::new_with_expr(&s, &expr);
+        let table = scalar_store.string_table();
+        // `cons` is a symbol too.
+        assert_eq!(4, table.len());
+        for (ptr, name) in [(foo, "FOO"), (bar, "bar"), (baz, "BAZ")] {
+            let scalar_ptr = s.get_expr_hash(&ptr).unwrap();
+            assert_eq!(Some(&name.to_string()), table.get(&scalar_ptr));
+        }
+        assert_eq!(
+            Tag::Str.as_field::<Fr>(),
+            *s.get_expr_hash(&bar).unwrap().tag()
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_retain() {
+        let mut s = Store::<Fr>::default();
+        let expr = s.read("(1 (2 . 3) sym)").unwrap();
+        let nums: Vec<_> = (1..=3).map(|n| s.num(n)).collect();
+        let io = IO {
+            expr,
+            env: empty_sym_env(&s),
+            cont: s.intern_cont_outermost(),
+        };
+        s.hydrate_scalar_cache();
+
+        let (mut scalar_store, _, _, cont) = ScalarStore::new_with_io(&s, &io);
+        let len = scalar_store.scalar_map.len();
+        scalar_store.retain(|_, expr| !matches!(expr, Some(ScalarExpression::Num(_))));
+
+        assert_eq!(len - 3, scalar_store.scalar_map.len());
+        let mut missing: Vec<_> = nums
+            .iter()
+            .map(|n| uptr(&s.get_expr_hash(n).unwrap()))
+            .collect();
+        missing.sort();
+        assert_eq!(Err(missing), scalar_store.check_complete());
+
+        scalar_store.retain_conts(|ptr, _| *ptr != cont);
+        assert!(scalar_store.get_cont(&cont).is_none());
+    }
+
+    #[test]
+    fn test_scalar_store_tag_histogram() {
+        let mut s = Store::<Fr>::default();
+        let expr = s.read("(1 2 sym \"str\")").unwrap();
+        let opaque = s.intern_maybe_opaque_comm(Fr::from(123));
+        let list = s.list(&[expr, opaque]);
+        let io = IO {
+            expr: list,
+            env: empty_sym_env(&s),
+            cont: s.intern_cont_outermost(),
+        };
+        s.hydrate_scalar_cache();
+
+        let (scalar_store, _, _, _) = ScalarStore::new_with_io(&s, &io);
+        let histogram = scalar_store.tag_histogram();
+        assert_eq!(
+            vec![
+                (Tag::Nil, 1),
+                (Tag::Cons, 6),
+                (Tag::Sym, 1),
+                (Tag::Num, 2),
+                (Tag::Str, 1),
+                (Tag::Comm, 1)
+            ],
+            histogram.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![(ContTag::Outermost, 1)],
+            scalar_store
+                .cont_tag_histogram()
+                .into_iter()
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_scalar_serde_names() {
+        let ptr = ScalarPtr::from_parts(Fr::from(Tag::Nil as u64), Fr::from(0));
+        let cont_ptr = ScalarContPtr::from_parts(Fr::from(ContTag::Outermost as u64), Fr::from(0));
+        let fun = ScalarExpression::Fun {
+            arg: ptr,
+            body: ptr,
+            closed_env: ptr,
+        };
+        let binop = ScalarContinuation::Binop {
+            operator: Op2::Sum,
+            saved_env: ptr,
+            unevaled_args: ptr,
+            continuation: cont_ptr,
+        };
+
+        assert_eq!(
+            serde_json::json!("Nil"),
+            serde_json::to_value(ScalarExpression::<Fr>::Nil).unwrap()
+        );
+        assert_eq!(
+            vec!["arg", "body", "closed_env"],
+            serde_json::to_value(&fun).unwrap()["Fun"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["continuation", "value"],
+            serde_json::to_value(ScalarExpression::Thunk(ScalarThunk {
+                value: ptr,
+                continuation: cont_ptr,
+            }))
+            .unwrap()["Thunk"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["continuation", "operator", "saved_env", "unevaled_args"],
+            serde_json::to_value(&binop).unwrap()["Binop"]
+                .as_object()
+                .unwrap()
+                .keys()
+                .collect::<Vec<_>>()
+        );
+
+        #[cfg(feature = "ipld")]
+        {
+            let ptr_ipld = to_ipld(ptr).unwrap();
+            let cont_ptr_ipld = to_ipld(cont_ptr).unwrap();
+            assert_eq!(
+                Ipld::List(vec![Ipld::Integer(0)]),
+                to_ipld(ScalarExpression::<Fr>::Nil).unwrap()
+            );
+            assert_eq!(
+                Ipld::List(vec![
+                    Ipld::Integer(4),
+                    ptr_ipld.clone(),
+                    ptr_ipld.clone(),
+                    ptr_ipld.clone()
+                ]),
+                to_ipld(fun).unwrap()
+            );
+            assert_eq!(
+                Ipld::List(vec![
+                    Ipld::Integer(8),
+                    to_ipld(Op2::Sum).unwrap(),
+                    ptr_ipld.clone(),
+                    ptr_ipld,
+                    cont_ptr_ipld
+                ]),
+                to_ipld(binop).unwrap()
+            );
+            assert_eq!(
+                Ipld::List(vec![Ipld::Integer(15)]),
+                to_ipld(ScalarContinuation::<Fr>::Terminal).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_scalar_store_subgraph() {
+        let mut store = Store::<Fr>::default();
+        let shared = store.read("(2 3)").unwrap();
+        let one = store.num(1);
+        let four = store.num(4);
+        let list1 = store.cons(one, shared);
+        let list2 = store.cons(four, shared);
+        store.hydrate_scalar_cache();
+
+        let mut scalar_store = ScalarStore::default();
+        let root1 = scalar_store.add_one_ptr(&store, &list1).unwrap();
+        scalar_store.add_one_ptr(&store, &list2).unwrap();
+        let before = scalar_store.clone();
+
+        // The subgraph of the shared sublist is exactly what adding the sublist alone produces.
+        let (expected, shared_root) = ScalarStore::new_with_expr(&store, &shared);
+        let shared_root = shared_root.unwrap();
+        let (subgraph, root) = scalar_store.subgraph(&shared_root).unwrap();
+        assert_eq!(shared_root, root);
+        assert_eq!(expected, subgraph);
+        assert!(subgraph.len() < scalar_store.len());
+
+        let (subgraph, root) = scalar_store.subgraph(&root1).unwrap();
+        assert_eq!(root1, root);
+        assert_eq!(ScalarStore::new_with_expr(&store, &list1).0, subgraph);
+        assert_eq!(before, scalar_store);
+
+        let missing = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(987));
+        assert!(scalar_store.subgraph(&missing).is_none());
+
+        // Cycles terminate.
+        let a = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(1));
+        let b = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(2));
+        let mut cyclic = ScalarStore::default();
+        cyclic
+            .scalar_map
+            .insert(a, Some(ScalarExpression::Cons(b, a)));
+        cyclic
+            .scalar_map
+            .insert(b, Some(ScalarExpression::Cons(a, b)));
+        let (subgraph, _) = cyclic.subgraph(&a).unwrap();
+        assert_eq!(cyclic, subgraph);
+    }
+
+    #[test]
+    fn test_scalar_store_dedup_stats() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(+ 1 2 2 (* 3 4) \"asdf\" \"asdf\")").unwrap();
+        let distinct = store.read("(+ 1 2 5 (* 3 4) \"asdf\" \"qwer\")").unwrap();
+        store.hydrate_scalar_cache();
+
+        let empty = ScalarStore::default();
+        let stats = empty.dedup_stats(&store, &expr);
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &expr);
+        assert_eq!(scalar_store.len(), stats.unique);
+        assert_eq!(stats.visits, stats.unique + stats.hits());
+        assert!(empty.is_empty());
+
+        // The repeated `2` and `"asdf"` are the only difference from a list of distinct literals.
+        let distinct_stats = empty.dedup_stats(&store, &distinct);
+        assert_eq!(distinct_stats.visits, stats.visits);
+        assert_eq!(distinct_stats.hits() + 2, stats.hits());
+
+        // Against a store which contains part of it, only the rest is unique.
+        let part = store.read("(* 3 4)").unwrap();
+        store.hydrate_scalar_cache();
+        let (partial, _) = ScalarStore::new_with_expr(&store, &part);
+        assert_eq!(
+            scalar_store.len() - partial.len(),
+            partial.dedup_stats(&store, &expr).unique
+        );
+        assert_eq!(stats, empty.dedup_stats(&store, &expr));
+
+        // Against a store which already contains it, every visit is a hit.
+        let stats = scalar_store.dedup_stats(&store, &expr);
+        assert_eq!(
+            DedupStats {
+                visits: 1,
+                unique: 0
+            },
+            stats
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_deserialize_lazy() {
+        use std::fs::File;
+        use std::io::BufReader;
+
+        let mut store = Store::<Fr>::default();
+        let expr = store
+            .read("(let ((a 123)) (lambda (x) (+ x a \"asdf\")))")
+            .unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 5)
+            .get_frames()
+            .unwrap();
+        let io = frames.last().unwrap().output;
+        store.hydrate_scalar_cache();
+        let (scalar_store, _, _, _) = ScalarStore::new_with_io(&store, &io);
+        assert!(!scalar_store.scalar_cont_map.is_empty());
+
+        let path = std::env::temp_dir().join(format!(
+            "lurk-test-deserialize-lazy-{}.bin",
+            std::process::id()
+        ));
+        let mut file = File::create(&path).unwrap();
+        scalar_store.serialize_to(&mut file).unwrap();
+        drop(file);
+
+        let entries =
+            ScalarStore::<Fr>::deserialize_lazy(BufReader::new(File::open(&path).unwrap()))
+                .collect::<io::Result<Vec<_>>>()
+                .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Only expressions are yielded, each exactly once, in serialization order.
+        assert_eq!(
+            scalar_store.scalar_map,
+            entries.iter().cloned().collect::<BTreeMap<_, _>>()
+        );
+        assert_eq!(scalar_store.scalar_map.len(), entries.len());
+        assert!(entries
+            .windows(2)
+            .all(|pair| uptr(&pair[0].0) < uptr(&pair[1].0)));
+
+        let mut bytes = Vec::new();
+        scalar_store.serialize_to(&mut bytes).unwrap();
+        let truncated = &bytes[..bytes.len() - 1];
+        let results = ScalarStore::<Fr>::deserialize_lazy(truncated).collect::<Vec<_>>();
+        assert_eq!(
+            io::ErrorKind::UnexpectedEof,
+            results.last().unwrap().as_ref().unwrap_err().kind()
+        );
+        assert_eq!(1, results.iter().filter(|result| result.is_err()).count());
+    }
+
+    #[cfg(feature = "ipld")]
+    #[test]
+    fn test_scalar_store_to_dag_blocks() {
+        use libipld::{cbor::DagCborCodec, codec::Codec};
+
+        let mut store = Store::<Fr>::default();
+        let expr = store
+            .read("(let ((a 123)) (lambda (x) (+ x a \"asdf\" \"asdf\")))")
+            .unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 5)
+            .get_frames()
+            .unwrap();
+        let io = frames.last().unwrap().output;
+        store.hydrate_scalar_cache();
+        let (scalar_store, _, _, _) = ScalarStore::new_with_io(&store, &io);
+
+        let blocks = scalar_store.to_dag_blocks().unwrap();
+        assert_eq!(
+            scalar_store.scalar_map.len() + scalar_store.scalar_cont_map.len(),
+            blocks.len()
+        );
+        let cids: BTreeSet<_> = blocks.iter().map(|(cid, _)| *cid).collect();
+        assert_eq!(blocks.len(), cids.len());
+        assert_eq!(
+            blocks,
+            ScalarStore::new_with_io(&store, &io)
+                .0
+                .to_dag_blocks()
+                .unwrap()
+        );
+
+        // Each block links only to blocks which precede it.
+        let mut seen = BTreeSet::new();
+        for (cid, bytes) in &blocks {
+            let block: Ipld = DagCborCodec.decode(bytes).unwrap();
+            let mut links = Vec::new();
+            block.get("children").unwrap().references(&mut links);
+            assert!(links.iter().all(|link| seen.contains(link)));
+            seen.insert(*cid);
+        }
+
+        let a = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(1));
+        let b = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(2));
+        let mut cyclic = ScalarStore::default();
+        cyclic
+            .scalar_map
+            .insert(a, Some(ScalarExpression::Cons(b, b)));
+        cyclic
+            .scalar_map
+            .insert(b, Some(ScalarExpression::Cons(a, a)));
+        assert!(matches!(cyclic.to_dag_blocks(), Err(DagError::Cycle(_))));
+    }
+
+    #[cfg(feature = "ipld")]
+    #[test]
+    fn test_scalar_store_from_dag_blocks() {
+        use libipld::{cbor::DagCborCodec, codec::Codec};
+
+        let mut store = Store::<Fr>::default();
+        let expr = store
+            .read("(let ((a 123)) (lambda (x) (+ x a \"asdf\" \"asdf\")))")
+            .unwrap();
+        let other = store.read("(1 2 3)").unwrap();
+        store.hydrate_scalar_cache();
+        let (scalar_store, scalar_ptrs) = ScalarStore::new_with_exprs(&store, &[expr, other]);
+        let (expected, _) = ScalarStore::new_with_expr(&store, &expr);
+
+        let blocks = scalar_store.to_dag_blocks().unwrap();
+        let root = blocks
+            .iter()
+            .find(|(_, bytes)| {
+                let block: Ipld = DagCborCodec.decode(bytes).unwrap();
+                from_ipld::<ScalarPtr<Fr>>(block.get("ptr").unwrap().clone()).ok() == scalar_ptrs[0]
+            })
+            .unwrap()
+            .0;
+
+        // Only the blocks reachable from `root` are used.
+        let (rebuilt, root_ptr) = ScalarStore::<Fr>::from_dag_blocks(blocks.clone(), root).unwrap();
+        assert_eq!(scalar_ptrs[0], Some(root_ptr));
+        assert_eq!(expected, rebuilt);
+
+        let (missing, _) = blocks.iter().find(|(cid, _)| *cid != root).unwrap();
+        let incomplete = blocks.iter().filter(|(cid, _)| cid != missing).cloned();
+        assert_eq!(
+            Err(DagError::MissingBlock(*missing)),
+            ScalarStore::<Fr>::from_dag_blocks(incomplete, root)
+        );
+
+        let tampered = blocks.into_iter().map(|(cid, bytes)| {
+            if cid == root {
+                (cid, vec![0])
+            } else {
+                (cid, bytes)
+            }
+        });
+        assert_eq!(
+            Err(DagError::InvalidBlock(root)),
+            ScalarStore::<Fr>::from_dag_blocks(tampered, root)
+        );
+    }
+
+    #[test]
+    fn test_scalar_expression_ser_f_char() {
+        // Every char fits in the first four bytes of a field element's repr, so encoding the extremes succeeds, and the
+        // error path is only reachable for a field too small to hold a code point.
+        for c in ['\0', '\u{d7ff}', '\u{e000}', char::MAX] {
+            let expr = ScalarExpression::<Fr>::Char(c);
+            assert_eq!(Ok(vec![char_to_f(c).unwrap()]), expr.ser_f());
+
+            let ptr = ScalarPtr::from_parts(Tag::Char.as_field(), char_to_f(c).unwrap());
+            let mut scalar_store = ScalarStore::default();
+            scalar_store.scalar_map.insert(ptr, Some(expr));
+            let fs = scalar_store.clone().ser_f().unwrap();
+            assert_eq!(Ok(scalar_store.clone()), ScalarStore::de_f(&fs));
+            assert!(scalar_store.to_bytes().is_ok());
+            assert!(scalar_store.digest().is_ok());
+        }
+
+        // A corrupted char payload is rejected when decoding rather than panicking.
+        let ptr = ScalarPtr::from_parts(Tag::Char.as_field(), Fr::from(0xd800));
+        let fs = vec![
+            *ptr.tag(),
+            *ptr.value(),
+            Fr::from(2),
+            Fr::one(),
+            Fr::from(0xd800),
+        ];
+        assert_eq!(
+            Err(DeserializeError::InvalidPayload(4)),
+            ScalarStore::<Fr>::de_f(&fs)
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_add_one_cont_ptr() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(let ((a 1)) (+ a (* 2 3)))").unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 100)
+            .get_frames()
+            .unwrap();
+        // An intermediate continuation, in the middle of evaluating the arguments of `+`.
+        let cont = frames
+            .iter()
+            .map(|frame| frame.output.cont)
+            .find(|cont| matches!(store.fetch_cont(cont), Some(Continuation::Binop { .. })))
+            .unwrap();
+        store.hydrate_scalar_cache();
+
+        let mut scalar_store = ScalarStore::default();
+        let scalar_cont_ptr = scalar_store.add_one_cont_ptr(&store, &cont).unwrap();
+        assert!(scalar_store.is_finalized());
+        assert_eq!(Some(scalar_cont_ptr), store.get_cont_hash(&cont));
+        assert!(matches!(
+            scalar_store.get_cont(&scalar_cont_ptr),
+            Some(ScalarContinuation::Binop { .. })
+        ));
+        // The continuations it is built on, down to `Outermost`, are added too.
+        assert!(scalar_store.scalar_cont_map.len() > 1);
+        assert!(scalar_store
+            .scalar_cont_map
+            .values()
+            .any(|cont| *cont == Some(ScalarContinuation::Outermost)));
+        assert_eq!(Ok(()), scalar_store.check_complete());
+
+        // The continuation can be interned back into a fresh `Store` with the same hash.
+        let mut new_store = scalar_store.to_store().unwrap();
+        let new_cont = new_store
+            .intern_scalar_cont_ptr(scalar_cont_ptr, &scalar_store)
+            .unwrap();
+        new_store.hydrate_scalar_cache();
+        assert_eq!(Some(scalar_cont_ptr), new_store.get_cont_hash(&new_cont));
+    }
+
+    #[test]
+    fn test_scalar_store_read_fr_file() {
+        use std::fs::File;
+
+        let (scalar_store, _) =
+            ScalarStore::<Fr>::from_source("(let ((a 123)) (lambda (x) (+ x a \"asdf\")))")
+                .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("lurk-test-read-fr-file-{}.fr", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        scalar_store.serialize_to(&mut file).unwrap();
+        drop(file);
+        assert_eq!(scalar_store, ScalarStore::read_fr_file(&path).unwrap());
+
+        // A truncated file reports the offset at which input ended, a whole number of field elements in.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.truncate(bytes.len() - 32);
+        std::fs::write(&path, &bytes).unwrap();
+        match ScalarStore::<Fr>::read_fr_file(&path) {
+            Err(FrFileError::Decode {
+                offset,
+                error: DeserializeError::UnexpectedEnd(_),
+            }) => assert_eq!(bytes.len(), offset),
+            res => panic!("unexpected result: {:?}", res),
+        }
+
+        // An invalid field element is reported at its own offset.
+        bytes[64..96].copy_from_slice(&[0xff; 32]);
+        std::fs::write(&path, &bytes).unwrap();
+        match ScalarStore::<Fr>::read_fr_file(&path) {
+            Err(FrFileError::Decode {
+                offset,
+                error: DeserializeError::InvalidFieldElement(2),
+            }) => assert_eq!(64, offset),
+            res => panic!("unexpected result: {:?}", res),
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            ScalarStore::<Fr>::read_fr_file(&path),
+            Err(FrFileError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_scalar_store_shared_pointers() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(+ x x)").unwrap();
+        let x = store.sym("x");
+        let nil = store.nil();
+        store.hydrate_scalar_cache();
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &expr);
+        let x = store.get_expr_hash(&x).unwrap();
+
+        assert_eq!(BTreeSet::from([x]), scalar_store.shared_pointers());
+
+        // `nil` ends both lists, so it is shared, but the distinct symbols are not.
+        let expr = store.read("(+ (f y) z)").unwrap();
+        store.hydrate_scalar_cache();
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &expr);
+        assert_eq!(
+            BTreeSet::from([store.get_expr_hash(&nil).unwrap()]),
+            scalar_store.shared_pointers()
+        );
+    }
+
+    #[test]
+    fn test_scalar_expression_from_ptr_every_tag() {
+        // `Store` has no key-value tag, and `from_ptr` matches every `Tag` without a catch-all, so adding one to `Tag`
+        // fails to compile until `ScalarExpression` handles it. This checks that each existing tag converts.
+        let mut store = Store::<Fr>::default();
+        let num = store.num(123);
+        let x = store.sym("x");
+        let nil = store.nil();
+        let outermost = store.intern_cont_outermost();
+        let ptrs = [
+            nil,
+            store.cons(num, nil),
+            x,
+            store.intern_fun(x, x, nil),
+            num,
+            store.intern_thunk(crate::store::Thunk {
+                value: num,
+                continuation: outermost,
+            }),
+            store.str("asdf"),
+            store.get_char('a'),
+            store.intern_comm(Fr::from(987), num),
+            store.uint64(456),
+        ];
+        store.hydrate_scalar_cache();
+
+        let tags: BTreeSet<Tag> = ptrs.iter().map(|ptr| ptr.tag()).collect();
+        assert_eq!(ptrs.len(), tags.len());
+        for ptr in ptrs {
+            let expr = ScalarExpression::from_ptr(&store, &ptr).unwrap();
+            assert_eq!(ptr.tag(), expression_tag(&expr));
+        }
+    }
+
+    #[test]
+    fn test_scalar_store_evaluated_thunk() {
+        // Evaluation produces thunks whenever a value is returned to a continuation other than `Outermost`.
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("((lambda (x) (+ x 1)) 2)").unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 100)
+            .get_frames()
+            .unwrap();
+        let io = frames
+            .iter()
+            .map(|frame| frame.output)
+            .find(|io| io.expr.tag() == Tag::Thunk)
+            .unwrap();
+        store.hydrate_scalar_cache();
+
+        let (mut scalar_store, expr, _, cont) = ScalarStore::new_with_io(&store, &io);
+        let thunk = match scalar_store.get_expr(&expr) {
+            Some(ScalarExpression::Thunk(thunk)) => *thunk,
+            expr => panic!("expected a thunk, got {:?}", expr),
+        };
+        assert!(scalar_store.get_expr(&thunk.value).is_some());
+        assert!(scalar_store.get_cont(&thunk.continuation).is_some());
+        assert_eq!(
+            Some(&ScalarContinuation::Dummy),
+            scalar_store.get_cont(&cont)
+        );
+
+        let (new_store, new_expr) = scalar_store.to_store_with_expr(&expr).unwrap();
+        assert_eq!(Tag::Thunk, new_expr.tag());
+        assert_eq!(Some(expr), new_store.get_expr_hash(&new_expr));
+    }
+
+    #[test]
+    fn test_scalar_store_expr_eq() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(+ 1 2)").unwrap();
+        let outermost = store.intern_cont_outermost();
+        let emit = store.intern_cont_emit(outermost);
+        store.hydrate_scalar_cache();
+
+        let (scalar_store1, _) = ScalarStore::new_with_expr(&store, &expr);
+        let mut scalar_store2 = scalar_store1.clone();
+        scalar_store2.add_one_cont_ptr(&store, &emit).unwrap();
+        assert_ne!(scalar_store1, scalar_store2);
+        assert!(scalar_store1.expr_eq(&scalar_store2));
+        assert!(scalar_store2.expr_eq(&scalar_store1));
+
+        let num = store.num(3);
+        scalar_store2.add_one_ptr(&store, &num).unwrap();
+        assert!(!scalar_store1.expr_eq(&scalar_store2));
+    }
+
+    #[cfg(feature = "canonical")]
+    #[test]
+    fn test_scalar_store_canonical_order() {
+        use crate::field::canonical_bytes;
+
+        let (scalar_store, _) =
+            ScalarStore::<Fr>::from_source("(let ((a 123)) (lambda (x) (+ x a \"asdf\")))")
+                .unwrap();
+        let keys: Vec<_> = scalar_store
+            .ser_entries()
+            .map(|(uptr, _)| (canonical_bytes(uptr.tag()), canonical_bytes(uptr.value())))
+            .collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(sorted, keys);
+        assert_eq!(scalar_store.len(), keys.len());
+
+        // Tags are small integers, so they sort numerically, with every expression tag before any continuation tag.
+        let nil = UPtr::new(Tag::Nil.as_field::<Fr>(), Fr::from(u64::MAX));
+        let cons = UPtr::new(Tag::Cons.as_field(), Fr::from(0));
+        let outermost = UPtr::new(ContTag::Outermost.as_field(), Fr::from(0));
+        assert!(nil < cons && cons < outermost);
+    }
+
+    #[test]
+    fn test_scalar_store_validate_against_store() {
+        let (mut scalar_store, root) =
+            ScalarStore::<Fr>::from_source("(let ((a 123)) (lambda (x) (+ x a \"asdf\" #\\z)))")
+                .unwrap();
+        let (mut store, _) = scalar_store.to_store_with_expr(&root).unwrap();
+        store.hydrate_scalar_cache();
+        assert_eq!(Ok(()), scalar_store.validate_against_store(&store));
+
+        // An expression which the store does not know, and one which it represents differently.
+        let missing = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(987));
+        scalar_store
+            .scalar_map
+            .insert(missing, Some(ScalarExpression::Num(Fr::from(987))));
+        let (_, str_expr) = scalar_store
+            .scalar_map
+            .iter_mut()
+            .find(|(_, expr)| matches!(expr, Some(ScalarExpression::Str(_))))
+            .unwrap();
+        *str_expr = Some(ScalarExpression::Str("qwer".into()));
+        let str_ptr = *scalar_store
+            .scalar_map
+            .iter()
+            .find(|(_, expr)| matches!(expr, Some(ScalarExpression::Str(_))))
+            .unwrap()
+            .0;
+
+        let mut expected = vec![missing, str_ptr];
+        expected.sort();
+        assert_eq!(Err(expected), scalar_store.validate_against_store(&store));
+    }
+
+    #[quickcheck]
+    fn prop_scalar_store_serialized_len_fr(x: ScalarStore<Fr>) -> bool {
+        x.serialized_len_fr() == x.ser_f().unwrap().len()
+    }
+
+    #[test]
+    fn test_scalar_store_serialized_len_fr() {
+        let chunk = string_chunk_len::<Fr>();
+        let sources = vec![
+            "nil".to_string(),
+            "(1 . 2)".to_string(),
+            format!("\"{}\"", "a".repeat(chunk)),
+            format!(
+                "(\"{}\" . {})",
+                "b".repeat(chunk + 1),
+                "c".repeat(2 * chunk)
+            ),
+            "(let ((a 123)) (lambda (x) (+ x a #\\z)))".to_string(),
+        ];
+        for source in sources {
+            let (scalar_store, _) = ScalarStore::<Fr>::from_source(&source).unwrap();
+            assert_eq!(
+                scalar_store.clone().ser_f().unwrap().len(),
+                scalar_store.serialized_len_fr()
+            );
+        }
+
+        // Continuations, and an opaque expression.
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("((lambda (x) (+ x 1)) 2)").unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 100)
+            .get_frames()
+            .unwrap();
+        let opaque = store.intern_opaque_fun(Fr::from(123));
+        store.hydrate_scalar_cache();
+
+        let mut scalar_store = ScalarStore::default();
+        for frame in frames.iter() {
+            scalar_store.add_one_ptr(&store, &frame.output.expr);
+            scalar_store.add_one_cont_ptr(&store, &frame.output.cont);
+        }
+        scalar_store.add_one_ptr(&store, &opaque);
+        assert!(!scalar_store.scalar_cont_map.is_empty());
+        assert!(scalar_store.scalar_map.values().any(Option::is_none));
+        assert_eq!(
+            scalar_store.clone().ser_f().unwrap().len(),
+            scalar_store.serialized_len_fr()
+        );
+        assert_eq!(0, ScalarStore::<Fr>::default().serialized_len_fr());
+    }
+
+    #[quickcheck]
+    fn prop_scalar_store_map_field(x: ScalarStore<Fr>) -> bool {
+        let negated = x.map_field(|f| -*f);
+        x.map_field(|f| *f) == x && negated.map_field(|f| -*f) == x
+    }
+
+    #[test]
+    fn test_scalar_store_map_field() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("((lambda (x) (+ x 1)) 2)").unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 100)
+            .get_frames()
+            .unwrap();
+        store.hydrate_scalar_cache();
+        let mut scalar_store = ScalarStore::default();
+        for frame in frames.iter() {
+            scalar_store.add_one_ptr(&store, &frame.output.expr);
+            scalar_store.add_one_cont_ptr(&store, &frame.output.cont);
+        }
+        let root = store.get_expr_hash(&expr).unwrap();
+        scalar_store.add_one_ptr(&store, &expr);
+        assert!(!scalar_store.scalar_cont_map.is_empty());
+
+        // A field wrapper which preserves every element converts the store to an equal one.
+        let wrapped: ScalarStore<Fr> = scalar_store.map_field(|f| FWrap(*f).0);
+        assert_eq!(scalar_store, wrapped);
+        assert_eq!(
+            scalar_store.clone().ser_f().unwrap(),
+            wrapped.ser_f().unwrap()
+        );
+
+        // Any other conversion rewrites every element, including tags.
+        let negated = scalar_store.map_field(|f| -*f);
+        assert_eq!(scalar_store.len(), negated.len());
+        assert!(negated.get_expr(&root).is_none());
+        let (car, cdr) = match scalar_store.get_expr(&root) {
+            Some(ScalarExpression::Cons(car, cdr)) => (*car, *cdr),
+            expr => panic!("expected a cons, got {:?}", expr),
+        };
+        let negate = |ptr: ScalarPtr<Fr>| ScalarPtr::from_parts(-*ptr.tag(), -*ptr.value());
+        assert_eq!(
+            Some(&ScalarExpression::Cons(negate(car), negate(cdr))),
+            negated.get_expr(&negate(root))
+        );
+        assert_eq!(scalar_store, negated.map_field(|f| -*f));
+    }
+
+    #[test]
+    fn test_scalar_store_canonicalize() {
+        let mut store = Store::<Fr>::default();
+        let expr = store
+            .read("(cons 123 (cons #\\a (cons \"\" (cons 18446744073709551616 nil))))")
+            .unwrap();
+        let uint = store.get_u64(7);
+        let exprs = store.intern_list(&[expr, uint]);
+        store.hydrate_scalar_cache();
+        let (expanded, _) = ScalarStore::new_with_expr(&store, &exprs);
+
+        // An equivalent store, in which every immediate, and one other expression, is opaque.
+        let cons = store.get_expr_hash(&expr).unwrap();
+        let mut opaque = expanded.clone();
+        let mut made_opaque = 0;
+        for (ptr, expr) in opaque.scalar_map.iter_mut() {
+            if ScalarExpression::from_immediate(ptr).is_some() || *ptr == cons {
+                *expr = None;
+                made_opaque += 1;
+            }
+        }
+        assert_eq!(6, made_opaque);
+        assert!(opaque.equiv(&expanded));
+        assert_ne!(opaque.digest().unwrap(), expanded.digest().unwrap());
+
+        let mut canonical_opaque = opaque.clone();
+        canonical_opaque.canonicalize();
+        let mut canonical_expanded = expanded.clone();
+        canonical_expanded.canonicalize();
+        assert_eq!(expanded, canonical_expanded);
+
+        // Only the expression which is not an immediate remains opaque.
+        let mut expected = expanded.clone();
+        expected.scalar_map.insert(cons, None);
+        assert_eq!(expected, canonical_opaque);
+        assert_eq!(
+            expected.digest().unwrap(),
+            canonical_opaque.digest().unwrap()
+        );
+
+        // Canonicalization expands immediates to what `Store` would produce.
+        for (ptr, expr) in canonical_opaque.scalar_map.iter() {
+            if *ptr != cons {
+                assert_eq!(expanded.get_expr(ptr), expr.as_ref());
+            }
+        }
+    }
+
+    #[test]
+    fn test_scalar_store_append_to_file() {
+        let path = std::env::temp_dir().join(format!(
+            "lurk-test-append-to-file-{}.fr",
+            std::process::id()
+        ));
+        let index_path = ScalarStore::<Fr>::append_index_path(&path);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&index_path);
+        let repr_len = Fr::zero().to_repr().as_ref().len() as u64;
+        let file_len = || std::fs::metadata(&path).unwrap().len();
+        let index_len = || std::fs::metadata(&index_path).unwrap().len();
+
+        let (a, _) = ScalarStore::<Fr>::from_source("(1 2 3)").unwrap();
+        a.append_to_file(&path).unwrap();
+        assert_eq!(a, ScalarStore::read_fr_file(&path).unwrap());
+        assert_eq!(a.serialized_len_fr() as u64 * repr_len, file_len());
+        assert_eq!(8 + a.len() as u64 * 2 * repr_len, index_len());
+
+        // Only the entries which are not yet in the file are appended.
+        let (b, _) = ScalarStore::<Fr>::from_source("(0 1 2 3)").unwrap();
+        b.append_to_file(&path).unwrap();
+        let mut union = a.clone();
+        union.merge(&b).unwrap();
+        assert_eq!(union, ScalarStore::read_fr_file(&path).unwrap());
+        assert_eq!(union.serialized_len_fr() as u64 * repr_len, file_len());
+        assert_eq!(8 + union.len() as u64 * 2 * repr_len, index_len());
+
+        let len = file_len();
+        a.append_to_file(&path).unwrap();
+        b.append_to_file(&path).unwrap();
+        assert_eq!(len, file_len());
+
+        // Without its index, the file is scanned to rebuild it.
+        std::fs::remove_file(&index_path).unwrap();
+        let (c, _) = ScalarStore::<Fr>::from_source("(4 . (1 2 3))").unwrap();
+        c.append_to_file(&path).unwrap();
+        union.merge(&c).unwrap();
+        assert_eq!(union, ScalarStore::read_fr_file(&path).unwrap());
+        assert_eq!(union.serialized_len_fr() as u64 * repr_len, file_len());
+        assert_eq!(8 + union.len() as u64 * 2 * repr_len, index_len());
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&index_path).unwrap();
+    }
+
+    #[test]
+    fn test_scalar_store_append_to_file_stale_index() {
+        let path = std::env::temp_dir().join(format!(
+            "lurk-test-append-to-file-stale-{}.fr",
+            std::process::id()
+        ));
+        let index_path = ScalarStore::<Fr>::append_index_path(&path);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&index_path);
+
+        let (a, _) = ScalarStore::<Fr>::from_source("(1 2 3)").unwrap();
+        let (b, _) = ScalarStore::<Fr>::from_source("(0 1 2 3)").unwrap();
+        let mut union = a.clone();
+        union.merge(&b).unwrap();
+
+        // An index left behind by a deleted file does not stop the entries it lists from being written.
+        a.append_to_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        a.append_to_file(&path).unwrap();
+        assert_eq!(a, ScalarStore::read_fr_file(&path).unwrap());
+
+        // Nor does an index which was not updated when the file was rewritten.
+        let stale = std::fs::read(&index_path).unwrap();
+        b.append_to_file(&path).unwrap();
+        std::fs::write(&index_path, &stale).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        b.append_to_file(&path).unwrap();
+        a.append_to_file(&path).unwrap();
+        assert_eq!(union, ScalarStore::read_fr_file(&path).unwrap());
+
+        // A truncated index is rebuilt rather than trusted.
+        let len = std::fs::metadata(&path).unwrap().len();
+        let index = std::fs::read(&index_path).unwrap();
+        std::fs::write(&index_path, &index[..index.len() - 1]).unwrap();
+        union.append_to_file(&path).unwrap();
+        assert_eq!(len, std::fs::metadata(&path).unwrap().len());
+        assert_eq!(index, std::fs::read(&index_path).unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&index_path).unwrap();
+    }
+
+    #[test]
+    fn test_scalar_store_contains() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(+ 1 2)").unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 100)
+            .get_frames()
+            .unwrap();
+        let opaque = store.intern_opaque_fun(Fr::from(123));
+        store.hydrate_scalar_cache();
+
+        let mut scalar_store = ScalarStore::default();
+        let scalar_expr = scalar_store.add_one_ptr(&store, &expr).unwrap();
+        let scalar_opaque = scalar_store.add_one_ptr(&store, &opaque).unwrap();
+        let scalar_cont = scalar_store
+            .add_one_cont_ptr(&store, &frames[1].input.cont)
+            .unwrap();
+
+        assert!(scalar_store.contains_expr(&scalar_expr));
+        assert!(scalar_store.contains_expr(&scalar_opaque));
+        assert!(scalar_store.get_expr(&scalar_opaque).is_none());
+        assert!(
+            !scalar_store.contains_expr(&ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(987)))
+        );
+
+        assert!(scalar_store.contains_cont(&scalar_cont));
+        // The continuations which `scalar_cont` continues to are also present.
+        let outermost = store.get_cont_hash(&frames[0].input.cont).unwrap();
+        assert!(scalar_store.contains_cont(&outermost));
+        assert!(!scalar_store.contains_cont(&ScalarContPtr::from_parts(
+            ContTag::Outermost.as_field(),
+            Fr::from(987)
+        )));
     }
 
-    #[quickcheck]
-    fn prop_scalar_expression_ipld(x: ScalarExpression<Fr>) -> bool {
-        match to_ipld(x.clone()) {
-            Ok(ipld) => match from_ipld(ipld.clone()) {
-                Ok(y) => {
-                    println!("x: {:?}", x);
-                    println!("y: {:?}", y);
-                    x == y
-                }
-                Err(e) => {
-                    println!("ser x: {:?}", x);
-                    println!("de ipld: {:?}", ipld);
-                    println!("err e: {:?}", e);
-                    false
-                }
-            },
-            Err(e) => {
-                println!("ser x: {:?}", x);
-                println!("err e: {:?}", e);
-                false
-            }
+    #[test]
+    fn test_scalar_store_to_source_with_quotes() {
+        for (source, expected) in [
+            ("'foo", "'FOO"),
+            ("(quote foo)", "'FOO"),
+            ("'(1 'x \"y\")", "'(1 'X \"y\")"),
+            ("''foo", "''FOO"),
+            ("(a quote b)", "(A QUOTE B)"),
+            ("(quote a b)", "(QUOTE A B)"),
+            ("(quote)", "(QUOTE)"),
+            ("(quote . a)", "(QUOTE . A)"),
+        ] {
+            let (scalar_store, root) = ScalarStore::<Fr>::from_source(source).unwrap();
+            let rendered = scalar_store.to_source_with_quotes(&root).unwrap();
+            assert_eq!(expected, rendered);
+
+            // The shorthand reads back as the same expression.
+            let (reread, reread_root) = ScalarStore::<Fr>::from_source(&rendered).unwrap();
+            assert_eq!(root, reread_root);
+            assert_eq!(scalar_store, reread);
         }
+
+        // Without opting in, the quote form is rendered as a plain list.
+        let (scalar_store, root) = ScalarStore::<Fr>::from_source("'foo").unwrap();
+        assert_eq!("(QUOTE FOO)", scalar_store.to_source(&root).unwrap());
     }
 
-    #[quickcheck]
-    fn prop_scalar_continuation_ipld(x: ScalarExpression<Fr>) -> bool {
-        if let Ok(ipld) = to_ipld(x.clone()) {
-            if let Ok(y) = from_ipld(ipld) {
-                x == y
-            } else {
-                false
-            }
-        } else {
-            false
+    #[test]
+    fn test_scalar_store_to_shallow() {
+        let (scalar_store, root) = ScalarStore::<Fr>::from_source("(+ 1 (* 2 3))").unwrap();
+        let list = |source: &str| {
+            let (store, ptr) = ScalarStore::<Fr>::from_source(source).unwrap();
+            (ptr, store.get_expr(&ptr).cloned())
+        };
+        let (mul, mul_expr) = list("(* 2 3)");
+        let (one, _) = list("1");
+        let (plus, _) = list("+");
+
+        // At depth 1, the top list is expanded, and its elements, including the inner multiply, are opaque.
+        let preview = scalar_store.to_shallow(&root, 1);
+        assert_eq!(scalar_store.get_expr(&root), preview.get_expr(&root));
+        for element in [plus, one, mul] {
+            assert_eq!(Some(&None), preview.scalar_map.get(&element));
         }
+        assert_eq!(Ok(()), preview.check_complete());
+        let rendered = preview.to_source(&root).unwrap();
+        assert_eq!(3, rendered.matches("<opaque:").count(), "{}", rendered);
+
+        // At depth 2, the multiply is expanded too.
+        let preview = scalar_store.to_shallow(&root, 2);
+        assert_eq!(mul_expr.as_ref(), preview.get_expr(&mul));
+        assert_eq!(Ok(()), preview.check_complete());
+
+        // Deep enough, the preview is the whole expression.
+        let (whole, _) = scalar_store.subgraph(&root).unwrap();
+        assert_eq!(whole, scalar_store.to_shallow(&root, 3));
+        assert_eq!(whole, scalar_store.to_shallow(&root, 100));
+
+        // At depth 0, only the opaque root remains.
+        let preview = scalar_store.to_shallow(&root, 0);
+        assert_eq!(1, preview.len());
+        assert_eq!(Some(&None), preview.scalar_map.get(&root));
+
+        let missing = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(987));
+        assert!(scalar_store.to_shallow(&missing, 1).is_empty());
     }
 
-    // This doesn't create well-defined ScalarStores, so is only useful for
-    // testing ipld
-    impl Arbitrary for ScalarStore<Fr> {
-        fn arbitrary(g: &mut Gen) -> Self {
-            let map: Vec<(ScalarPtr<Fr>, Option<ScalarExpression<Fr>>)> = Arbitrary::arbitrary(g);
-            let cont_map: Vec<(ScalarContPtr<Fr>, Option<ScalarContinuation<Fr>>)> =
-                Arbitrary::arbitrary(g);
-            ScalarStore {
-                scalar_map: map.into_iter().collect(),
-                scalar_cont_map: cont_map.into_iter().collect(),
-                pending_scalar_ptrs: Vec::new(),
-            }
+    #[test]
+    fn test_scalar_store_rehash() {
+        let (scalar_store, _) = ScalarStore::<Fr>::from_source("(1 2 \"abc\")").unwrap();
+
+        // A consistent store is unchanged.
+        let mut rehashed = scalar_store.clone();
+        rehashed.rehash().unwrap();
+        assert_eq!(scalar_store, rehashed);
+
+        // Alter some values in place, leaving their pointers, and those of their parents, stale.
+        let mut altered = scalar_store.clone();
+        let one = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(1));
+        altered
+            .scalar_map
+            .insert(one, Some(ScalarExpression::Num(Fr::from(5))));
+        let abc = *altered
+            .iter_exprs()
+            .find(|(_, expr)| expr == &Some(&ScalarExpression::Str("abc".into())))
+            .unwrap()
+            .0;
+        altered
+            .scalar_map
+            .insert(abc, Some(ScalarExpression::Str("abd".into())));
+        assert!(altered.check_complete().is_ok());
+
+        altered.rehash().unwrap();
+        let (expected, root) = ScalarStore::<Fr>::from_source("(5 2 \"abd\")").unwrap();
+        assert_eq!(expected, altered);
+        assert!(altered.get_expr(&root).is_some());
+        assert!(!altered.contains_expr(&one));
+        assert!(!altered.contains_expr(&abc));
+
+        // Opaque entries keep their pointers.
+        let mut opaque = scalar_store.clone();
+        opaque.scalar_map.insert(abc, None);
+        let mut rehashed = opaque.clone();
+        rehashed.rehash().unwrap();
+        assert_eq!(opaque, rehashed);
+
+        // An expression whose contents do not match its tag cannot be rehashed.
+        let mut mismatched = scalar_store;
+        mismatched
+            .scalar_map
+            .insert(one, Some(ScalarExpression::Str("one".into())));
+        assert!(matches!(
+            mismatched.rehash(),
+            Err(ScalarStoreError::InternFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_scalar_store_rehash_thunk() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("((lambda (x) (+ x 1)) 2)").unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 100)
+            .get_frames()
+            .unwrap();
+        store.hydrate_scalar_cache();
+        let mut scalar_store = ScalarStore::default();
+        for frame in frames.iter() {
+            scalar_store.add_one_ptr(&store, &frame.output.expr);
+            scalar_store.add_one_cont_ptr(&store, &frame.output.cont);
         }
+        assert!(!scalar_store.scalar_cont_map.is_empty());
+
+        let mut rehashed = scalar_store.clone();
+        rehashed.rehash().unwrap();
+        assert_eq!(scalar_store, rehashed);
+    }
+
+    #[test]
+    fn test_scalar_store_env_bindings() {
+        let closure = |source: &str| {
+            let mut store = Store::<Fr>::default();
+            let expr = store.read(source).unwrap();
+            let env = empty_sym_env(&store);
+            let (io, _, _) = Evaluator::new(expr, env, &mut store, 1000).eval().unwrap();
+            store.hydrate_scalar_cache();
+            let (scalar_store, fun) = ScalarStore::new_with_expr(&store, &io.expr);
+            let closed_env = match scalar_store.get_expr(&fun.unwrap()) {
+                Some(ScalarExpression::Fun { closed_env, .. }) => *closed_env,
+                expr => panic!("expected a function, got {:?}", expr),
+            };
+            (scalar_store, closed_env)
+        };
+        let num = |n| ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(n));
+
+        let (scalar_store, env) = closure("(let ((a 1)) (lambda (x) (+ x a)))");
+        assert_eq!(
+            vec![("A".to_string(), num(1))],
+            scalar_store.env_bindings(&env)
+        );
+
+        // Bindings are innermost first, and a `letrec` frame contributes all of its bindings.
+        let (scalar_store, env) =
+            closure("(let ((a 1) (b 2)) (letrec ((c 3)) (let ((a 4)) (lambda (x) x))))");
+        assert_eq!(
+            vec![
+                ("A".to_string(), num(4)),
+                ("C".to_string(), num(3)),
+                ("B".to_string(), num(2)),
+                ("A".to_string(), num(1)),
+            ],
+            scalar_store.env_bindings(&env)
+        );
+
+        // The empty environment has no bindings.
+        let (scalar_store, env) = closure("(lambda (x) x)");
+        assert_eq!(Some(&ScalarExpression::Nil), scalar_store.get_expr(&env));
+        assert!(scalar_store.env_bindings(&env).is_empty());
+
+        // A malformed environment yields the bindings before the first malformed part.
+        let (scalar_store, env) =
+            ScalarStore::<Fr>::from_source("((a . 1) (b . 2) 3 (c . 4))").unwrap();
+        assert_eq!(
+            vec![("A".to_string(), num(1)), ("B".to_string(), num(2))],
+            scalar_store.env_bindings(&env)
+        );
+        let (scalar_store, env) = ScalarStore::<Fr>::from_source("((a . 1) . 2)").unwrap();
+        assert_eq!(
+            vec![("A".to_string(), num(1))],
+            scalar_store.env_bindings(&env)
+        );
+        let (scalar_store, env) = ScalarStore::<Fr>::from_source("(((a . 1) 2) (b . 3))").unwrap();
+        assert_eq!(
+            vec![("A".to_string(), num(1))],
+            scalar_store.env_bindings(&env)
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_find_sym_str() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(foo \"bar\" \"FOO\")").unwrap();
+        let foo = store.sym("foo");
+        let bar = store.str("bar");
+        let foo_str = store.str("FOO");
+        store.hydrate_scalar_cache();
+        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &expr);
+
+        assert_eq!(store.get_expr_hash(&foo), scalar_store.find_sym("FOO"));
+        assert_eq!(store.get_expr_hash(&bar), scalar_store.find_str("bar"));
+        // Symbols and strings with the same text are distinct.
+        assert_eq!(store.get_expr_hash(&foo_str), scalar_store.find_str("FOO"));
+        assert_ne!(scalar_store.find_sym("FOO"), scalar_store.find_str("FOO"));
+
+        assert_eq!(None, scalar_store.find_sym("foo"));
+        assert_eq!(None, scalar_store.find_sym("bar"));
+        assert_eq!(None, scalar_store.find_str("baz"));
+        assert_eq!(None, ScalarStore::<Fr>::default().find_sym("FOO"));
     }
 
     #[quickcheck]
-    fn prop_scalar_store_ipld(x: ScalarStore<Fr>) -> bool {
-        if let Ok(ipld) = to_ipld(x.clone()) {
-            if let Ok(y) = from_ipld(ipld) {
-                x == y
-            } else {
-                false
-            }
-        } else {
-            false
+    fn prop_scalar_store_ser_f_compressed(x: TaggedScalarStore) -> bool {
+        let store = x.0;
+        let compressed = store.clone().ser_f_compressed().unwrap();
+        ScalarStore::de_f_compressed(&compressed, store.serialized_len_fr()) == Ok(store)
+    }
+
+    #[test]
+    fn test_rle_round_trip() {
+        let fs = |xs: &[u64]| xs.iter().map(|x| Fr::from(*x)).collect::<Vec<_>>();
+        for input in [
+            vec![],
+            vec![1],
+            vec![1, 1],
+            vec![1, 1, 1],
+            vec![1, 2, 2, 3, 3, 3, 4, 4, 4, 4],
+            vec![0, 0, 0, 1, 2, 0, 0, 0, 0, 5],
+        ] {
+            let input = fs(&input);
+            assert_eq!(
+                Ok(input.clone()),
+                rle_decode(&rle_encode(&input), input.len())
+            );
         }
+        assert_eq!(fs(&[4, 1, 2, 7, 3]), rle_encode(&fs(&[1, 2, 3, 3, 3])));
     }
 
     #[test]
-    fn test_expr_ipld() {
-        let test = |src| {
-            let mut store1 = Store::<Fr>::default();
-            let expr1 = store1.read(src).unwrap();
-            store1.hydrate_scalar_cache();
+    fn test_rle_decode_errors() {
+        assert_eq!(
+            Err(DeserializeError::InvalidPayload(0)),
+            rle_decode(&[Fr::from(0)], 10)
+        );
+        assert_eq!(
+            Err(DeserializeError::InvalidPayload(2)),
+            rle_decode(&[Fr::from(3), Fr::from(9), Fr::from(1)], 10)
+        );
+        assert_eq!(
+            Err(DeserializeError::InvalidPayload(0)),
+            rle_decode(&[-Fr::from(1)], 10)
+        );
+        assert_eq!(
+            Err(DeserializeError::UnexpectedEnd(2)),
+            rle_decode(&[Fr::from(4), Fr::from(9)], 10)
+        );
+        assert_eq!(
+            Err(DeserializeError::UnexpectedEnd(1)),
+            rle_decode(&[Fr::from(5)], 10)
+        );
+    }
 
-            if let (scalar_store, Some(scalar_expr)) = ScalarStore::new_with_expr(&store1, &expr1) {
-                let ipld = to_ipld(scalar_store.clone()).unwrap();
-                let mut scalar_store2 = from_ipld(ipld).unwrap();
-                assert_eq!(scalar_store, scalar_store2);
-                if let Some((mut store2, expr2)) = scalar_store2.to_store_with_expr(&scalar_expr) {
-                    store2.hydrate_scalar_cache();
-                    let (scalar_store3, _) = ScalarStore::new_with_expr(&store2, &expr2);
-                    assert_eq!(scalar_store2, scalar_store3)
-                } else {
-                    assert!(false)
-                }
-            } else {
-                assert!(false)
-            }
-        };
+    #[test]
+    fn test_rle_decode_max_len() {
+        let fs = |xs: &[u64]| xs.iter().map(|x| Fr::from(*x)).collect::<Vec<_>>();
+        // A single run header asking for an enormous run is rejected without allocating it.
+        let huge = Fr::from(u64::MAX);
+        assert_eq!(
+            Err(DeserializeError::InvalidPayload(0)),
+            rle_decode(&[huge, Fr::from(9)], 1000)
+        );
+        assert_eq!(
+            Err(DeserializeError::InvalidPayload(0)),
+            ScalarStore::de_f_compressed(&[huge, Fr::from(9)], 1000)
+        );
 
-        test("symbol");
-        test("1");
-        test("(1 . 2)");
-        test("\"foo\" . \"bar\")");
-        test("(foo . bar)");
-        test("(1 . 2)");
-        test("(+ 1 2 3)");
-        test("(+ 1 2 (* 3 4))");
-        test("(+ 1 2 (* 3 4) \"asdf\" )");
-        test("(+ 1 2 2 (* 3 4) \"asdf\" \"asdf\")");
+        // The limit covers the total output, not just each block.
+        let input = fs(&[7, 1, 4, 2, 3]);
+        assert_eq!(Ok(fs(&[1, 1, 1, 2, 3])), rle_decode(&input, 5));
+        assert_eq!(
+            Err(DeserializeError::InvalidPayload(2)),
+            rle_decode(&input, 4)
+        );
+        assert_eq!(
+            Err(DeserializeError::InvalidPayload(0)),
+            rle_decode(&input, 2)
+        );
     }
 
     #[test]
-    fn test_expr_eval_ipld() {
-        use crate::eval;
-        let test = |src| {
-            let mut s = Store::<Fr>::default();
-            let expr = s.read(src).unwrap();
-            s.hydrate_scalar_cache();
+    fn test_scalar_store_ser_f_compressed_zeros() {
+        // A string of NULs is serialized as 10k zero chunks.
+        let len = 10_000 * string_chunk_len::<Fr>();
+        let mut scalar_store = ScalarStore::<Fr>::default();
+        scalar_store.scalar_map.insert(
+            ScalarPtr::from_parts(Tag::Str.as_field(), Fr::from(1)),
+            Some(ScalarExpression::Str("\0".repeat(len))),
+        );
 
-            let env = empty_sym_env(&s);
-            let mut eval = eval::Evaluator::new(expr, env, &mut s, 100);
-            let (
-                eval::IO {
-                    expr,
-                    env: _,
-                    cont: _,
-                },
-                _lim,
-                _emitted,
-            ) = eval.eval().unwrap();
+        let plain = scalar_store.clone().ser_f().unwrap();
+        let compressed = scalar_store.clone().ser_f_compressed().unwrap();
+        assert!(plain.len() > 10_000);
+        assert!(compressed.len() < 10);
+        assert_eq!(
+            Ok(scalar_store),
+            ScalarStore::de_f_compressed(&compressed, plain.len())
+        );
+    }
 
-            let (scalar_store, _) = ScalarStore::new_with_expr(&s, &expr);
-            println!("{:?}", scalar_store);
-            let ipld = to_ipld(scalar_store.clone()).unwrap();
-            let scalar_store2 = from_ipld(ipld).unwrap();
-            println!("{:?}", scalar_store2);
-            assert_eq!(scalar_store, scalar_store2);
+    #[test]
+    fn test_scalar_store_estimate_constraints() {
+        let estimate = |src: &str| {
+            let (scalar_store, root) = ScalarStore::<Fr>::from_source(src).unwrap();
+            scalar_store.estimate_constraints(&root)
         };
 
-        test("(let ((a 123)) (lambda (x) (+ x a)))");
+        // Each source is a subexpression of the next.
+        let sources = [
+            "1",
+            "(1)",
+            "(+ 1)",
+            "(+ 1 2)",
+            "(+ 1 (* 2 3))",
+            "(lambda (x) (+ 1 (* 2 3)))",
+            "(let ((f (lambda (x) (+ 1 (* 2 3))))) (f \"arg\"))",
+        ];
+        for (smaller, bigger) in sources.iter().tuple_windows() {
+            assert!(
+                estimate(smaller) < estimate(bigger),
+                "{} {}",
+                smaller,
+                bigger
+            );
+        }
+
+        let (scalar_store, root) = ScalarStore::<Fr>::from_source("(a . 1)").unwrap();
+        let w = DEFAULT_CONSTRAINT_WEIGHTS;
+        assert_eq!(
+            w.cons + w.sym + w.per_char + w.num,
+            scalar_store.estimate_constraints(&root)
+        );
+        let weights = ConstraintWeights {
+            cons: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            w.sym + w.per_char + w.num,
+            scalar_store.estimate_constraints_with(&root, &weights)
+        );
+        assert_eq!(0, ScalarStore::<Fr>::default().estimate_constraints(&root));
     }
 
     #[test]
-    fn test_scalar_store() {
-        let test = |src, expected| {
-            let mut s = Store::<Fr>::default();
-            let expr = s.read(src).unwrap();
-            s.hydrate_scalar_cache();
+    fn test_scalar_store_to_store_verified() {
+        let (mut scalar_store, root) = ScalarStore::<Fr>::from_source("(1 . \"foo\")").unwrap();
+        assert!(scalar_store.to_store_verified().is_ok());
 
-            let (scalar_store, _) = ScalarStore::new_with_expr(&s, &expr);
-            assert_eq!(expected, scalar_store.scalar_map.len());
-        };
+        // Move the root under a pointer which its contents do not hash to.
+        let mut tampered = scalar_store.clone();
+        let expr = tampered.scalar_map.remove(&root).unwrap();
+        let bogus = ScalarPtr::from_parts(*root.tag(), *root.value() + Fr::from(1));
+        tampered.scalar_map.insert(bogus, expr);
+        assert!(tampered.to_store().is_ok());
+        assert_eq!(
+            Err(VerifyError::HashMismatch {
+                claimed: uptr(&bogus),
+                actual: uptr(&root),
+            }),
+            tampered.to_store_verified().map(|_| ())
+        );
 
-        // Four atoms, four conses (four-element list), and NIL.
-        test("symbol", 1);
-        test("(1 . 2)", 3);
-        test("(+ 1 2 3)", 9);
-        test("(+ 1 2 (* 3 4))", 14);
-        // String are handled.
-        test("(+ 1 2 (* 3 4) \"asdf\" )", 16);
-        // Duplicate strings or symbols appear only once.
-        test("(+ 1 2 2 (* 3 4) \"asdf\" \"asdf\")", 18);
+        // Change the contents of a child, which the parent's hash commits to.
+        let foo = scalar_store.find_str("foo").unwrap();
+        scalar_store
+            .scalar_map
+            .insert(foo, Some(ScalarExpression::Str("bar".into())));
+        assert!(matches!(
+            scalar_store.to_store_verified(),
+            Err(VerifyError::HashMismatch { .. })
+        ));
+
+        // A continuation under the wrong pointer.
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(+ 1 2)").unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 100)
+            .get_frames()
+            .unwrap();
+        store.hydrate_scalar_cache();
+        let mut scalar_store = ScalarStore::default();
+        scalar_store.add_one_cont_ptr(&store, &frames[1].output.cont);
+        assert!(scalar_store.to_store_verified().is_ok());
+        let cont_ptr = store.get_cont_hash(&frames[1].output.cont).unwrap();
+        let cont = scalar_store.scalar_cont_map.remove(&cont_ptr).unwrap();
+        let bogus = ScalarContPtr::from_parts(*cont_ptr.tag(), *cont_ptr.value() + Fr::from(1));
+        scalar_store.scalar_cont_map.insert(bogus, cont);
+        assert_eq!(
+            Err(VerifyError::HashMismatch {
+                claimed: uptr(&bogus),
+                actual: uptr(&cont_ptr),
+            }),
+            scalar_store.to_store_verified().map(|_| ())
+        );
+    }
+
+    #[quickcheck]
+    fn prop_scalar_store_shard(x: ScalarStore<Fr>, n: u8) -> bool {
+        let n = n as usize % 8 + 1;
+        let shards = x.shard(n);
+        shards.len() == n && ScalarStore::merge_all(shards) == Ok(x)
     }
 
     #[test]
-    fn test_scalar_store_opaque_cons() {
-        let mut store = Store::<Fr>::default();
+    fn test_scalar_store_shard() {
+        let source = format!(
+            "(list {})",
+            (0..100).map(|i| format!("(sym{} . {})", i, i)).join(" ")
+        );
+        let (scalar_store, _) = ScalarStore::<Fr>::from_source(&source).unwrap();
+        let shards = scalar_store.shard(4);
 
-        let num1 = store.num(123);
-        let num2 = store.num(987);
-        let cons = store.intern_cons(num1, num2);
-        let cons_hash = store.hash_expr(&cons).unwrap();
-        let opaque_cons = store.intern_maybe_opaque_cons(*cons_hash.value());
+        assert_eq!(4, shards.len());
+        assert!(shards.iter().all(|shard| !shard.scalar_map.is_empty()));
+        assert_eq!(
+            scalar_store.scalar_map.len(),
+            shards
+                .iter()
+                .map(|shard| shard.scalar_map.len())
+                .sum::<usize>()
+        );
+        assert_eq!(Ok(scalar_store.clone()), ScalarStore::merge_all(shards));
+        assert_eq!(vec![scalar_store.clone()], scalar_store.shard(1));
 
-        store.hydrate_scalar_cache();
+        // Conflicting shards.
+        let num = ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(1));
+        let mut a = ScalarStore::default();
+        a.scalar_map
+            .insert(num, Some(ScalarExpression::Num(Fr::from(1))));
+        let mut b = ScalarStore::default();
+        b.scalar_map
+            .insert(num, Some(ScalarExpression::Num(Fr::from(2))));
+        assert_eq!(
+            Err(MergeConflict(uptr(&num))),
+            ScalarStore::merge_all(vec![a, b])
+        );
+    }
 
-        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &opaque_cons);
-        println!("{:?}", scalar_store.scalar_map);
-        println!("{:?}", scalar_store.scalar_map.len());
-        // If a non-opaque version has been found when interning opaque, children appear in `ScalarStore`.
-        assert_eq!(3, scalar_store.scalar_map.len());
+    #[test]
+    #[should_panic]
+    fn test_scalar_store_shard_zero() {
+        ScalarStore::<Fr>::default().shard(0);
     }
+
     #[test]
-    fn test_scalar_store_opaque_fun() {
-        let mut store = Store::<Fr>::default();
+    fn test_scalar_store_list_elements() {
+        let num = |n: u64| ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(n));
 
-        let arg = store.sym("A");
-        let body = store.num(123);
-        let empty_env = empty_sym_env(&store);
-        let fun = store.intern_fun(arg, body, empty_env);
-        let fun_hash = store.hash_expr(&fun).unwrap();
-        let opaque_fun = store.intern_maybe_opaque_fun(*fun_hash.value());
-        store.hydrate_scalar_cache();
+        let (scalar_store, root) = ScalarStore::<Fr>::from_source("(1 2 3)").unwrap();
+        assert_eq!(
+            Ok(vec![num(1), num(2), num(3)]),
+            scalar_store.list_elements(&root)
+        );
 
-        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &opaque_fun);
-        // If a non-opaque version has been found when interning opaque, children appear in `ScalarStore`.
-        assert_eq!(4, scalar_store.scalar_map.len());
+        let (scalar_store, root) = ScalarStore::<Fr>::from_source("(1 . 2)").unwrap();
+        assert_eq!(Err(ImproperList(num(2))), scalar_store.list_elements(&root));
+
+        let (scalar_store, root) = ScalarStore::<Fr>::from_source("nil").unwrap();
+        assert_eq!(Ok(vec![]), scalar_store.list_elements(&root));
+        assert_eq!(
+            Err(ImproperList(num(7))),
+            scalar_store.list_elements(&num(7))
+        );
+
+        // A cycle through the cdrs.
+        let mut scalar_store = ScalarStore::<Fr>::default();
+        let cons = ScalarPtr::from_parts(Tag::Cons.as_field(), Fr::from(1));
+        scalar_store
+            .scalar_map
+            .insert(cons, Some(ScalarExpression::Cons(num(1), cons)));
+        assert_eq!(Err(ImproperList(cons)), scalar_store.list_elements(&cons));
     }
+
     #[test]
-    fn test_scalar_store_opaque_sym() {
+    fn test_scalar_store_trace() {
         let mut store = Store::<Fr>::default();
+        let expr = store.read("(let ((x 1)) (+ x 2))").unwrap();
+        let env = empty_sym_env(&store);
+        let (_, iterations, _) = Evaluator::new(expr, env, &mut store, 100).eval().unwrap();
+        let initial = IO {
+            expr,
+            env,
+            cont: store.intern_cont_outermost(),
+        };
 
-        let sym = store.sym(&"sym");
-        let sym_hash = store.hash_expr(&sym).unwrap();
-        let opaque_sym = store.intern_maybe_opaque_sym(*sym_hash.value());
+        let trace = ScalarStore::trace(&mut store, initial, 100).unwrap();
+        assert_eq!(iterations + 1, trace.len());
 
-        store.hydrate_scalar_cache();
+        let (first, first_expr, first_cont) = &trace[0];
+        assert_eq!(Some(*first_expr), store.get_expr_hash(&expr));
+        assert_eq!(Some(*first_cont), store.get_cont_hash(&initial.cont));
+        assert_eq!(
+            Some("(LET ((X 1)) (+ X 2))".to_string()),
+            first.to_source(first_expr)
+        );
+        let (last, last_expr, last_cont) = trace.last().unwrap();
+        assert_eq!(
+            Some(&ScalarExpression::Num(Fr::from(3))),
+            last.get_expr(last_expr)
+        );
+        assert_eq!(ContTag::Terminal.as_field::<Fr>(), *last_cont.tag());
+        assert!(trace.iter().all(|(s, _, _)| s.check_complete().is_ok()));
 
-        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &opaque_sym);
-        // Only the symbol's string itself, not all of its substrings, appears in `ScalarStore`.
-        assert_eq!(1, scalar_store.scalar_map.len());
+        // Stopping early still includes the state after the last step.
+        let trace = ScalarStore::trace(&mut store, initial, 2).unwrap();
+        assert_eq!(3, trace.len());
     }
+
     #[test]
-    fn test_scalar_store_opaque_str() {
+    fn test_scalar_store_compress_trace() {
         let mut store = Store::<Fr>::default();
+        let expr = store
+            .read("(letrec ((f (lambda (n) (if (= n 0) 0 (f (- n 1)))))) (f 10))")
+            .unwrap();
+        let initial = IO {
+            expr,
+            env: empty_sym_env(&store),
+            cont: store.intern_cont_outermost(),
+        };
+        let trace = ScalarStore::trace(&mut store, initial, 50).unwrap();
+        assert_eq!(51, trace.len());
 
-        let str = store.str(&"str");
-        let str_hash = store.hash_expr(&str).unwrap();
-        let opaque_str = store.intern_maybe_opaque_sym(*str_hash.value());
+        let (merged, roots) = ScalarStore::compress_trace(&trace).unwrap();
+        assert_eq!(trace.len(), roots.len());
+        for ((snapshot, expr, cont), root) in trace.iter().zip(&roots) {
+            assert_eq!(
+                RootRefs {
+                    expr: *expr,
+                    cont: *cont
+                },
+                *root
+            );
+            assert_eq!(snapshot.get_expr(expr), merged.get_expr(&root.expr));
+            assert_eq!(snapshot.get_cont(cont), merged.get_cont(&root.cont));
+            assert_eq!(snapshot.to_source(expr), merged.to_source(&root.expr));
+        }
+        assert!(merged.check_complete().is_ok());
+
+        // The snapshots share most of their nodes.
+        let separate: usize = trace.iter().map(|(s, _, _)| s.serialized_len_fr()).sum();
+        assert!(merged.serialized_len_fr() * 10 < separate);
+    }
 
+    #[test]
+    fn test_scalar_store_get_owned() {
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(1 . \"foo\")").unwrap();
+        let env = empty_sym_env(&store);
+        let frames = Evaluator::new(expr, env, &mut store, 100)
+            .get_frames()
+            .unwrap();
         store.hydrate_scalar_cache();
+        let mut scalar_store = ScalarStore::default();
+        let expr = scalar_store.add_one_ptr(&store, &expr).unwrap();
+        let cont = scalar_store
+            .add_one_cont_ptr(&store, &frames[0].output.cont)
+            .unwrap();
 
-        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &opaque_str);
-        // Only the string itself, not all of its substrings, appears in `ScalarStore`.
-        assert_eq!(1, scalar_store.scalar_map.len());
+        let owned_expr = scalar_store.get_expr_owned(&expr);
+        let owned_cont = scalar_store.get_cont_owned(&cont);
+        assert_eq!(scalar_store.get_expr(&expr).cloned(), owned_expr);
+        assert_eq!(scalar_store.get_cont(&cont).copied(), owned_cont);
+        drop(scalar_store);
+        assert!(matches!(owned_expr, Some(ScalarExpression::Cons(..))));
+        assert!(owned_cont.is_some());
+
+        let opaque = ScalarPtr::from_parts(Tag::Sym.as_field(), Fr::from(1));
+        let mut scalar_store = ScalarStore::<Fr>::default();
+        scalar_store.scalar_map.insert(opaque, None);
+        assert_eq!(None, scalar_store.get_expr_owned(&opaque));
+        assert_eq!(None, scalar_store.get_cont_owned(&cont));
     }
+
     #[test]
-    fn test_scalar_store_opaque_comm() {
+    fn test_scalar_store_intern_list() {
         let mut store = Store::<Fr>::default();
+        let elements: Vec<_> = (1..=3).map(|n| store.num(n)).collect();
+        let mut scalar_store = ScalarStore::default();
+        let list = scalar_store.intern_list(&mut store, &elements);
 
-        let num = store.num(987);
-        let comm = store.intern_comm(Fr::from(123), num);
-        let comm_hash = store.hash_expr(&comm).unwrap();
-        let opaque_comm = store.intern_maybe_opaque_comm(*comm_hash.value());
+        let (parsed, parsed_list) = ScalarStore::<Fr>::from_source("(1 2 3)").unwrap();
+        assert_eq!(parsed_list, list);
+        assert_eq!(parsed, scalar_store);
+        assert_eq!(3, scalar_store.list_elements(&list).unwrap().len());
 
-        store.hydrate_scalar_cache();
+        let nil = scalar_store.intern_list(&mut store, &[]);
+        assert_eq!(Some(&ScalarExpression::Nil), scalar_store.get_expr(&nil));
+    }
 
-        let (scalar_store, _) = ScalarStore::new_with_expr(&store, &opaque_comm);
-        println!("{:?}", scalar_store.scalar_map);
-        println!("{:?}", scalar_store.scalar_map.len());
-        // If a non-opaque version has been found when interning opaque, children appear in `ScalarStore`.
-        assert_eq!(2, scalar_store.scalar_map.len());
+    #[test]
+    fn test_scalar_store_health_check() {
+        let (scalar_store, root) = ScalarStore::<Fr>::from_source("(1 . \"foo\")").unwrap();
+        assert!(scalar_store.health_check().is_empty());
+        assert!(!scalar_store.is_fully_opaque());
+
+        let empty = ScalarStore::<Fr>::default();
+        assert_eq!(vec![HealthWarning::Empty], empty.health_check());
+        assert!(!empty.is_fully_opaque());
+
+        let mut opaque = ScalarStore::<Fr>::default();
+        opaque.scalar_map.insert(root, None);
+        assert!(opaque.is_fully_opaque());
+        assert_eq!(vec![HealthWarning::FullyOpaque], opaque.health_check());
+
+        let mut dangling = scalar_store;
+        let foo = dangling.find_str("foo").unwrap();
+        dangling.scalar_map.remove(&foo);
+        assert!(!dangling.is_fully_opaque());
+        assert_eq!(
+            vec![HealthWarning::DanglingChildren(vec![uptr(&foo)])],
+            dangling.health_check()
+        );
+    }
+
+    #[test]
+    fn test_scalar_store_replace() {
+        let num = |n: u64| ScalarPtr::from_parts(Tag::Num.as_field(), Fr::from(n));
+        let (mut scalar_store, root) = ScalarStore::<Fr>::from_source("(+ 1 (* 2 3))").unwrap();
+        scalar_store
+            .scalar_map
+            .insert(num(6), Some(ScalarExpression::Num(Fr::from(6))));
+        let original = scalar_store.clone();
+
+        let rekeyed = scalar_store.replace(num(3), num(6)).unwrap();
+        let new_root = rekeyed[&root];
+        let (_, expected_root) = ScalarStore::<Fr>::from_source("(+ 1 (* 2 6))").unwrap();
+        assert_eq!(expected_root, new_root);
+        assert_eq!(
+            Some("(+ 1 (* 2 6))".to_string()),
+            scalar_store.to_source(&new_root)
+        );
+        assert!(!scalar_store.contains_expr(&root));
+        assert!(scalar_store.contains_expr(&num(3)));
+        assert!(scalar_store.check_complete().is_ok());
+
+        // The same replacement gives the same pointers.
+        let mut again = original.clone();
+        assert_eq!(Ok(rekeyed), again.replace(num(3), num(6)));
+        assert_eq!(scalar_store, again);
+
+        // Errors leave the store unchanged.
+        let mut unchanged = original.clone();
+        assert_eq!(
+            Err(ScalarStoreError::MissingPtr(uptr(&num(7)))),
+            unchanged.replace(num(3), num(7))
+        );
+        let product = original.list_elements(&root).unwrap()[2];
+        assert!(matches!(
+            unchanged.replace(num(3), product),
+            Err(ScalarStoreError::Cycle(_))
+        ));
+        assert_eq!(original, unchanged);
     }
 }
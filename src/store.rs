@@ -8,10 +8,10 @@ use string_interner::symbol::{Symbol, SymbolUsize};
 use neptune::poseidon::PoseidonConstants;
 use once_cell::sync::OnceCell;
 
-use libipld::Cid;
+use cid::Cid;
 
 use crate::error::LurkError;
-use crate::field::{FWrap, LurkField};
+use crate::field::{f_from_hex, f_to_hex, FWrap, LurkField};
 use crate::scalar_store::ScalarContinuation;
 use crate::scalar_store::ScalarExpression;
 use crate::scalar_store::ScalarStore;
@@ -21,6 +21,7 @@ use serde::Deserialize;
 use serde::Serialize;
 use serde::{de, ser};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use subtle::{Choice, ConstantTimeEq};
 
 /// Holds the constants needed for poseidon hashing.
 #[derive(Debug)]
@@ -195,6 +196,20 @@ pub trait ScalarPointer<F: LurkField>: fmt::Debug + Copy + Clone + PartialEq + H
     fn value(&self) -> &F;
 }
 
+/// Render a pointer as the hex of its tag followed by the hex of its value.
+pub(crate) fn ptr_to_hex<F: LurkField, P: ScalarPointer<F>>(ptr: &P) -> String {
+    format!("{}{}", f_to_hex(ptr.tag()), f_to_hex(ptr.value()))
+}
+
+/// Parse the output of `ptr_to_hex`.
+pub(crate) fn ptr_from_hex<F: LurkField, P: ScalarPointer<F>>(s: &str) -> Option<P> {
+    if !s.is_ascii() || s.len() % 2 != 0 {
+        return None;
+    }
+    let (tag, value) = s.split_at(s.len() / 2);
+    Some(P::from_parts(f_from_hex(tag)?, f_from_hex(value)?))
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Ptr<F: LurkField>(Tag, RawPtr<F>);
 
@@ -240,6 +255,15 @@ pub struct ScalarPtr<F: LurkField>(F, F);
 
 impl<F: LurkField> Copy for ScalarPtr<F> {}
 
+/// Compare in constant time, for pointers derived from secrets, such as those of `Comm`s. Unlike `==`, which compares
+/// byte representations and may stop at the first difference, this takes the same time whether or not the pointers
+/// are equal.
+impl<F: LurkField> ConstantTimeEq for ScalarPtr<F> {
+    fn ct_eq(&self, other: &Self) -> Choice {
+        self.0.ct_eq(&other.0) & self.1.ct_eq(&other.1)
+    }
+}
+
 impl<F: LurkField> PartialOrd for ScalarPtr<F> {
     fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
         (self.0.to_repr().as_ref(), self.1.to_repr().as_ref())
@@ -302,6 +326,18 @@ impl<F: LurkField> ScalarPointer<F> for ScalarPtr<F> {
     }
 }
 
+impl<F: LurkField> ScalarPtr<F> {
+    /// Render the pointer as the fixed-width hex of the `to_repr` bytes of its tag, followed by those of its value.
+    pub fn to_hex(&self) -> String {
+        ptr_to_hex(self)
+    }
+
+    /// Parse the output of `to_hex`, or return `None` if `s` is not exactly that of some `ScalarPtr`.
+    pub fn from_hex(s: &str) -> Option<Self> {
+        ptr_from_hex(s)
+    }
+}
+
 pub trait IntoHashComponents<F: LurkField> {
     fn into_hash_components(self) -> [F; 2];
 }
@@ -616,6 +652,24 @@ impl Op1 {
         }
     }
 
+    pub fn from_field<F: From<u64> + ff::Field>(f: F) -> Option<Self> {
+        match f {
+            f if f == Op1::Car.as_field() => Some(Op1::Car),
+            f if f == Op1::Cdr.as_field() => Some(Op1::Cdr),
+            f if f == Op1::Atom.as_field() => Some(Op1::Atom),
+            f if f == Op1::Emit.as_field() => Some(Op1::Emit),
+            f if f == Op1::Open.as_field() => Some(Op1::Open),
+            f if f == Op1::Secret.as_field() => Some(Op1::Secret),
+            f if f == Op1::Commit.as_field() => Some(Op1::Commit),
+            f if f == Op1::Num.as_field() => Some(Op1::Num),
+            f if f == Op1::Comm.as_field() => Some(Op1::Comm),
+            f if f == Op1::Char.as_field() => Some(Op1::Char),
+            f if f == Op1::Eval.as_field() => Some(Op1::Eval),
+            f if f == Op1::U64.as_field() => Some(Op1::U64),
+            _ => None,
+        }
+    }
+
     pub fn as_field<F: From<u64> + ff::Field>(&self) -> F {
         F::from(*self as u64)
     }
@@ -656,6 +710,7 @@ impl Op2 {
             x if x == Op2::LessEqual as u16 => Some(Op2::LessEqual),
             x if x == Op2::GreaterEqual as u16 => Some(Op2::GreaterEqual),
             x if x == Op2::Cons as u16 => Some(Op2::Cons),
+            x if x == Op2::StrCons as u16 => Some(Op2::StrCons),
             x if x == Op2::Begin as u16 => Some(Op2::Begin),
             x if x == Op2::Hide as u16 => Some(Op2::Hide),
             x if x == Op2::Modulo as u16 => Some(Op2::Modulo),
@@ -663,6 +718,28 @@ impl Op2 {
             _ => None,
         }
     }
+
+    pub fn from_field<F: From<u64> + ff::Field>(f: F) -> Option<Self> {
+        match f {
+            f if f == Op2::Sum.as_field() => Some(Op2::Sum),
+            f if f == Op2::Diff.as_field() => Some(Op2::Diff),
+            f if f == Op2::Product.as_field() => Some(Op2::Product),
+            f if f == Op2::Quotient.as_field() => Some(Op2::Quotient),
+            f if f == Op2::Equal.as_field() => Some(Op2::Equal),
+            f if f == Op2::NumEqual.as_field() => Some(Op2::NumEqual),
+            f if f == Op2::Less.as_field() => Some(Op2::Less),
+            f if f == Op2::Greater.as_field() => Some(Op2::Greater),
+            f if f == Op2::LessEqual.as_field() => Some(Op2::LessEqual),
+            f if f == Op2::GreaterEqual.as_field() => Some(Op2::GreaterEqual),
+            f if f == Op2::Cons.as_field() => Some(Op2::Cons),
+            f if f == Op2::StrCons.as_field() => Some(Op2::StrCons),
+            f if f == Op2::Begin.as_field() => Some(Op2::Begin),
+            f if f == Op2::Hide.as_field() => Some(Op2::Hide),
+            f if f == Op2::Modulo.as_field() => Some(Op2::Modulo),
+            f if f == Op2::Eval.as_field() => Some(Op2::Eval),
+            _ => None,
+        }
+    }
     pub fn as_field<F: From<u64> + ff::Field>(&self) -> F {
         F::from(*self as u64)
     }
@@ -707,7 +784,9 @@ impl fmt::Display for Op2 {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize_repr, Deserialize_repr)]
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize_repr, Deserialize_repr,
+)]
 #[repr(u16)]
 pub enum Tag {
     Nil = 0b0000_0000_0000_0000,
@@ -740,6 +819,7 @@ impl Tag {
             f if f == Tag::Str.as_field() => Some(Tag::Str),
             f if f == Tag::Char.as_field() => Some(Tag::Char),
             f if f == Tag::Comm.as_field() => Some(Tag::Comm),
+            f if f == Tag::U64.as_field() => Some(Tag::U64),
             _ => None,
         }
     }
@@ -749,7 +829,7 @@ impl Tag {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(u16)]
 pub enum ContTag {
     Outermost = 0b0001_0000_0000_0000,
@@ -1128,6 +1208,11 @@ impl<F: LurkField> Store<F> {
         let ptr = Ptr(tag, self.new_opaque_raw_ptr());
         // Always insert. Key is unique because of newly allocated opaque raw_ptr.
         self.opaque_map.insert(ptr, scalar_ptr);
+        if return_non_opaque_if_existing {
+            // Record the opaque pointer, so later references to the same hash share it until a non-opaque version is
+            // interned.
+            self.scalar_ptr_map.insert(scalar_ptr, ptr);
+        }
         ptr
     }
 
@@ -1141,6 +1226,17 @@ impl<F: LurkField> Store<F> {
         use ScalarContinuation::*;
         match (tag, cont) {
             (ContTag::Outermost, Some(Outermost)) => Some(self.intern_cont_outermost()),
+            (
+                ContTag::Call0,
+                Some(Call0 {
+                    saved_env,
+                    continuation,
+                }),
+            ) => {
+                let env = self.intern_scalar_ptr(*saved_env, scalar_store)?;
+                let cont = self.intern_scalar_cont_ptr(*continuation, scalar_store)?;
+                Some(self.intern_cont_call0(env, cont))
+            }
             (
                 ContTag::Call,
                 Some(Call {
@@ -1265,7 +1361,7 @@ impl<F: LurkField> Store<F> {
                 let body = self.intern_scalar_ptr(*body, scalar_store)?;
                 let env = self.intern_scalar_ptr(*saved_env, scalar_store)?;
                 let cont = self.intern_scalar_cont_ptr(*continuation, scalar_store)?;
-                Some(self.intern_cont_let(var, body, env, cont))
+                Some(self.intern_cont_let_rec(var, body, env, cont))
             }
             (ContTag::Emit, Some(Emit { continuation })) => {
                 let cont = self.intern_scalar_cont_ptr(*continuation, scalar_store)?;
@@ -1295,6 +1391,12 @@ impl<F: LurkField> Store<F> {
             (Tag::Str, Some(Str(s))) => Some(self.intern_str(s)),
             (Tag::Sym, Some(Sym(s))) => Some(self.intern_sym(s)),
             (Tag::Num, Some(Num(x))) => Some(self.intern_num(crate::Num::Scalar(*x))),
+            (Tag::Char, Some(Char(c))) => Some(self.get_char(*c)),
+            (Tag::U64, Some(UInt(crate::UInt::U64(x)))) => Some(self.get_u64(*x)),
+            (Tag::Comm, Some(Comm(secret, payload))) => {
+                let payload = self.intern_scalar_ptr(*payload, scalar_store)?;
+                Some(self.intern_comm(*secret, payload))
+            }
             (Tag::Thunk, Some(Thunk(t))) => {
                 let value = self.intern_scalar_ptr(t.value, scalar_store)?;
                 let continuation = self.intern_scalar_cont_ptr(t.continuation, scalar_store)?;
@@ -1329,6 +1431,10 @@ impl<F: LurkField> Store<F> {
         self.intern_maybe_opaque(Tag::Sym, hash)
     }
 
+    pub fn intern_maybe_opaque_str(&mut self, hash: F) -> Ptr<F> {
+        self.intern_maybe_opaque(Tag::Str, hash)
+    }
+
     pub fn intern_maybe_opaque_cons(&mut self, hash: F) -> Ptr<F> {
         self.intern_maybe_opaque(Tag::Cons, hash)
     }
@@ -1345,6 +1451,10 @@ impl<F: LurkField> Store<F> {
         self.intern_opaque(Tag::Sym, hash)
     }
 
+    pub fn intern_opaque_str(&mut self, hash: F) -> Ptr<F> {
+        self.intern_opaque(Tag::Str, hash)
+    }
+
     pub fn intern_opaque_cons(&mut self, hash: F) -> Ptr<F> {
         self.intern_opaque(Tag::Cons, hash)
     }
@@ -1707,6 +1817,9 @@ impl<F: LurkField> Store<F> {
 
     pub(crate) fn fetch_str(&self, ptr: &Ptr<F>) -> Option<&str> {
         debug_assert!(matches!(ptr.0, Tag::Str));
+        if ptr.1.is_opaque() {
+            return None;
+        }
         let symbol = SymbolUsize::try_from_usize(ptr.1.idx()).expect("invalid pointer");
         self.str_store.0.resolve(symbol)
     }
@@ -1749,11 +1862,24 @@ impl<F: LurkField> Store<F> {
         self.num_store.get_index(ptr.1.idx())
     }
 
-    fn fetch_thunk(&self, ptr: &Ptr<F>) -> Option<&Thunk<F>> {
+    pub(crate) fn fetch_thunk(&self, ptr: &Ptr<F>) -> Option<&Thunk<F>> {
         debug_assert!(matches!(ptr.0, Tag::Thunk));
         self.thunk_store.get_index(ptr.1.idx())
     }
 
+    /// The scalar pointers of the value and continuation of the thunk `ptr`, or `None` if `ptr` is not a thunk in
+    /// this store. Like `get_expr_hash`, this does not record new scalar pointers, so the scalar cache should have been
+    /// hydrated.
+    pub fn fetch_scalar_thunk(&self, ptr: &Ptr<F>) -> Option<(ScalarPtr<F>, ScalarContPtr<F>)> {
+        if !matches!(ptr.0, Tag::Thunk) || ptr.1.is_opaque() {
+            return None;
+        }
+        let thunk = self.fetch_thunk(ptr)?;
+        let value = self.get_expr_hash(&thunk.value)?;
+        let continuation = self.get_cont_hash(&thunk.continuation)?;
+        Some((value, continuation))
+    }
+
     pub(crate) fn fetch_uint(&self, ptr: &Ptr<F>) -> Option<UInt> {
         // If more UInt variants are added, the following assertion should be relaxed to check for any of them.
         debug_assert!(matches!(ptr.0, Tag::U64));
@@ -1989,12 +2115,28 @@ impl<F: LurkField> Store<F> {
         Some(self.create_cont_scalar_ptr(*ptr, hash))
     }
 
+    // Get hash for cont without recording it in scalar_ptr_cont_map. This is the continuation counterpart of
+    // get_expr_hash, for use when building scalar representations after the cache has been hydrated.
+    pub fn get_cont_hash(&self, ptr: &ContPtr<F>) -> Option<ScalarContPtr<F>> {
+        let components = self.get_hash_components_cont(ptr)?;
+        let hash = self.poseidon_cache.hash8(&components);
+
+        Some(ScalarContPtr(ptr.tag_field(), hash))
+    }
+
     /// The only places that `ScalarPtr`s for `Ptr`s should be created, to
     /// ensure that they are cached properly
     fn create_scalar_ptr(&self, ptr: Ptr<F>, hash: F) -> ScalarPtr<F> {
         let scalar_ptr = ScalarPtr(ptr.tag_field(), hash);
         let entry = self.scalar_ptr_map.entry(scalar_ptr);
-        entry.or_insert(ptr);
+        // A non-opaque pointer replaces an opaque one recorded by `intern_maybe_opaque`.
+        entry
+            .and_modify(|p| {
+                if p.is_opaque() && !ptr.is_opaque() {
+                    *p = ptr
+                }
+            })
+            .or_insert(ptr);
         scalar_ptr
     }
 
@@ -2375,6 +2517,8 @@ impl<F: LurkField> Store<F> {
 
     fn hash_thunk(&self, ptr: Ptr<F>) -> Option<ScalarPtr<F>> {
         let thunk = self.fetch_thunk(&ptr)?;
+        // Hash the value first so that, like the children of other expressions, it is registered in scalar_ptr_map.
+        self.hash_expr(&thunk.value)?;
         let components = self.get_hash_components_thunk(thunk)?;
         Some(self.create_scalar_ptr(ptr, self.poseidon_cache.hash4(&components)))
     }
@@ -2593,6 +2737,11 @@ impl<F: LurkField> Store<F> {
         self.get_expr_hash(a) == self.get_expr_hash(b)
     }
 
+    /// True if every interned pointer has been hashed into the scalar cache, so that `scalar_ptr_map` is complete.
+    pub fn is_hydrated(&self) -> bool {
+        self.dehydrated.is_empty() && self.dehydrated_cont.is_empty()
+    }
+
     /// Fill the cache for Scalars. Only Ptrs which have been interned since last hydration will be hashed, so it is
     /// safe to call this incrementally. However, for best proving performance, we should call exactly once so all
     /// hashing can be batched, e.g. on the GPU.
@@ -2683,8 +2832,11 @@ pub mod test {
 
     use crate::test::frequency;
 
+    #[cfg(feature = "ipld")]
     use libipld::serde::from_ipld;
+    #[cfg(feature = "ipld")]
     use libipld::serde::to_ipld;
+    #[cfg(feature = "ipld")]
     use libipld::Ipld;
 
     impl Arbitrary for Tag {
@@ -2732,6 +2884,7 @@ pub mod test {
         }
     }
 
+    #[cfg(feature = "ipld")]
     #[quickcheck]
     fn test_scalar_ptr_ipld(x: ScalarPtr<Fr>) -> bool {
         if let Ok(ipld) = to_ipld(x) {
@@ -2745,6 +2898,7 @@ pub mod test {
         }
     }
 
+    #[cfg(feature = "ipld")]
     #[test]
     fn unit_scalar_ptr_ipld() {
         let tag = Tag::Num.as_field();
@@ -2762,6 +2916,7 @@ pub mod test {
         }
     }
 
+    #[cfg(feature = "ipld")]
     #[quickcheck]
     fn prop_scalar_cont_ptr_ipld(x: ScalarContPtr<Fr>) -> bool {
         if let Ok(ipld) = to_ipld(x) {
@@ -2775,6 +2930,7 @@ pub mod test {
         }
     }
 
+    #[cfg(feature = "ipld")]
     #[test]
     fn unit_scalar_cont_ptr_ipld() {
         let tag = ContTag::Dummy.as_field();
@@ -2802,6 +2958,7 @@ pub mod test {
         }
     }
 
+    #[cfg(feature = "ipld")]
     #[quickcheck]
     fn prop_op1_ipld(x: Op1) -> bool {
         if let Ok(ipld) = to_ipld(x) {
@@ -2815,11 +2972,12 @@ pub mod test {
         }
     }
 
+    #[cfg(feature = "ipld")]
     #[test]
     fn unit_op1_ipld() {
         assert_eq!(
             to_ipld(Op1::Car).unwrap(),
-            Ipld::Integer(0b0010_0000_0000_0000 as i128)
+            Ipld::Integer(0b0010_0000_0000_0000_i128)
         );
     }
 
@@ -2846,6 +3004,7 @@ pub mod test {
         }
     }
 
+    #[cfg(feature = "ipld")]
     #[quickcheck]
     fn prop_op2_ipld_embed(x: Op2) -> bool {
         if let Ok(ipld) = to_ipld(x) {
@@ -2859,11 +3018,12 @@ pub mod test {
         }
     }
 
+    #[cfg(feature = "ipld")]
     #[test]
     fn unit_op2_ipld() {
         assert_eq!(
             to_ipld(Op2::Sum).unwrap(),
-            Ipld::Integer(0b0011_0000_0000_0000 as i128)
+            Ipld::Integer(0b0011_0000_0000_0000_i128)
         );
     }
 
@@ -3337,9 +3497,153 @@ pub mod test {
         let num = num::Num::from_scalar(scalar);
         assert_eq!(
             format!("<Opaque Comm {}>", Expression::Num(num).fmt_to_string(s)),
-            opaque_comm.fmt_to_string(&s),
+            opaque_comm.fmt_to_string(s),
+        );
+
+        assert_eq!(opaque_comm.fmt_to_string(s), expr.fmt_to_string(s));
+    }
+
+    #[test]
+    fn fetch_scalar_thunk_and_cont_hash() {
+        let s = &mut Store::<Fr>::default();
+
+        let num = s.num(123);
+        let outermost = s.intern_cont_outermost();
+        let thunk = s.intern_thunk(Thunk {
+            value: num,
+            continuation: outermost,
+        });
+        s.hydrate_scalar_cache();
+
+        let num_hash = s.get_expr_hash(&num).unwrap();
+        let outermost_hash = s.get_cont_hash(&outermost).unwrap();
+        assert_eq!(Some(outermost_hash), s.hash_cont(&outermost));
+        assert_eq!(
+            Some((num_hash, outermost_hash)),
+            s.fetch_scalar_thunk(&thunk)
+        );
+
+        // Pointers which are not thunks in this store have none.
+        assert_eq!(None, s.fetch_scalar_thunk(&num));
+        let other = Store::<Fr>::default();
+        assert_eq!(None, other.fetch_scalar_thunk(&thunk));
+    }
+
+    #[test]
+    fn op1_from_field() {
+        for op in [
+            Op1::Car,
+            Op1::Cdr,
+            Op1::Atom,
+            Op1::Emit,
+            Op1::Open,
+            Op1::Secret,
+            Op1::Commit,
+            Op1::Num,
+            Op1::Comm,
+            Op1::Char,
+            Op1::Eval,
+            Op1::U64,
+        ] {
+            assert_eq!(Some(op), Op1::from_field::<Fr>(op.as_field()));
+        }
+        assert_eq!(None, Op1::from_field(Fr::from(Op1::U64 as u64 + 1)));
+        assert_eq!(None, Op1::from_field(Fr::from(0)));
+    }
+
+    #[test]
+    fn op2_from_field() {
+        for op in [
+            Op2::Sum,
+            Op2::Diff,
+            Op2::Product,
+            Op2::Quotient,
+            Op2::Equal,
+            Op2::NumEqual,
+            Op2::Less,
+            Op2::Greater,
+            Op2::LessEqual,
+            Op2::GreaterEqual,
+            Op2::Cons,
+            Op2::StrCons,
+            Op2::Begin,
+            Op2::Hide,
+            Op2::Modulo,
+            Op2::Eval,
+        ] {
+            assert_eq!(Some(op), Op2::from_field::<Fr>(op.as_field()));
+        }
+        assert_eq!(None, Op2::from_field(Fr::from(Op2::Eval as u64 + 1)));
+        assert_eq!(None, Op2::from_field(Op1::Car.as_field::<Fr>()));
+    }
+
+    #[quickcheck]
+    fn prop_scalar_ptr_hex(x: ScalarPtr<Fr>) -> bool {
+        ScalarPtr::from_hex(&x.to_hex()) == Some(x)
+    }
+
+    #[test]
+    fn test_scalar_ptr_hex() {
+        let repr_zeros = "00".repeat(32);
+
+        // Leading zeros are kept, so every pointer renders at the same width.
+        let nil = ScalarPtr::from_parts(Fr::from(0), Fr::from(1));
+        let hex = nil.to_hex();
+        assert_eq!(format!("{}01{}", repr_zeros, "00".repeat(31)), hex);
+        assert_eq!(Some(nil), ScalarPtr::from_hex(&hex));
+
+        let zero = ScalarPtr::from_parts(Fr::from(0), Fr::from(0));
+        assert_eq!(format!("{}{}", repr_zeros, repr_zeros), zero.to_hex());
+        assert_eq!(Some(zero), ScalarPtr::from_hex(&zero.to_hex()));
+
+        let mut store = Store::<Fr>::default();
+        let expr = store.read("(cons \"abc\" #\\z)").unwrap();
+        store.hydrate_scalar_cache();
+        let scalar_ptr = store.get_expr_hash(&expr).unwrap();
+        assert_eq!(Some(scalar_ptr), ScalarPtr::from_hex(&scalar_ptr.to_hex()));
+        // Upper case hex is also accepted.
+        assert_eq!(
+            Some(scalar_ptr),
+            ScalarPtr::from_hex(&scalar_ptr.to_hex().to_uppercase())
         );
 
-        assert_eq!(opaque_comm.fmt_to_string(&s), expr.fmt_to_string(&s));
+        // Malformed hex is rejected.
+        let hex = scalar_ptr.to_hex();
+        for bad in [
+            String::new(),
+            hex[1..].to_string(),
+            hex[2..].to_string(),
+            format!("{}00", hex),
+            format!("g{}", &hex[1..]),
+            format!("+{}", &hex[1..]),
+            format!("{}+{}", &hex[..64], &hex[65..]),
+            format!("é{}", &hex[2..]),
+            // A value which is not a canonical field element.
+            format!("{}{}", &hex[..64], "ff".repeat(32)),
+        ] {
+            assert_eq!(None, ScalarPtr::<Fr>::from_hex(&bad), "{:?}", bad);
+        }
+    }
+
+    #[quickcheck]
+    fn prop_scalar_ptr_ct_eq(x: ScalarPtr<Fr>, y: ScalarPtr<Fr>) -> bool {
+        bool::from(x.ct_eq(&x)) && bool::from(x.ct_eq(&y)) == (x == y)
+    }
+
+    #[test]
+    fn test_scalar_ptr_ct_eq() {
+        let mut store = Store::<Fr>::default();
+        let payload = store.num(123);
+        let comm = store.hide(Fr::from(7), payload);
+        let other = store.hide(Fr::from(8), payload);
+        store.hydrate_scalar_cache();
+        let comm = store.get_expr_hash(&comm).unwrap();
+        let other = store.get_expr_hash(&other).unwrap();
+
+        assert!(bool::from(comm.ct_eq(&comm)));
+        assert!(!bool::from(comm.ct_eq(&other)));
+        // Pointers which differ only in their tag.
+        let num = ScalarPtr::from_parts(Tag::Num.as_field(), *comm.value());
+        assert!(!bool::from(comm.ct_eq(&num)));
     }
 }
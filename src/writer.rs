@@ -43,7 +43,10 @@ impl<F: LurkField> Write<F> for ContPtr<F> {
     }
 }
 
-fn write_symbol<F: LurkField, W: io::Write>(w: &mut W, symbol_name: &str) -> io::Result<()> {
+pub(crate) fn write_symbol<F: LurkField, W: io::Write>(
+    w: &mut W,
+    symbol_name: &str,
+) -> io::Result<()> {
     let mut chars = symbol_name.chars().peekmore();
     let unquoted = Store::<F>::read_unquoted_symbol_name(&mut chars);
     if unquoted == symbol_name {